@@ -0,0 +1,63 @@
+//! Splitting input into console elements, comparing plain ascii (which takes the fast path) with
+//! text that needs grapheme segmentation
+use std::io;
+use std::ops::ControlFlow;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use prettycat::console::{for_each_console_element, DEFAULT_READ_BUFFER_SIZE};
+use prettycat::stream_colors::{ColorizerConfig, Flag, StreamColorizer};
+use prettycat::presets::default_flag_preset;
+
+
+/// Roughly a megabyte of text, made by repeating a line
+fn repeated(line: &str) -> Vec<u8> {
+    line.repeat((1 << 20) / line.len()).into_bytes()
+}
+
+
+fn inputs() -> [(&'static str, Vec<u8>); 3] {
+    [
+        ("ascii", repeated("2024-05-01 12:00:00 INFO server: request handled in 12ms\n")),
+        ("accented", repeated("2024-05-01 12:00:00 INFO café: requête traitée en 12ms\n")),
+        ("escapes", repeated("\u{1B}[32m2024-05-01 12:00:00\u{1B}[0m INFO server: handled\n")),
+    ]
+}
+
+
+fn split_elements(c: &mut Criterion) {
+    let mut group = c.benchmark_group("split_elements");
+    for (name, input) in inputs() {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter(|| {
+                let mut count = 0usize;
+                for_each_console_element(input.as_slice(), DEFAULT_READ_BUFFER_SIZE, |elem| {
+                    black_box(elem);
+                    count += 1;
+                    Ok(ControlFlow::Continue(()))
+                }).unwrap();
+                count
+            })
+        });
+    }
+    group.finish();
+}
+
+
+fn colorize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("colorize");
+    let config = ColorizerConfig::default();
+    for (name, input) in inputs() {
+        group.throughput(Throughput::Bytes(input.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &input, |b, input| {
+            b.iter(|| {
+                let mut flag = Flag::from_preset(&default_flag_preset());
+                flag.copy_colorized(input.as_slice(), io::sink(), &config).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+
+criterion_group!(benches, split_elements, colorize);
+criterion_main!(benches);
@@ -0,0 +1,92 @@
+//! Playing animated images, redrawing the input colored by each frame in turn
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use image::{AnimationDecoder, ImageFormat, RgbImage, RgbaImage};
+use image::codecs::gif::GifDecoder;
+
+use crate::console::RESET_CODE;
+use crate::follow::interrupted;
+use crate::stream_colors::{ColorizerConfig, Image, StreamColorizer};
+
+
+const HIDE_CURSOR_CODE: &str = "\u{001B}[?25l";
+const SHOW_CURSOR_CODE: &str = "\u{001B}[?25h";
+const CLEAR_SCREEN_CODE: &str = "\u{001B}[2J";
+const CURSOR_HOME_CODE: &str = "\u{001B}[H";
+
+/// Frames with a shorter delay than this are shown for [DEFAULT_DELAY] instead, as browsers do,
+/// since many gifs rely on it
+const MIN_DELAY: Duration = Duration::from_millis(20);
+const DEFAULT_DELAY: Duration = Duration::from_millis(100);
+
+/// Longest time to sleep for before checking whether we have been interrupted
+const INTERRUPT_POLL: Duration = Duration::from_millis(50);
+
+
+/// Decode each frame of a gif, along with how long it is shown for
+pub fn load_gif_frames(path: &Path) -> Result<Vec<(RgbaImage, Duration)>> {
+    if ImageFormat::from_path(path).ok() != Some(ImageFormat::Gif) {
+        return Err(anyhow!("Only gif images can be animated, but {} isn't a gif", path.display()));
+    }
+
+    let file = File::open(path)
+        .with_context(|| format!("Couldn't open {}", path.display()))?;
+    let frames = GifDecoder::new(BufReader::new(file))?
+        .into_frames()
+        .collect_frames()?;
+
+    Ok(frames.into_iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let delay = Duration::from_secs_f64(numer as f64 / denom.max(1) as f64 / 1000f64);
+            let delay = if delay < MIN_DELAY { DEFAULT_DELAY } else { delay };
+            (frame.into_buffer(), delay)
+        })
+        .collect())
+}
+
+
+/// Redraw the text from the top left of the screen, colored by each frame in turn, until
+/// interrupted. The cursor is hidden while playing, then shown again and colors reset afterwards.
+pub fn play_animation<W: Write>(frames: &mut [(Image<RgbImage>, Duration)], text: &[u8], mut output: W, config: &ColorizerConfig) -> io::Result<()> {
+    if frames.is_empty() {
+        return Ok(());
+    }
+
+    write!(output, "{HIDE_CURSOR_CODE}{CLEAR_SCREEN_CODE}")?;
+
+    'playing: loop {
+        for (image, delay) in frames.iter_mut() {
+            write!(output, "{CURSOR_HOME_CODE}")?;
+            image.copy_colorized(text, &mut output, config)?;
+            output.flush()?;
+
+            if sleep_unless_interrupted(*delay) {
+                break 'playing;
+            }
+        }
+    }
+
+    write!(output, "{RESET_CODE}{SHOW_CURSOR_CODE}")?;
+    output.flush()
+}
+
+
+/// Sleep for the given time, waking early if interrupted. Returns true if interrupted
+fn sleep_unless_interrupted(duration: Duration) -> bool {
+    let mut remaining = duration;
+    while !remaining.is_zero() {
+        if interrupted() {
+            return true;
+        }
+        let step = remaining.min(INTERRUPT_POLL);
+        thread::sleep(step);
+        remaining -= step;
+    }
+    interrupted()
+}
@@ -2,9 +2,18 @@
 pub use ansi_parsing::*;
 pub use console_color::*;
 pub use console_elem::*;
+pub use width::*;
+pub use named_colors::*;
 
 mod console_elem;
 mod ansi_parsing;
 mod console_color;
+mod width;
+mod named_colors;
 
-pub const RESET_CODE: & str = "\u{001B}[0m";
\ No newline at end of file
+pub const RESET_CODE: & str = "\u{001B}[0m";
+pub const BOLD_CODE: & str = "\u{001B}[1m";
+pub const NORMAL_INTENSITY_CODE: & str = "\u{001B}[22m";
+pub const UNDERLINE_CODE: & str = "\u{001B}[4m";
+pub const NO_UNDERLINE_CODE: & str = "\u{001B}[24m";
+pub const DEFAULT_BACKGROUND_CODE: & str = "\u{001B}[49m";
\ No newline at end of file
@@ -1,3 +1,42 @@
+use super::Color;
+
+/// Find the length in bytes of the escape sequence at the start of s. None is returned if s doesn't
+/// start with an escape, or if the sequence is cut off before its end.
+pub fn ansi_sequence_len(s: &str) -> Option<usize> {
+    if !s.starts_with('\u{001B}') {
+        return None;
+    }
+
+    if s.starts_with("\u{001B}]") {
+        // Operating system commands, such as hyperlinks, run until a BEL or ST terminator
+        s.char_indices()
+            .skip(2)
+            .find_map(|(i, c)| match c {
+                '\u{0007}' => Some(i + 1),
+                '\u{001B}' if s[i+1..].starts_with('\\') => Some(i + 2),
+                _ => None,
+            })
+    } else if s.len() > 1 && !s.starts_with("\u{001B}[") {
+        // Escape sequences other than control sequences, such as saving the cursor with ESC 7 or
+        // selecting a charset with ESC ( B, are any intermediate bytes and then one final byte.
+        // A malformed sequence is cut short after the escape, rather than eating the text after it
+        let intermediates = s.bytes().skip(1).take_while(|b| (0x20..=0x2F).contains(b)).count();
+        match s.as_bytes().get(1 + intermediates) {
+            Some(0x30..=0x7E) => Some(intermediates + 2),
+            Some(_) => Some(1),
+            None => None,
+        }
+    } else {
+        // Control sequences run until their final byte. The opening [ isn't final, but anything
+        // after it is
+        s.char_indices()
+            .skip(1)
+            .find(|&(i, c)| c > '\u{0040}' && (c != '[' || i > 1))
+            .map(|(i, c)| i + c.len_utf8())
+    }
+}
+
+
 fn take_one_argument(remaining: &str, default: isize) -> (&str, isize) {
     if remaining.is_empty() {
         return ("", 0);
@@ -19,30 +58,48 @@ fn take_one_argument(remaining: &str, default: isize) -> (&str, isize) {
 #[derive(Debug, Clone)]
 pub enum AnsiCodeType {
     ResetStyle,
-    SetColor,
+    /// Sets the foreground or background color. The foreground color is given if the sequence
+    /// sets it to a basic, 256 color palette or 24-bit color
+    SetColor(Option<Color>),
+    /// Sets text attributes such as bold or underline, possibly along with colors. reset is true
+    /// if the style was reset first, and codes lists the attribute codes which follow the reset,
+    /// with any colors left out. The foreground color is given as for [AnsiCodeType::SetColor]
+    SetAttributes { reset: bool, codes: Vec<u16>, color: Option<Color> },
     MoveCursor(Option<isize>, Option<isize>),
     SetCursor(Option<usize>, Option<usize>),
+    /// Erase part of the line. 0 erases to the end, 1 to the start and 2 the whole line
+    EraseLine(u8),
+    /// Erase part of the screen. 0 erases to the end, 1 to the start, 2 the whole screen and 3
+    /// the scrollback too
+    EraseDisplay(u8),
+    SaveCursor,
+    RestoreCursor,
+    /// An operating system command, such as a hyperlink or window title. These print nothing, so
+    /// don't move the cursor
+    OperatingSystemCommand,
     Other,
 }
 
 
 pub fn parse_ansi_type(ansi: &str) -> AnsiCodeType {
-    if ansi.len() <= 2 {
+    match ansi {
+        "\u{001B}7" | "\u{001B}[s" => return AnsiCodeType::SaveCursor,
+        "\u{001B}8" | "\u{001B}[u" => return AnsiCodeType::RestoreCursor,
+        _ => {},
+    }
+
+    if ansi.starts_with("\u{001B}]") {
+        return AnsiCodeType::OperatingSystemCommand;
+    }
+    // Other escapes, such as selecting a charset with ESC ( B, can end in the same letters as
+    // control sequences, so are only recognised above
+    if ansi.len() <= 2 || !ansi.starts_with("\u{001B}[") {
         return AnsiCodeType::Other;
     }
     let args = &ansi[2..ansi.len()-1];
 
     if ansi.ends_with('m') {
-        if ansi[1..].starts_with("[3") {
-            AnsiCodeType::SetColor
-        } else {
-            let (_, mode) = take_one_argument(args, 0);
-            if mode == 0 {
-                AnsiCodeType::ResetStyle
-            } else {
-                AnsiCodeType::Other
-            }
-        }
+        parse_sgr(args)
 
     } else if ansi[1..].ends_with('A') {
         let (_, count) = take_one_argument(args, 1);
@@ -78,7 +135,255 @@ pub fn parse_ansi_type(ansi: &str) -> AnsiCodeType {
         let (_, col) = take_one_argument(args, 1);
         AnsiCodeType::SetCursor(Some((col-1) as usize), Some((row-1) as usize))
 
+    } else if ansi[1..].ends_with('J') {
+        let (_, mode) = take_one_argument(args, 0);
+        AnsiCodeType::EraseDisplay(u8::try_from(mode).unwrap_or(0))
+
+    } else if ansi[1..].ends_with('K') {
+        let (_, mode) = take_one_argument(args, 0);
+        AnsiCodeType::EraseLine(u8::try_from(mode).unwrap_or(0))
+
     } else {
         AnsiCodeType::Other
     }
-}
\ No newline at end of file
+}
+
+
+/// Parse the parameters of a select graphic rendition code, separating any colors from the other
+/// attributes. Codes which don't parse, such as private modes, are left as [AnsiCodeType::Other].
+fn parse_sgr(args: &str) -> AnsiCodeType {
+    let mut reset = false;
+    let mut codes = Vec::new();
+    let mut color = None;
+
+    // An empty parameter is the same as 0, so "\x1b[m" is a reset
+    let mut params = args.split(';');
+    while let Some(param) = params.next() {
+        // Colon separated sub-parameters are only used by extended colors
+        if param.contains(':') {
+            continue;
+        }
+
+        let Ok(code) = (if param.is_empty() { Ok(0) } else { param.parse::<u16>() })
+            else {
+                return AnsiCodeType::Other;
+            };
+
+        match code {
+            0 => {
+                reset = true;
+                codes.clear();
+                color = None;
+            },
+            30..=37 => color = Some(Color::from_ansi_index((code - 30) as u8)),
+            90..=97 => color = Some(Color::from_ansi_index((code - 90 + 8) as u8)),
+            39 => color = None,
+            40..=47 | 49 | 59 | 100..=107 => {},
+            // Extended colors are followed by either a palette index, or red, green and blue
+            38 | 48 | 58 => {
+                let extended = match params.next() {
+                    Some("5") => params.next()
+                        .and_then(|index| index.parse().ok())
+                        .map(Color::from_ansi_index),
+                    Some("2") => {
+                        let mut channel = || params.next().and_then(|c| c.parse().ok());
+                        match (channel(), channel(), channel()) {
+                            (Some(r), Some(g), Some(b)) => Some(Color::from_rgb(r, g, b)),
+                            _ => None,
+                        }
+                    },
+                    _ => None,
+                };
+                if code == 38 {
+                    color = extended;
+                }
+            },
+            _ => codes.push(code),
+        }
+    }
+
+    match (reset, codes.is_empty()) {
+        (false, true) => AnsiCodeType::SetColor(color),
+        (true, true) => AnsiCodeType::ResetStyle,
+        (_, false) => AnsiCodeType::SetAttributes { reset, codes, color },
+    }
+}
+
+
+/// Build a select graphic rendition code from its parameters
+pub fn sgr_code(codes: &[u16]) -> String {
+    let codes: Vec<String> = codes.iter().map(u16::to_string).collect();
+    format!("\u{001B}[{}m", codes.join(";"))
+}
+
+
+/// Text attributes such as bold and underline which are currently enabled, tracked from the
+/// attribute codes 1 to 9 and the codes which turn them off again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TextAttributes(u16);
+
+
+impl TextAttributes {
+    /// Update the attributes for a select graphic rendition code. Unknown codes are ignored
+    pub fn apply(&mut self, code: u16) {
+        let bits = |codes: &[u16]| codes.iter().fold(0, |bits, code| bits | 1 << code);
+        match code {
+            0 => self.0 = 0,
+            1..=9 => self.0 |= 1 << code,
+            // Normal intensity turns off both bold and faint, and steady turns off both blinks
+            22 => self.0 &= !bits(&[1, 2]),
+            25 => self.0 &= !bits(&[5, 6]),
+            23 | 24 | 27 | 28 | 29 => self.0 &= !(1 << (code - 20)),
+            _ => {},
+        }
+    }
+
+    /// The code to enable every attribute, or None if none are enabled
+    pub fn to_sgr(self) -> Option<String> {
+        let codes: Vec<u16> = (1..=9).filter(|code| self.0 & (1 << code) != 0).collect();
+        (!codes.is_empty()).then(|| sgr_code(&codes))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The foreground color set by a code, if it only sets colors
+    fn set_color(ansi: &str) -> Option<Color> {
+        match parse_ansi_type(ansi) {
+            AnsiCodeType::SetColor(color) => color,
+            other => panic!("Expected {ansi:?} to set a color, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_foreground_colors() {
+        assert_eq!(set_color("\u{1B}[31m"), Some(Color::from_ansi_index(1)));
+        assert_eq!(set_color("\u{1B}[97m"), Some(Color::from_ansi_index(15)));
+        assert_eq!(set_color("\u{1B}[38;5;208m"), Some(Color::from_ansi_index(208)));
+        assert_eq!(set_color("\u{1B}[38;2;10;20;30m"), Some(Color::from_rgb(10, 20, 30)));
+
+        // The last foreground color wins, and 39 is the default color
+        assert_eq!(set_color("\u{1B}[31;38;5;9m"), Some(Color::from_ansi_index(9)));
+        assert_eq!(set_color("\u{1B}[31;39m"), None);
+
+        // Background colors don't give a foreground color
+        assert_eq!(set_color("\u{1B}[44m"), None);
+        assert_eq!(set_color("\u{1B}[48;2;1;2;3m"), None);
+    }
+
+    #[test]
+    fn parses_erase_modes() {
+        assert!(matches!(parse_ansi_type("\u{1B}[K"), AnsiCodeType::EraseLine(0)));
+        assert!(matches!(parse_ansi_type("\u{1B}[1K"), AnsiCodeType::EraseLine(1)));
+        assert!(matches!(parse_ansi_type("\u{1B}[2K"), AnsiCodeType::EraseLine(2)));
+        assert!(matches!(parse_ansi_type("\u{1B}[J"), AnsiCodeType::EraseDisplay(0)));
+        assert!(matches!(parse_ansi_type("\u{1B}[2J"), AnsiCodeType::EraseDisplay(2)));
+        assert!(matches!(parse_ansi_type("\u{1B}[3J"), AnsiCodeType::EraseDisplay(3)));
+    }
+
+    #[test]
+    fn keeps_colors_set_with_attributes() {
+        match parse_ansi_type("\u{1B}[1;38;5;9m") {
+            AnsiCodeType::SetAttributes { reset, codes, color } => {
+                assert!(!reset);
+                assert_eq!(codes, [1]);
+                assert_eq!(color, Some(Color::from_ansi_index(9)));
+            },
+            other => panic!("Expected attributes, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn measures_escapes_without_brackets() {
+        assert_eq!(ansi_sequence_len("\u{1B}7abc"), Some(2));
+        assert_eq!(ansi_sequence_len("\u{1B}8"), Some(2));
+        assert_eq!(ansi_sequence_len("\u{1B}(Babc"), Some(3));
+        assert_eq!(ansi_sequence_len("\u{1B}Mabc"), Some(2));
+        assert_eq!(ansi_sequence_len("\u{1B}cabc"), Some(2));
+
+        // A malformed sequence is only the escape, leaving the text after it alone
+        assert_eq!(ansi_sequence_len("\u{1B}\u{7F}abc"), Some(1));
+        assert_eq!(ansi_sequence_len("\u{1B}(\u{7F}abc"), Some(1));
+        assert_eq!(ansi_sequence_len("\u{1B}é"), Some(1));
+
+        // Sequences cut off at the end need more data
+        assert_eq!(ansi_sequence_len("\u{1B}"), None);
+        assert_eq!(ansi_sequence_len("\u{1B}("), None);
+        assert_eq!(ansi_sequence_len("abc"), None);
+    }
+
+    #[test]
+    fn parses_cursor_saves_without_brackets() {
+        assert!(matches!(parse_ansi_type("\u{1B}7"), AnsiCodeType::SaveCursor));
+        assert!(matches!(parse_ansi_type("\u{1B}8"), AnsiCodeType::RestoreCursor));
+        assert!(matches!(parse_ansi_type("\u{1B}(B"), AnsiCodeType::Other));
+    }
+
+    /// The reset flag, attribute codes and foreground color set by a code with attributes
+    fn attributes(ansi: &str) -> (bool, Vec<u16>, Option<Color>) {
+        match parse_ansi_type(ansi) {
+            AnsiCodeType::SetAttributes { reset, codes, color } => (reset, codes, color),
+            other => panic!("Expected {ansi:?} to set attributes, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parses_attribute_codes() {
+        assert_eq!(attributes("\u{1B}[1;4m"), (false, vec![1, 4], None));
+        assert_eq!(attributes("\u{1B}[0;1m"), (true, vec![1], None));
+        assert_eq!(attributes("\u{1B}[22;25m"), (false, vec![22, 25], None));
+
+        // A reset part way through drops the codes before it
+        assert_eq!(attributes("\u{1B}[4;0;1m"), (true, vec![1], None));
+    }
+
+    #[test]
+    fn skips_extended_color_parameters() {
+        // The channels of an extended color aren't attribute codes, even if they look like them
+        assert_eq!(attributes("\u{1B}[38;2;1;4;5;7m"), (false, vec![7], Some(Color::from_rgb(1, 4, 5))));
+        assert_eq!(attributes("\u{1B}[48;2;1;2;3;1m"), (false, vec![1], None));
+        assert_eq!(attributes("\u{1B}[48;5;4;3m"), (false, vec![3], None));
+    }
+
+    #[test]
+    fn empty_parameters_are_resets() {
+        assert!(matches!(parse_ansi_type("\u{1B}[m"), AnsiCodeType::ResetStyle));
+        assert!(matches!(parse_ansi_type("\u{1B}[;m"), AnsiCodeType::ResetStyle));
+        assert_eq!(attributes("\u{1B}[;1m"), (true, vec![1], None));
+        // An empty parameter after a code resets it too
+        assert!(matches!(parse_ansi_type("\u{1B}[1;m"), AnsiCodeType::ResetStyle));
+    }
+
+    #[test]
+    fn tracks_enabled_attributes() {
+        let mut attributes = TextAttributes::default();
+        attributes.apply(1);
+        attributes.apply(4);
+        assert_eq!(attributes.to_sgr().as_deref(), Some("\u{1B}[1;4m"));
+
+        // Normal intensity turns off both bold and faint, and steady both blinks
+        attributes.apply(2);
+        attributes.apply(5);
+        attributes.apply(6);
+        attributes.apply(22);
+        attributes.apply(25);
+        assert_eq!(attributes.to_sgr().as_deref(), Some("\u{1B}[4m"));
+
+        attributes.apply(0);
+        assert_eq!(attributes.to_sgr(), None);
+    }
+
+    #[test]
+    fn measures_hyperlinks() {
+        let open = "\u{1B}]8;;https://example.com\u{1B}\\";
+        assert_eq!(ansi_sequence_len(&format!("{open}label")), Some(open.len()));
+        assert_eq!(ansi_sequence_len("\u{1B}]8;;https://example.com\u{7}label"), Some(25));
+        assert!(matches!(parse_ansi_type(open), AnsiCodeType::OperatingSystemCommand));
+
+        // A hyperlink cut off before its terminator needs more data
+        assert_eq!(ansi_sequence_len("\u{1B}]8;;https://exa"), None);
+    }
+}
@@ -1,56 +1,107 @@
 use std::fmt::{Display, Formatter};
 use std::io;
 use std::str::FromStr;
-use std::sync::LazyLock;
 
+use super::color_by_name;
+
+/// Foreground codes of the 8 color ansi palette. Codes are written without a leading 0, which
+/// would reset any attributes such as bold along with the color
 // TODO: This palette isn't very accurate - should be easy to improve if a good resource can be found
-const ANSI_PALETTE: &[((u8, u8), Color)] = &[
-    ((0, 30), Color(0, 0, 0)),
-    ((0, 31), Color(200, 0, 0)),
-    ((0, 32), Color(0, 200, 0)),
-    ((0, 33), Color(200, 200, 0)),
-    ((0, 34), Color(0, 0, 200)),
-    ((0, 35), Color(200, 0, 200)),
-    ((0, 36), Color(0, 200, 200)),
-    ((0, 37), Color(255, 255, 255)),
+const ANSI_PALETTE: &[(u8, Color)] = &[
+    (30, Color::BLACK),
+    (31, Color(200, 0, 0)),
+    (32, Color(0, 200, 0)),
+    (33, Color(200, 200, 0)),
+    (34, Color(0, 0, 200)),
+    (35, Color(200, 0, 200)),
+    (36, Color(0, 200, 200)),
+    (37, Color::WHITE),
 ];
 
 
-/// Color index -> ansi color lookup table. Generated at runtime.
-static COLOR_LOOKUP: LazyLock<Vec<(u8, u8)>> = LazyLock::new(|| {
-    let mut lookup = vec![(0, 0); 256*256*256];
-    for r in 0..=255u8 {
-        for g in 0..=255u8 {
-            for b in 0..=255u8 {
-                let this_col = Color(r, g, b);
-                let index = this_col.lookup_index();
-
-                lookup[index] = ANSI_PALETTE.iter().min_by_key(|(_, c)| {
-                    c.dist2(this_col)
-                }).expect("Palette is non-empty").0
-            }
-        }
-    }
+/// The 16 system colors of the 256 color palette, using xterm's defaults. Terminals often let these
+/// be changed, so the real colors may differ.
+const SYSTEM_COLORS: [Color; 16] = [
+    Color(0, 0, 0), Color(205, 0, 0), Color(0, 205, 0), Color(205, 205, 0),
+    Color(0, 0, 238), Color(205, 0, 205), Color(0, 205, 205), Color(229, 229, 229),
+    Color(127, 127, 127), Color(255, 0, 0), Color(0, 255, 0), Color(255, 255, 0),
+    Color(92, 92, 255), Color(255, 0, 255), Color(0, 255, 255), Color(255, 255, 255),
+];
 
-    lookup
-});
+/// Channel levels of the 6x6x6 color cube in the 256 color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
 
 
-/// A single rbg24 color
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// A single rbg24 color. Colors are ordered by their red, then green, then blue channels, which
+/// is arbitrary but cheap, and lets them be used in sorted collections
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Color(u8, u8, u8);
 
 
 impl Color {
+    pub const BLACK: Color = Color::gray(0);
+    pub const WHITE: Color = Color::gray(255);
+
     pub const fn from_rgb(r: u8, g: u8, b: u8) -> Self {
         Self(r, g, b)
     }
 
-    const fn lookup_index(self) -> usize {
-        (self.0 as usize * 256 + (self.1 as usize)) * 256 + (self.2 as usize)
+    pub const fn to_rgb(self) -> (u8, u8, u8) {
+        (self.0, self.1, self.2)
+    }
+
+    /// A shade of gray, from black (0) to white (255)
+    pub const fn gray(level: u8) -> Self {
+        Self(level, level, level)
+    }
+
+    /// The color of an entry in the xterm 256 color palette. Indices 0-15 are the system colors,
+    /// 16-231 form a 6x6x6 color cube, and 232-255 are a ramp of grays.
+    pub const fn from_ansi_index(index: u8) -> Self {
+        match index {
+            0..=15 => SYSTEM_COLORS[index as usize],
+            16..=231 => {
+                let i = index as usize - 16;
+                Self(CUBE_LEVELS[i / 36], CUBE_LEVELS[(i / 6) % 6], CUBE_LEVELS[i % 6])
+            },
+            _ => Self::gray(8 + (index - 232) * 10),
+        }
+    }
+
+    /// Index of the closest color in the xterm 256 color palette, measured using
+    /// [Color::distance_weighted]. The cube and gray ramp are preferred over the system colors
+    /// when equally close, since the system colors are often changed by the terminal's theme.
+    pub fn to_ansi_index(self) -> u8 {
+        (16..=255).chain(0..16)
+            .min_by(|&a, &b| {
+                let a = self.distance_weighted(Self::from_ansi_index(a));
+                let b = self.distance_weighted(Self::from_ansi_index(b));
+                a.total_cmp(&b)
+            })
+            .expect("Palette is non-empty")
+    }
+
+    /// Closest color in the 8 color ansi palette, as used by [Color::write_as_paletted_ansi]
+    pub fn to_palette8(self) -> Self {
+        let code = self.nearest_palette_code();
+        ANSI_PALETTE.iter()
+            .find(|&&(c, _)| c == code)
+            .map(|&(_, color)| color)
+            .expect("Code is from the palette")
+    }
+
+    /// Code of the closest color in the 8 color ansi palette. The palette is tiny, so searching it
+    /// each time is cheaper than building a lookup table
+    fn nearest_palette_code(self) -> u8 {
+        ANSI_PALETTE.iter()
+            .min_by_key(|(_, c)| c.distance_sq(self))
+            .expect("Palette is non-empty")
+            .0
     }
 
-    fn dist2(self, other: Color) -> u32 {
+    /// Squared euclidean distance between two colors in rgb space. Cheap, but doesn't match human
+    /// perception very well.
+    pub fn distance_sq(self, other: Color) -> u32 {
         let dr = (self.0 as u32).abs_diff(other.0 as u32);
         let dg = (self.1 as u32).abs_diff(other.1 as u32);
         let db = (self.2 as u32).abs_diff(other.2 as u32);
@@ -58,6 +109,56 @@ impl Color {
         dr*dr + dg*dg + db*db
     }
 
+    /// True if the colors are no further apart than tolerance, measured as euclidean distance in
+    /// rgb space. Useful for comparing colors which went through fixed point arithmetic, such as
+    /// interpolation, where channels may be off by one
+    pub fn is_close_to(self, other: Color, tolerance: u32) -> bool {
+        self.distance_sq(other) <= tolerance.saturating_mul(tolerance)
+    }
+
+    /// Squared euclidean distance in rgb space, weighting each channel by how sensitive human
+    /// vision is to it (the "redmean" approximation). A good compromise between speed and accuracy.
+    pub fn distance_weighted(self, other: Color) -> f32 {
+        let r_mean = (self.0 as f32 + other.0 as f32) / 2f32;
+        let dr = self.0 as f32 - other.0 as f32;
+        let dg = self.1 as f32 - other.1 as f32;
+        let db = self.2 as f32 - other.2 as f32;
+
+        (2f32 + r_mean / 256f32) * dr*dr
+            + 4f32 * dg*dg
+            + (2f32 + (255f32 - r_mean) / 256f32) * db*db
+    }
+
+    /// The CIE76 color difference, which is the euclidean distance between two colors in CIELAB
+    /// space. A difference of around 2.3 is just noticeable.
+    pub fn delta_e_cie76(self, other: Color) -> f32 {
+        let (l1, a1, b1) = self.to_lab();
+        let (l2, a2, b2) = other.to_lab();
+
+        ((l1-l2).powi(2) + (a1-a2).powi(2) + (b1-b2).powi(2)).sqrt()
+    }
+
+    /// Convert to CIELAB, treating this color as sRGB with a D65 white point
+    pub fn to_lab(self) -> (f32, f32, f32) {
+        let (r, g, b) = (srgb_to_linear(self.0), srgb_to_linear(self.1), srgb_to_linear(self.2));
+
+        // Linear rgb -> XYZ, normalised by the reference white
+        let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+        fn f(t: f32) -> f32 {
+            if t > 216f32 / 24389f32 {
+                t.cbrt()
+            } else {
+                (24389f32 / 27f32 * t + 16f32) / 116f32
+            }
+        }
+
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+        (116f32 * fy - 16f32, 500f32 * (fx - fy), 200f32 * (fy - fz))
+    }
+
     pub fn rgb_interpolate(self, Color(or, og, ob): Self, alpha: f32) -> Self {
         let Color(tr, tg, tb) = self;
 
@@ -73,26 +174,245 @@ impl Color {
         )
     }
 
+    /// Interpolate between this color and another in the OKLab color space, where alpha ranges from
+    /// 0 (this color) to 1 (the other color). Unlike [Color::rgb_interpolate], the midpoints keep
+    /// their brightness and saturation rather than turning muddy.
+    pub fn oklab_interpolate(self, other: Self, alpha: f32) -> Self {
+        let alpha = alpha.clamp(0f32, 1f32) as f64;
+        let (l1, a1, b1) = self.to_oklab();
+        let (l2, a2, b2) = other.to_oklab();
+
+        Self::from_oklab(
+            l1 + (l2 - l1) * alpha,
+            a1 + (a2 - a1) * alpha,
+            b1 + (b2 - b1) * alpha,
+        )
+    }
+
+    /// Convert to OKLab lightness and a, b components
+    pub fn to_oklab(self) -> (f64, f64, f64) {
+        let to_linear = |c: u8| srgb_to_linear(c) as f64;
+        let (r, g, b) = (to_linear(self.0), to_linear(self.1), to_linear(self.2));
+
+        let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+        let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+        let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+
+        (
+            0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+            1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+            0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+        )
+    }
+
+    /// Convert from OKLab lightness and a, b components. Colors outside of the sRGB gamut are
+    /// clamped channel-wise.
+    pub fn from_oklab(lightness: f64, a: f64, b: f64) -> Self {
+        let l = (lightness + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+        let m = (lightness - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+        let s = (lightness - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+
+        let to_channel = |c: f64| {
+            let c = c.clamp(0f64, 1f64);
+            let c = if c <= 0.0031308 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1f64 / 2.4) - 0.055
+            };
+            (c * 255f64).round().clamp(0f64, 255f64) as u8
+        };
+
+        Color(
+            to_channel(4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s),
+            to_channel(-1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s),
+            to_channel(-0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s),
+        )
+    }
+
+    /// Composite this color at the given opacity over a background, where alpha ranges from 0
+    /// (only the background) to 1 (only this color). Unlike [Color::rgb_interpolate], the end
+    /// points are exact.
+    pub fn blend_over(self, Color(br, bg, bb): Self, alpha: f32) -> Self {
+        let alpha = alpha.clamp(0f32, 1f32);
+        let blend = |fore: u8, back: u8| {
+            (fore as f32 * alpha + back as f32 * (1f32 - alpha)).round() as u8
+        };
+
+        Color(blend(self.0, br), blend(self.1, bg), blend(self.2, bb))
+    }
+
+    /// Convert to hue (in degrees), saturation and lightness, with saturation and lightness in
+    /// the range 0..=1
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let (r, g, b) = (self.0 as f32 / 255f32, self.1 as f32 / 255f32, self.2 as f32 / 255f32);
+
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let lightness = (max + min) / 2f32;
+
+        let delta = max - min;
+        if delta == 0f32 {
+            return (0f32, 0f32, lightness);
+        }
+
+        let saturation = delta / (1f32 - (2f32 * lightness - 1f32).abs());
+        let hue = if max == r {
+            60f32 * ((g - b) / delta).rem_euclid(6f32)
+        } else if max == g {
+            60f32 * ((b - r) / delta + 2f32)
+        } else {
+            60f32 * ((r - g) / delta + 4f32)
+        };
+
+        (hue, saturation, lightness)
+    }
+
+    /// Convert from hue (in degrees), saturation and lightness. Saturation and lightness are
+    /// clamped to the range 0..=1
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+        let saturation = saturation.clamp(0f32, 1f32);
+        let lightness = lightness.clamp(0f32, 1f32);
+
+        let chroma = (1f32 - (2f32 * lightness - 1f32).abs()) * saturation;
+        let sector = hue.rem_euclid(360f32) / 60f32;
+        let x = chroma * (1f32 - (sector % 2f32 - 1f32).abs());
+
+        let (r, g, b) = match sector as u32 {
+            0 => (chroma, x, 0f32),
+            1 => (x, chroma, 0f32),
+            2 => (0f32, chroma, x),
+            3 => (0f32, x, chroma),
+            4 => (x, 0f32, chroma),
+            _ => (chroma, 0f32, x),
+        };
+
+        let m = lightness - chroma / 2f32;
+        let to_channel = |v: f32| ((v + m) * 255f32).round().clamp(0f32, 255f32) as u8;
+        Color(to_channel(r), to_channel(g), to_channel(b))
+    }
+
+    /// Adjust the saturation and lightness in hsl space, leaving the color untouched if neither
+    /// changes
+    fn adjust_hsl(self, saturation_delta: f32, lightness_delta: f32) -> Self {
+        if saturation_delta == 0f32 && lightness_delta == 0f32 {
+            return self;
+        }
+
+        let (hue, saturation, lightness) = self.to_hsl();
+        Self::from_hsl(hue, saturation + saturation_delta, lightness + lightness_delta)
+    }
+
+    /// Increase the saturation by the given amount, out of 1
+    pub fn saturate(self, amount: f32) -> Self {
+        self.adjust_hsl(amount, 0f32)
+    }
+
+    /// Decrease the saturation by the given amount, out of 1
+    pub fn desaturate(self, amount: f32) -> Self {
+        self.adjust_hsl(-amount, 0f32)
+    }
+
+    /// Increase the lightness by the given amount, out of 1
+    pub fn lighten(self, amount: f32) -> Self {
+        self.adjust_hsl(0f32, amount)
+    }
+
+    /// Decrease the lightness by the given amount, out of 1
+    pub fn darken(self, amount: f32) -> Self {
+        self.adjust_hsl(0f32, -amount)
+    }
+
+    /// Average a slice of colors, channel-wise. An empty slice gives the default color.
+    pub fn mix_many(colors: &[Color]) -> Self {
+        if colors.is_empty() {
+            return Self::default();
+        }
+
+        let (mut r, mut g, mut b) = (0u32, 0u32, 0u32);
+        for &Color(cr, cg, cb) in colors {
+            r += cr as u32;
+            g += cg as u32;
+            b += cb as u32;
+        }
+
+        let n = colors.len() as u32;
+        Color((r / n) as u8, (g / n) as u8, (b / n) as u8)
+    }
+
+    /// Average a slice of colors, where each color is paired with its weight. If the total weight
+    /// is zero, the default color is given.
+    pub fn mix_many_weighted(colors: &[(Color, u32)]) -> Self {
+        let total: u64 = colors.iter().map(|&(_, weight)| weight as u64).sum();
+        if total == 0 {
+            return Self::default();
+        }
+
+        let (mut r, mut g, mut b) = (0u64, 0u64, 0u64);
+        for &(Color(cr, cg, cb), weight) in colors {
+            r += cr as u64 * weight as u64;
+            g += cg as u64 * weight as u64;
+            b += cb as u64 * weight as u64;
+        }
+
+        Color((r / total) as u8, (g / total) as u8, (b / total) as u8)
+    }
+
     pub fn write_as_24bit_ansi<O>(self, mut output: O) -> io::Result<()>
         where O: io::Write {
         let Color(r, g, b) = self;
         write!(output, "\u{001B}[38;2;{r};{g};{b}m")
     }
 
+    pub fn write_as_24bit_ansi_bg<O>(self, mut output: O) -> io::Result<()>
+        where O: io::Write {
+        let Color(r, g, b) = self;
+        write!(output, "\u{001B}[48;2;{r};{g};{b}m")
+    }
+
+    /// Write the closest color from the 256 color palette, as found by [Color::to_ansi_index]
+    pub fn write_as_256_ansi<O>(self, mut output: O) -> io::Result<()>
+        where O: io::Write {
+        write!(output, "\u{001B}[38;5;{}m", self.to_ansi_index())
+    }
+
+    pub fn write_as_256_ansi_bg<O>(self, mut output: O) -> io::Result<()>
+        where O: io::Write {
+        write!(output, "\u{001B}[48;5;{}m", self.to_ansi_index())
+    }
+
+    /// Write the closest color from the 8 color ansi palette, where "closest" is measured using
+    /// [Color::distance_sq]
     pub fn write_as_paletted_ansi<O>(self, mut output: O) -> io::Result<()>
         where O: io::Write {
-        // Find closest
-        let (a, b) = *COLOR_LOOKUP.get(self.lookup_index())
-            .expect("All colors have corresponding palette value");
+        let code = self.nearest_palette_code();
 
-        write!(output, "\u{001B}[{a};{b}m")
+        write!(output, "\u{001B}[{code}m")
+    }
+
+    pub fn write_as_paletted_ansi_bg<O>(self, mut output: O) -> io::Result<()>
+        where O: io::Write {
+        // Background codes are offset by 10 from their foreground equivalents
+        let code = self.nearest_palette_code();
+
+        write!(output, "\u{001B}[{}m", code + 10)
+    }
+}
+
+
+/// Convert an sRGB channel to linear light, in the range 0..=1
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255f32;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
     }
 }
 
 
 impl Default for Color {
     fn default() -> Self {
-        Self::from_rgb(0, 0, 0)
+        Self::BLACK
     }
 }
 
@@ -101,8 +421,26 @@ impl FromStr for Color {
     type Err = &'static str;
 
     fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(color) = color_by_name(value) {
+            return Ok(color);
+        }
+
+        let value = value.strip_prefix('#').unwrap_or(value);
+
+        // Shorthand hex codes double up each digit, as in css
+        let expanded;
+        let value = if value.len() == 3 {
+            expanded = value.chars().flat_map(|c| [c, c]).collect::<String>();
+            expanded.as_str()
+        } else {
+            value
+        };
+
         if value.len() != 6 {
-            Err("Color must have length 6")
+            Err("Color must be a color name, or a hex code of length 3 or 6")
+        } else if !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+            // from_str_radix would also accept a leading sign
+            Err("Invalid hexadecimal")
         } else if let Ok(int) = u32::from_str_radix(value, 16) {
             let b = (int % 256) as u8;
             let g = ((int / 256) % 256) as u8;
@@ -120,3 +458,167 @@ impl Display for Color {
         write!(f, "{:>02X}{:>02X}{:>02X}", self.0, self.1, self.2)
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances_are_zero_to_self_and_symmetric() {
+        let a = Color::from_rgb(0x5B, 0xCE, 0xFA);
+        let b = Color::from_rgb(0xF5, 0xA9, 0xB8);
+
+        assert_eq!(a.distance_sq(a), 0);
+        assert_eq!(a.distance_weighted(a), 0f32);
+        assert_eq!(a.delta_e_cie76(a), 0f32);
+
+        assert_eq!(a.distance_sq(b), b.distance_sq(a));
+        assert_eq!(a.distance_weighted(b), b.distance_weighted(a));
+        assert_eq!(a.delta_e_cie76(b), b.delta_e_cie76(a));
+        assert!(a.delta_e_cie76(b) > 2.3);
+    }
+
+    #[test]
+    fn black_and_white_span_lab_lightness() {
+        let (l, _, _) = Color::BLACK.to_lab();
+        assert!(l.abs() < 0.01);
+        let (l, a, b) = Color::WHITE.to_lab();
+        // The matrix is rounded, so white is very slightly off neutral
+        assert!((l - 100f32).abs() < 0.05 && a.abs() < 0.05 && b.abs() < 0.05);
+    }
+
+    #[test]
+    fn mixing_two_colors_is_their_midpoint() {
+        let a = Color::from_rgb(0x10, 0x80, 0xF0);
+        let b = Color::from_rgb(0xF0, 0x20, 0x00);
+        // Interpolation uses fixed point, so can be off by one in each channel
+        assert!(Color::mix_many(&[a, b]).is_close_to(a.rgb_interpolate(b, 0.5), 2));
+        assert_eq!(Color::mix_many(&[a, a, a]), a);
+    }
+
+    #[test]
+    fn blending_endpoints_are_exact() {
+        let fore = Color::from_rgb(0x10, 0x80, 0xF0);
+        let back = Color::from_rgb(0xF0, 0x20, 0x00);
+        assert_eq!(fore.blend_over(back, 1f32), fore);
+        assert_eq!(fore.blend_over(back, 0f32), back);
+
+        // Interpolation agrees at the end points, apart from its fixed point rounding
+        assert!(fore.blend_over(back, 1f32).is_close_to(back.rgb_interpolate(fore, 1f32), 2));
+        assert!(fore.blend_over(back, 0f32).is_close_to(back.rgb_interpolate(fore, 0f32), 2));
+    }
+
+    #[test]
+    fn grays_have_equal_channels() {
+        assert_eq!(Color::gray(0), Color::BLACK);
+        assert_eq!(Color::gray(255), Color::WHITE);
+        assert_eq!(Color::gray(0x80).to_rgb(), (0x80, 0x80, 0x80));
+        assert_eq!(Color::BLACK.to_rgb(), (0, 0, 0));
+        assert_eq!(Color::WHITE.to_rgb(), (255, 255, 255));
+    }
+
+    #[test]
+    fn mixing_nothing_gives_the_default() {
+        assert_eq!(Color::mix_many(&[]), Color::default());
+        assert_eq!(Color::mix_many_weighted(&[]), Color::default());
+        assert_eq!(Color::mix_many_weighted(&[(Color::WHITE, 0)]), Color::default());
+    }
+
+    #[test]
+    fn mixing_follows_weights() {
+        let mixed = Color::mix_many_weighted(&[(Color::WHITE, 3), (Color::BLACK, 1)]);
+        assert_eq!(mixed, Color::gray(191));
+    }
+
+    #[test]
+    fn palette8_matches_the_written_code() {
+        for color in [Color::from_rgb(0xE0, 0x10, 0x10), Color::from_rgb(0x10, 0x10, 0xC0), Color::gray(0x90)] {
+            let mut written = Vec::new();
+            color.write_as_paletted_ansi(&mut written).unwrap();
+            let mut expected = Vec::new();
+            color.to_palette8().write_as_paletted_ansi(&mut expected).unwrap();
+            assert_eq!(written, expected);
+        }
+        assert_eq!(Color::from_rgb(0xE0, 0x10, 0x10).to_palette8(), Color::from_rgb(200, 0, 0));
+    }
+
+    #[test]
+    fn hsl_adjustments_clamp() {
+        assert_eq!(Color::WHITE.lighten(0.5), Color::WHITE);
+        assert_eq!(Color::BLACK.darken(0.5), Color::BLACK);
+    }
+
+    #[test]
+    fn hsl_adjustments_by_zero_are_identity() {
+        let color = Color::from_rgb(0x12, 0x9A, 0xE4);
+        assert_eq!(color.darken(0f32), color);
+        assert_eq!(color.lighten(0f32), color);
+        assert_eq!(color.saturate(0f32), color);
+        assert_eq!(color.desaturate(0f32), color);
+    }
+
+    #[test]
+    fn hsl_adjustments_move_the_right_way() {
+        let color = Color::from_rgb(0xC0, 0x40, 0x40);
+        let (_, saturation, lightness) = color.to_hsl();
+
+        assert!(color.lighten(0.1).to_hsl().2 > lightness);
+        assert!(color.darken(0.1).to_hsl().2 < lightness);
+        assert!(color.saturate(0.1).to_hsl().1 > saturation);
+        assert!(color.desaturate(0.1).to_hsl().1 < saturation);
+        assert_eq!(color.desaturate(1f32).to_hsl().1, 0f32);
+    }
+
+    #[test]
+    fn ansi_indices_find_the_nearest_entry() {
+        assert_eq!(Color::from_rgb(0xFF, 0, 0).to_ansi_index(), 196);
+        assert!((232..=255).contains(&Color::gray(0x80).to_ansi_index()));
+
+        // The cube is preferred over the system colors which share its black and white
+        assert_eq!(Color::BLACK.to_ansi_index(), 16);
+        assert_eq!(Color::WHITE.to_ansi_index(), 231);
+
+        // Every entry outside the system colors maps back to itself
+        for index in 16..=255 {
+            assert_eq!(Color::from_ansi_index(index).to_ansi_index(), index);
+        }
+    }
+
+    #[test]
+    fn hex_codes_may_be_shorthand() {
+        assert_eq!("ff0000".parse(), Ok(Color::from_rgb(0xFF, 0, 0)));
+        assert_eq!("#FF0000".parse(), Ok(Color::from_rgb(0xFF, 0, 0)));
+        assert_eq!("f00".parse(), Ok(Color::from_rgb(0xFF, 0, 0)));
+        assert_eq!("#0aF".parse(), Ok(Color::from_rgb(0, 0xAA, 0xFF)));
+
+        assert!("ff00".parse::<Color>().is_err());
+        assert!("#".parse::<Color>().is_err());
+        assert!("f0g".parse::<Color>().is_err());
+        assert!("+FF00F".parse::<Color>().is_err());
+        assert!("\u{E9}00".parse::<Color>().is_err());
+    }
+
+    #[test]
+    fn paletted_codes_pick_the_nearest_entry() {
+        let code = |color: Color, background: bool| {
+            let mut written = Vec::new();
+            if background {
+                color.write_as_paletted_ansi_bg(&mut written).unwrap();
+            } else {
+                color.write_as_paletted_ansi(&mut written).unwrap();
+            }
+            String::from_utf8(written).unwrap()
+        };
+
+        // Every palette entry maps to its own code
+        for &(palette_code, color) in ANSI_PALETTE {
+            assert_eq!(code(color, false), format!("\x1b[{palette_code}m"));
+            assert_eq!(code(color, true), format!("\x1b[{}m", palette_code + 10));
+        }
+
+        assert_eq!(code(Color::from_rgb(0xF0, 0x30, 0x20), false), "\x1b[31m");
+        assert_eq!(code(Color::from_rgb(0x10, 0x20, 0x60), false), "\x1b[30m");
+        assert_eq!(code(Color::from_rgb(0x30, 0xD0, 0xE0), true), "\x1b[46m");
+    }
+}
@@ -1,6 +1,8 @@
 use std::io;
 use std::io::Read;
+use std::ops::ControlFlow;
 use unicode_segmentation::UnicodeSegmentation;
+use crate::console::ansi_sequence_len;
 
 // TODO: There are non-printing code points such as ZWS - how are these handled?
 /// Represents one "element" in a stream that is destined to end at a console
@@ -8,8 +10,11 @@ use unicode_segmentation::UnicodeSegmentation;
 #[derive(Debug, Clone, Copy)]
 pub enum ConsoleElem<'a> {
     Newline,
+    /// A carriage return immediately followed by a newline, as used by windows line endings
+    CrlfNewline,
     CarriageReturn,
     Tab,
+    Backspace,
     OtherNonPrinting(char),
     Ansi(&'a str),
     Grapheme(&'a str),
@@ -85,7 +90,7 @@ impl<'a> IterElements<'a> {
         }
     }
 
-    fn consume_from_utf8(&mut self) -> Result<ConsoleElem, NeedMoreData> {
+    fn consume_from_utf8(&mut self) -> Result<ConsoleElem<'_>, NeedMoreData> {
         let KnownSegment::ValidUtf8(mut remaining) =  self.known_segment
             else {panic!()};
 
@@ -93,6 +98,14 @@ impl<'a> IterElements<'a> {
             remaining = &remaining[1..];
             Ok(ConsoleElem::Newline)
 
+        } else if remaining.starts_with("\r\n") {
+            remaining = &remaining[2..];
+            Ok(ConsoleElem::CrlfNewline)
+
+        // A carriage return at the end of the slice may be the start of a windows line ending
+        } else if remaining == "\r" && self.remaining.is_empty() && !self.true_end {
+            return Err(NeedMoreData);
+
         } else if remaining.starts_with('\r') {
             remaining = &remaining[1..];
             Ok(ConsoleElem::CarriageReturn)
@@ -101,27 +114,29 @@ impl<'a> IterElements<'a> {
             remaining = &remaining[1..];
             Ok(ConsoleElem::Tab)
 
-        } else if remaining.starts_with('\u{001B}') {
-            let base = remaining;
-            let mut length = 0;
-
-            let mut valid_end_found = false;
-
-            while let Some(next) = remaining.chars().next() {
-                length += 1;
-                remaining = &remaining[1..];
-                if next > '\u{0040}' && (next != '[' || length > 2) {
-                    valid_end_found = true;
-                    break;
-                }
-            }
-
-            // Cancels the consumption
-            if !valid_end_found && !self.true_end {
-                return Err(NeedMoreData);
-            }
+        } else if remaining.starts_with('\u{0008}') {
+            remaining = &remaining[1..];
+            Ok(ConsoleElem::Backspace)
 
-            Ok(ConsoleElem::Ansi(&base[0..length]))
+        } else if remaining.starts_with('\u{001B}') {
+            // Any text wrapped by an operating system command (such as a hyperlink's label) is
+            // then printed as normal.
+            let length = match ansi_sequence_len(remaining) {
+                Some(length) => length,
+                None if !self.true_end => return Err(NeedMoreData),
+                None => remaining.len(),
+            };
+
+            let (esc_sequence, rest) = remaining.split_at(length);
+            remaining = rest;
+            Ok(ConsoleElem::Ansi(esc_sequence))
+
+        // Fast path for plain ascii text. A printable ascii character followed by any other ascii
+        // character is always a whole grapheme, so segmentation can be skipped
+        } else if matches!(remaining.as_bytes(), [first, next, ..] if (first.is_ascii_graphic() || *first == b' ') && next.is_ascii()) {
+            let (grapheme, rest) = remaining.split_at(1);
+            remaining = rest;
+            Ok(ConsoleElem::Grapheme(grapheme))
 
         } else {
             let first_char = remaining.chars().next().ok_or(NeedMoreData)?;
@@ -166,7 +181,7 @@ impl<'a> IterElements<'a> {
 
     /// Produce an element by consuming raw bytes from the known_segment
     /// requires that known_segment is [KnownSegment::RawBytes]
-    fn consume_from_raw(&mut self) -> Result<ConsoleElem, NeedMoreData> {
+    fn consume_from_raw(&mut self) -> Result<ConsoleElem<'_>, NeedMoreData> {
         let KnownSegment::RawBytes(mut remaining) = self.known_segment
             else {panic!("consume_from_raw called when known_segment was not RawBytes")};
 
@@ -185,7 +200,7 @@ impl<'a> IterElements<'a> {
 
 
     /// Attempts to return the next [ConsoleElement] from the slice
-    fn try_get_next_element(&mut self) -> Result<ConsoleElem, NeedMoreData> {
+    fn try_get_next_element(&mut self) -> Result<ConsoleElem<'_>, NeedMoreData> {
         if matches!(&self.known_segment, KnownSegment::None) {
             self.try_fetch_next_known()?;
         }
@@ -207,16 +222,22 @@ impl<'a> IterElements<'a> {
         self.remaining.len() + match self.known_segment {
             KnownSegment::None => 0,
             KnownSegment::RawBytes(x) => x.len(),
-            KnownSegment::ValidUtf8(x) => x.as_bytes().len(),
+            KnownSegment::ValidUtf8(x) => x.len(),
         }
     }
 }
 
 
-pub fn for_each_console_element<R, F>(mut i: R, mut f: F) -> io::Result<()>
+/// Size of the buffer input is read into, unless told otherwise
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 8192;
+
+
+/// Read console elements from a stream, calling f on each. f may break to stop reading the stream
+/// early. Input is read buffer_size bytes at a time.
+pub fn for_each_console_element<R, F>(mut i: R, buffer_size: usize, mut f: F) -> io::Result<()>
     where R: Read,
-          F: FnMut(ConsoleElem<'_>) -> io::Result<()> {
-    let mut buffer = vec![0; 256];
+          F: FnMut(ConsoleElem<'_>) -> io::Result<ControlFlow<()>> {
+    let mut buffer = vec![0; buffer_size.max(1)];
 
     let mut already_hit_end;
 
@@ -228,14 +249,22 @@ pub fn for_each_console_element<R, F>(mut i: R, mut f: F) -> io::Result<()>
 
     loop {
         match iter.try_get_next_element() {
-            Ok(elem) => f(elem)?,
+            Ok(elem) => if f(elem)?.is_break() {
+                return Ok(());
+            },
             Err(_) => {
                 if already_hit_end {
                     return Ok(());
                 }
                 let slop = iter.slop_bytes();
 
-                buffer.copy_within(last_end-slop.., 0);
+                buffer.copy_within(last_end-slop..last_end, 0);
+
+                // An element larger than the whole buffer can't be finished without more room,
+                // and reading into an empty slice would look like the end of the input
+                if slop == buffer.len() {
+                    buffer.resize(buffer.len() * 2, 0);
+                }
 
                 let amount = i.read(&mut buffer[slop..])?;
                 already_hit_end = amount == 0;
@@ -245,4 +274,120 @@ pub fn for_each_console_element<R, F>(mut i: R, mut f: F) -> io::Result<()>
             }
         }
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Describe each element of the input, read buffer_size bytes at a time. Graphemes are given
+    /// as they are, and anything else is debug formatted
+    fn elements(input: &[u8], buffer_size: usize) -> Vec<String> {
+        let mut elements = Vec::new();
+        for_each_console_element(input, buffer_size, |elem| {
+            elements.push(match elem {
+                ConsoleElem::Grapheme(grapheme) => grapheme.to_string(),
+                other => format!("{other:?}"),
+            });
+            Ok(ControlFlow::Continue(()))
+        }).unwrap();
+        elements
+    }
+
+    /// The bytes an element was read from
+    fn elem_bytes(elem: ConsoleElem) -> Vec<u8> {
+        match elem {
+            ConsoleElem::Newline => b"\n".to_vec(),
+            ConsoleElem::CrlfNewline => b"\r\n".to_vec(),
+            ConsoleElem::CarriageReturn => b"\r".to_vec(),
+            ConsoleElem::Tab => b"\t".to_vec(),
+            ConsoleElem::Backspace => b"\x08".to_vec(),
+            ConsoleElem::OtherNonPrinting(c) => c.to_string().into_bytes(),
+            ConsoleElem::Ansi(s) | ConsoleElem::Grapheme(s) => s.as_bytes().to_vec(),
+            ConsoleElem::NonUTF8Data(byte) => vec![byte],
+        }
+    }
+
+    /// Inputs mixing plain ascii, which takes the fast path, with everything that doesn't
+    const MIXED_INPUTS: &[&[u8]] = &[
+        b"plain ascii log line\n",
+        "cafe\u{301} and e\u{301}\u{302}x".as_bytes(),
+        "ab\u{1F1EC}\u{1F1E7}cd \u{1F468}\u{200D}\u{1F469} ok".as_bytes(),
+        "wide 全角 text".as_bytes(),
+        b"a\x1b[31mred\x1b[0m b\r\nc\rd\te\x08f\x07g",
+        b"no newline at the end",
+        b"bad \xff\xfe bytes",
+        b"z",
+    ];
+
+    #[test]
+    fn plain_text_matches_segmentation() {
+        for input in MIXED_INPUTS {
+            // Only text without control characters is all graphemes
+            let Ok(text) = std::str::from_utf8(input) else { continue };
+            if text.chars().any(|c| c.is_control()) {
+                continue;
+            }
+
+            let expected: Vec<&str> = text.graphemes(true).collect();
+            assert_eq!(elements(input, DEFAULT_READ_BUFFER_SIZE), expected, "for {text:?}");
+        }
+    }
+
+    #[test]
+    fn golden_elements() {
+        assert_eq!(elements("ae\u{301}b".as_bytes(), DEFAULT_READ_BUFFER_SIZE), ["a", "e\u{301}", "b"]);
+        assert_eq!(elements(b"a\x1b[1mb\r\nc\xff", DEFAULT_READ_BUFFER_SIZE), [
+            "a",
+            r#"Ansi("\u{1b}[1m")"#,
+            "b",
+            "CrlfNewline",
+            "c",
+            "NonUTF8Data(255)",
+        ]);
+    }
+
+    #[test]
+    fn output_is_byte_identical_at_any_buffer_size() {
+        for input in MIXED_INPUTS {
+            for buffer_size in [1, 2, 3, 7, DEFAULT_READ_BUFFER_SIZE] {
+                let mut output = Vec::new();
+                for_each_console_element(*input, buffer_size, |elem| {
+                    output.extend(elem_bytes(elem));
+                    Ok(ControlFlow::Continue(()))
+                }).unwrap();
+                assert_eq!(&output, input, "with a buffer of {buffer_size}");
+
+                // The same elements are found however the input is split up
+                assert_eq!(elements(input, buffer_size), elements(input, DEFAULT_READ_BUFFER_SIZE));
+            }
+        }
+    }
+
+    #[test]
+    fn crlf_split_across_reads_is_one_newline() {
+        let mut found = Vec::new();
+        let input = b"a\r".chain(&b"\nb"[..]);
+        for_each_console_element(input, DEFAULT_READ_BUFFER_SIZE, |elem| {
+            found.push(format!("{elem:?}"));
+            Ok(ControlFlow::Continue(()))
+        }).unwrap();
+        assert_eq!(found, [r#"Grapheme("a")"#, "CrlfNewline", r#"Grapheme("b")"#]);
+
+        assert_eq!(elements(b"a\r\nb", 2), ["a", "CrlfNewline", "b"]);
+        assert_eq!(elements(b"a\rb\r", 2), ["a", "CarriageReturn", "b", "CarriageReturn"]);
+        assert_eq!(elements(b"\r\r\n", 1), ["CarriageReturn", "CrlfNewline"]);
+    }
+
+    #[test]
+    fn elements_larger_than_the_buffer_are_kept_whole() {
+        let link = format!("\x1b]8;;https://example.com/{}\x1b\\", "x".repeat(1024));
+        let input = format!("a{link}b");
+
+        for buffer_size in [1, 16, 256] {
+            assert_eq!(elements(input.as_bytes(), buffer_size), ["a", &format!("Ansi({link:?})"), "b"],
+                       "with a buffer of {buffer_size}");
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Named colors, as used in CSS
+use super::Color;
+
+
+/// Each named color, sorted by name
+const NAMED_COLORS: &[(&str, Color)] = &[
+    ("aliceblue", Color::from_rgb(0xF0, 0xF8, 0xFF)),
+    ("antiquewhite", Color::from_rgb(0xFA, 0xEB, 0xD7)),
+    ("aqua", Color::from_rgb(0x00, 0xFF, 0xFF)),
+    ("aquamarine", Color::from_rgb(0x7F, 0xFF, 0xD4)),
+    ("azure", Color::from_rgb(0xF0, 0xFF, 0xFF)),
+    ("beige", Color::from_rgb(0xF5, 0xF5, 0xDC)),
+    ("bisque", Color::from_rgb(0xFF, 0xE4, 0xC4)),
+    ("black", Color::from_rgb(0x00, 0x00, 0x00)),
+    ("blanchedalmond", Color::from_rgb(0xFF, 0xEB, 0xCD)),
+    ("blue", Color::from_rgb(0x00, 0x00, 0xFF)),
+    ("blueviolet", Color::from_rgb(0x8A, 0x2B, 0xE2)),
+    ("brown", Color::from_rgb(0xA5, 0x2A, 0x2A)),
+    ("burlywood", Color::from_rgb(0xDE, 0xB8, 0x87)),
+    ("cadetblue", Color::from_rgb(0x5F, 0x9E, 0xA0)),
+    ("chartreuse", Color::from_rgb(0x7F, 0xFF, 0x00)),
+    ("chocolate", Color::from_rgb(0xD2, 0x69, 0x1E)),
+    ("coral", Color::from_rgb(0xFF, 0x7F, 0x50)),
+    ("cornflowerblue", Color::from_rgb(0x64, 0x95, 0xED)),
+    ("cornsilk", Color::from_rgb(0xFF, 0xF8, 0xDC)),
+    ("crimson", Color::from_rgb(0xDC, 0x14, 0x3C)),
+    ("cyan", Color::from_rgb(0x00, 0xFF, 0xFF)),
+    ("darkblue", Color::from_rgb(0x00, 0x00, 0x8B)),
+    ("darkcyan", Color::from_rgb(0x00, 0x8B, 0x8B)),
+    ("darkgoldenrod", Color::from_rgb(0xB8, 0x86, 0x0B)),
+    ("darkgray", Color::from_rgb(0xA9, 0xA9, 0xA9)),
+    ("darkgreen", Color::from_rgb(0x00, 0x64, 0x00)),
+    ("darkgrey", Color::from_rgb(0xA9, 0xA9, 0xA9)),
+    ("darkkhaki", Color::from_rgb(0xBD, 0xB7, 0x6B)),
+    ("darkmagenta", Color::from_rgb(0x8B, 0x00, 0x8B)),
+    ("darkolivegreen", Color::from_rgb(0x55, 0x6B, 0x2F)),
+    ("darkorange", Color::from_rgb(0xFF, 0x8C, 0x00)),
+    ("darkorchid", Color::from_rgb(0x99, 0x32, 0xCC)),
+    ("darkred", Color::from_rgb(0x8B, 0x00, 0x00)),
+    ("darksalmon", Color::from_rgb(0xE9, 0x96, 0x7A)),
+    ("darkseagreen", Color::from_rgb(0x8F, 0xBC, 0x8F)),
+    ("darkslateblue", Color::from_rgb(0x48, 0x3D, 0x8B)),
+    ("darkslategray", Color::from_rgb(0x2F, 0x4F, 0x4F)),
+    ("darkslategrey", Color::from_rgb(0x2F, 0x4F, 0x4F)),
+    ("darkturquoise", Color::from_rgb(0x00, 0xCE, 0xD1)),
+    ("darkviolet", Color::from_rgb(0x94, 0x00, 0xD3)),
+    ("deeppink", Color::from_rgb(0xFF, 0x14, 0x93)),
+    ("deepskyblue", Color::from_rgb(0x00, 0xBF, 0xFF)),
+    ("dimgray", Color::from_rgb(0x69, 0x69, 0x69)),
+    ("dimgrey", Color::from_rgb(0x69, 0x69, 0x69)),
+    ("dodgerblue", Color::from_rgb(0x1E, 0x90, 0xFF)),
+    ("firebrick", Color::from_rgb(0xB2, 0x22, 0x22)),
+    ("floralwhite", Color::from_rgb(0xFF, 0xFA, 0xF0)),
+    ("forestgreen", Color::from_rgb(0x22, 0x8B, 0x22)),
+    ("fuchsia", Color::from_rgb(0xFF, 0x00, 0xFF)),
+    ("gainsboro", Color::from_rgb(0xDC, 0xDC, 0xDC)),
+    ("ghostwhite", Color::from_rgb(0xF8, 0xF8, 0xFF)),
+    ("gold", Color::from_rgb(0xFF, 0xD7, 0x00)),
+    ("goldenrod", Color::from_rgb(0xDA, 0xA5, 0x20)),
+    ("gray", Color::from_rgb(0x80, 0x80, 0x80)),
+    ("green", Color::from_rgb(0x00, 0x80, 0x00)),
+    ("greenyellow", Color::from_rgb(0xAD, 0xFF, 0x2F)),
+    ("grey", Color::from_rgb(0x80, 0x80, 0x80)),
+    ("honeydew", Color::from_rgb(0xF0, 0xFF, 0xF0)),
+    ("hotpink", Color::from_rgb(0xFF, 0x69, 0xB4)),
+    ("indianred", Color::from_rgb(0xCD, 0x5C, 0x5C)),
+    ("indigo", Color::from_rgb(0x4B, 0x00, 0x82)),
+    ("ivory", Color::from_rgb(0xFF, 0xFF, 0xF0)),
+    ("khaki", Color::from_rgb(0xF0, 0xE6, 0x8C)),
+    ("lavender", Color::from_rgb(0xE6, 0xE6, 0xFA)),
+    ("lavenderblush", Color::from_rgb(0xFF, 0xF0, 0xF5)),
+    ("lawngreen", Color::from_rgb(0x7C, 0xFC, 0x00)),
+    ("lemonchiffon", Color::from_rgb(0xFF, 0xFA, 0xCD)),
+    ("lightblue", Color::from_rgb(0xAD, 0xD8, 0xE6)),
+    ("lightcoral", Color::from_rgb(0xF0, 0x80, 0x80)),
+    ("lightcyan", Color::from_rgb(0xE0, 0xFF, 0xFF)),
+    ("lightgoldenrodyellow", Color::from_rgb(0xFA, 0xFA, 0xD2)),
+    ("lightgray", Color::from_rgb(0xD3, 0xD3, 0xD3)),
+    ("lightgreen", Color::from_rgb(0x90, 0xEE, 0x90)),
+    ("lightgrey", Color::from_rgb(0xD3, 0xD3, 0xD3)),
+    ("lightpink", Color::from_rgb(0xFF, 0xB6, 0xC1)),
+    ("lightsalmon", Color::from_rgb(0xFF, 0xA0, 0x7A)),
+    ("lightseagreen", Color::from_rgb(0x20, 0xB2, 0xAA)),
+    ("lightskyblue", Color::from_rgb(0x87, 0xCE, 0xFA)),
+    ("lightslategray", Color::from_rgb(0x77, 0x88, 0x99)),
+    ("lightslategrey", Color::from_rgb(0x77, 0x88, 0x99)),
+    ("lightsteelblue", Color::from_rgb(0xB0, 0xC4, 0xDE)),
+    ("lightyellow", Color::from_rgb(0xFF, 0xFF, 0xE0)),
+    ("lime", Color::from_rgb(0x00, 0xFF, 0x00)),
+    ("limegreen", Color::from_rgb(0x32, 0xCD, 0x32)),
+    ("linen", Color::from_rgb(0xFA, 0xF0, 0xE6)),
+    ("magenta", Color::from_rgb(0xFF, 0x00, 0xFF)),
+    ("maroon", Color::from_rgb(0x80, 0x00, 0x00)),
+    ("mediumaquamarine", Color::from_rgb(0x66, 0xCD, 0xAA)),
+    ("mediumblue", Color::from_rgb(0x00, 0x00, 0xCD)),
+    ("mediumorchid", Color::from_rgb(0xBA, 0x55, 0xD3)),
+    ("mediumpurple", Color::from_rgb(0x93, 0x70, 0xDB)),
+    ("mediumseagreen", Color::from_rgb(0x3C, 0xB3, 0x71)),
+    ("mediumslateblue", Color::from_rgb(0x7B, 0x68, 0xEE)),
+    ("mediumspringgreen", Color::from_rgb(0x00, 0xFA, 0x9A)),
+    ("mediumturquoise", Color::from_rgb(0x48, 0xD1, 0xCC)),
+    ("mediumvioletred", Color::from_rgb(0xC7, 0x15, 0x85)),
+    ("midnightblue", Color::from_rgb(0x19, 0x19, 0x70)),
+    ("mintcream", Color::from_rgb(0xF5, 0xFF, 0xFA)),
+    ("mistyrose", Color::from_rgb(0xFF, 0xE4, 0xE1)),
+    ("moccasin", Color::from_rgb(0xFF, 0xE4, 0xB5)),
+    ("navajowhite", Color::from_rgb(0xFF, 0xDE, 0xAD)),
+    ("navy", Color::from_rgb(0x00, 0x00, 0x80)),
+    ("oldlace", Color::from_rgb(0xFD, 0xF5, 0xE6)),
+    ("olive", Color::from_rgb(0x80, 0x80, 0x00)),
+    ("olivedrab", Color::from_rgb(0x6B, 0x8E, 0x23)),
+    ("orange", Color::from_rgb(0xFF, 0xA5, 0x00)),
+    ("orangered", Color::from_rgb(0xFF, 0x45, 0x00)),
+    ("orchid", Color::from_rgb(0xDA, 0x70, 0xD6)),
+    ("palegoldenrod", Color::from_rgb(0xEE, 0xE8, 0xAA)),
+    ("palegreen", Color::from_rgb(0x98, 0xFB, 0x98)),
+    ("paleturquoise", Color::from_rgb(0xAF, 0xEE, 0xEE)),
+    ("palevioletred", Color::from_rgb(0xDB, 0x70, 0x93)),
+    ("papayawhip", Color::from_rgb(0xFF, 0xEF, 0xD5)),
+    ("peachpuff", Color::from_rgb(0xFF, 0xDA, 0xB9)),
+    ("peru", Color::from_rgb(0xCD, 0x85, 0x3F)),
+    ("pink", Color::from_rgb(0xFF, 0xC0, 0xCB)),
+    ("plum", Color::from_rgb(0xDD, 0xA0, 0xDD)),
+    ("powderblue", Color::from_rgb(0xB0, 0xE0, 0xE6)),
+    ("purple", Color::from_rgb(0x80, 0x00, 0x80)),
+    ("rebeccapurple", Color::from_rgb(0x66, 0x33, 0x99)),
+    ("red", Color::from_rgb(0xFF, 0x00, 0x00)),
+    ("rosybrown", Color::from_rgb(0xBC, 0x8F, 0x8F)),
+    ("royalblue", Color::from_rgb(0x41, 0x69, 0xE1)),
+    ("saddlebrown", Color::from_rgb(0x8B, 0x45, 0x13)),
+    ("salmon", Color::from_rgb(0xFA, 0x80, 0x72)),
+    ("sandybrown", Color::from_rgb(0xF4, 0xA4, 0x60)),
+    ("seagreen", Color::from_rgb(0x2E, 0x8B, 0x57)),
+    ("seashell", Color::from_rgb(0xFF, 0xF5, 0xEE)),
+    ("sienna", Color::from_rgb(0xA0, 0x52, 0x2D)),
+    ("silver", Color::from_rgb(0xC0, 0xC0, 0xC0)),
+    ("skyblue", Color::from_rgb(0x87, 0xCE, 0xEB)),
+    ("slateblue", Color::from_rgb(0x6A, 0x5A, 0xCD)),
+    ("slategray", Color::from_rgb(0x70, 0x80, 0x90)),
+    ("slategrey", Color::from_rgb(0x70, 0x80, 0x90)),
+    ("snow", Color::from_rgb(0xFF, 0xFA, 0xFA)),
+    ("springgreen", Color::from_rgb(0x00, 0xFF, 0x7F)),
+    ("steelblue", Color::from_rgb(0x46, 0x82, 0xB4)),
+    ("tan", Color::from_rgb(0xD2, 0xB4, 0x8C)),
+    ("teal", Color::from_rgb(0x00, 0x80, 0x80)),
+    ("thistle", Color::from_rgb(0xD8, 0xBF, 0xD8)),
+    ("tomato", Color::from_rgb(0xFF, 0x63, 0x47)),
+    ("turquoise", Color::from_rgb(0x40, 0xE0, 0xD0)),
+    ("violet", Color::from_rgb(0xEE, 0x82, 0xEE)),
+    ("wheat", Color::from_rgb(0xF5, 0xDE, 0xB3)),
+    ("white", Color::from_rgb(0xFF, 0xFF, 0xFF)),
+    ("whitesmoke", Color::from_rgb(0xF5, 0xF5, 0xF5)),
+    ("yellow", Color::from_rgb(0xFF, 0xFF, 0x00)),
+    ("yellowgreen", Color::from_rgb(0x9A, 0xCD, 0x32)),
+];
+
+
+/// Find a named color, ignoring case
+pub fn color_by_name(name: &str) -> Option<Color> {
+    NAMED_COLORS.iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, color)| color)
+}
+
+
+/// Iterate over each named color, in alphabetical order
+pub fn iter_named_colors() -> impl Iterator<Item=(&'static str, Color)> {
+    NAMED_COLORS.iter().copied()
+}
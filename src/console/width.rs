@@ -0,0 +1,61 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use super::ansi_sequence_len;
+
+
+/// Estimate the number of cells a grapheme takes up in the terminal. East Asian characters of
+/// ambiguous width take up either 1 or 2 cells, depending on the terminal's settings.
+///
+/// Really it's up to the terminal how it displays each grapheme, so this is only a best guess. No
+/// grapheme is assumed to take up more than two cells. Zero width graphemes, such as a combining
+/// mark with nothing to combine with or a zero width space, take up no cells, as the terminal draws
+/// them over the previous cell if at all.
+pub fn grapheme_width(grapheme: &str, ambiguous_width: usize) -> usize {
+    let width = if ambiguous_width >= 2 {
+        grapheme.width_cjk()
+    } else {
+        grapheme.width()
+    };
+
+    width.min(2)
+}
+
+
+/// Number of cells taken up by text which may already be colored, skipping over any escape
+/// sequences. Ambiguous width characters are counted as in [grapheme_width].
+pub fn visible_width(mut text: &str, ambiguous_width: usize) -> usize {
+    let mut width = 0;
+    while let Some(grapheme) = text.graphemes(true).next() {
+        match ansi_sequence_len(text) {
+            Some(length) => text = &text[length..],
+            None => {
+                width += grapheme_width(grapheme, ambiguous_width);
+                text = &text[grapheme.len()..];
+            },
+        }
+    }
+    width
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visible_width_skips_escapes() {
+        assert_eq!(visible_width("\u{1B}[31mred\u{1B}[0m", 1), 3);
+        assert_eq!(visible_width("全角", 1), 4);
+        assert_eq!(visible_width("e\u{301}", 1), 1);
+    }
+
+    #[test]
+    fn visible_width_follows_ambiguous_width() {
+        // Circled digits are ambiguous, so are wide in terminals set up for East Asian text
+        assert_eq!(visible_width("①②", 1), 2);
+        assert_eq!(visible_width("①②", 2), 4);
+        assert_eq!(grapheme_width("①", 2), 2);
+        assert_eq!(grapheme_width("a", 2), 1);
+    }
+}
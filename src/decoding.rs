@@ -0,0 +1,131 @@
+//! Transcoding of input text that isn't UTF-8
+use std::io;
+use std::io::Read;
+use anyhow::{anyhow, Result};
+use encoding_rs::{CoderResult, Decoder, Encoding, UTF_8};
+
+
+/// Look up an encoding by any of its standard labels, such as "latin1" or "shift_jis"
+pub fn encoding_by_label(label: &str) -> Result<&'static Encoding> {
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| anyhow!("Unknown input encoding {label}!"))
+}
+
+
+/// Wrap an input so that it is transcoded from the given encoding to UTF-8. UTF-8 input is left
+/// untouched, so that invalid bytes are forwarded exactly as they were.
+pub fn decode_input(input: Box<dyn Read>, encoding: &'static Encoding) -> Box<dyn Read> {
+    if encoding == UTF_8 {
+        input
+    } else {
+        Box::new(DecodingReader::new(input, encoding))
+    }
+}
+
+
+/// Reader which transcodes text from any encoding supported by encoding_rs into UTF-8
+pub struct DecodingReader<R> {
+    inner: R,
+    decoder: Decoder,
+    in_buffer: Vec<u8>,
+    in_range: (usize, usize),
+    out_buffer: Vec<u8>,
+    out_range: (usize, usize),
+    hit_end: bool,
+    finished: bool,
+}
+
+
+impl<R> DecodingReader<R> {
+    pub fn new(inner: R, encoding: &'static Encoding) -> Self {
+        Self {
+            inner,
+            decoder: encoding.new_decoder(),
+            in_buffer: vec![0; 4096],
+            in_range: (0, 0),
+            out_buffer: vec![0; 4096],
+            out_range: (0, 0),
+            hit_end: false,
+            finished: false,
+        }
+    }
+}
+
+
+impl<R: Read> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // Hand out any already decoded bytes first
+            let (start, end) = self.out_range;
+            if start < end {
+                let amount = buf.len().min(end - start);
+                buf[..amount].copy_from_slice(&self.out_buffer[start..start+amount]);
+                self.out_range.0 += amount;
+                return Ok(amount);
+            }
+
+            if self.finished {
+                return Ok(0);
+            }
+
+            // Refill the input buffer once it has been fully decoded
+            if self.in_range.0 == self.in_range.1 && !self.hit_end {
+                let amount = self.inner.read(&mut self.in_buffer)?;
+                self.in_range = (0, amount);
+                self.hit_end = amount == 0;
+            }
+
+            let (start, end) = self.in_range;
+            let (result, read, written, _) = self.decoder.decode_to_utf8(
+                &self.in_buffer[start..end],
+                &mut self.out_buffer,
+                self.hit_end);
+
+            self.in_range.0 += read;
+            self.out_range = (0, written);
+
+            if self.hit_end && result == CoderResult::InputEmpty {
+                self.finished = true;
+            }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Read all of an input through a decoder, a few bytes at a time
+    fn decode(input: &'static [u8], label: &str) -> Vec<u8> {
+        let mut reader = decode_input(Box::new(input), encoding_by_label(label).unwrap());
+        let mut output = Vec::new();
+        let mut buf = [0; 3];
+        loop {
+            let amount = reader.read(&mut buf).unwrap();
+            if amount == 0 {
+                return output;
+            }
+            output.extend_from_slice(&buf[..amount]);
+        }
+    }
+
+    #[test]
+    fn transcodes_to_utf8() {
+        assert_eq!(decode(b"caf\xE9 na\xEFve", "latin1"), "café naïve".as_bytes());
+        assert_eq!(decode(b"\x93\xFA\x96\x7B", "shift_jis"), "日本".as_bytes());
+    }
+
+    #[test]
+    fn utf8_is_left_alone() {
+        // Invalid bytes are forwarded as they are, rather than replaced
+        assert_eq!(decode(b"ok \xFF", "utf-8"), b"ok \xFF");
+    }
+
+    #[test]
+    fn unknown_labels_are_rejected() {
+        assert!(encoding_by_label("not-an-encoding").is_err());
+        assert!(encoding_by_label("Latin1").is_ok());
+    }
+}
@@ -0,0 +1,137 @@
+//! Elastic tabstops, where each tab-separated column is padded to fit its widest cell
+use std::io;
+use std::io::{BufRead, BufReader, Read};
+
+use crate::console::visible_width;
+
+
+/// Reader which replaces tabs with spaces, so that tab-separated columns line up. Lines are
+/// processed in blocks, with each column as wide as its widest cell in the block, rounded up to the
+/// next tab stop.
+pub struct ElasticTabsReader<R> {
+    inner: BufReader<R>,
+    block_lines: usize,
+    tab_size: usize,
+    /// Number of cells taken up by East Asian characters of ambiguous width
+    ambiguous_width: usize,
+    block: Vec<u8>,
+    consumed: usize,
+}
+
+
+impl<R: Read> ElasticTabsReader<R> {
+    pub fn new(inner: R, block_lines: usize, tab_size: usize, ambiguous_width: usize) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            block_lines,
+            tab_size,
+            ambiguous_width,
+            block: Vec::new(),
+            consumed: 0,
+        }
+    }
+
+    /// Read and align the next block of lines. An empty block means the input has ended.
+    fn fill_block(&mut self) -> io::Result<()> {
+        let mut lines = Vec::new();
+        while lines.len() < self.block_lines {
+            let mut line = Vec::new();
+            if self.inner.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            lines.push(line);
+        }
+
+        self.block = align_block(&lines, self.tab_size, self.ambiguous_width);
+        self.consumed = 0;
+        Ok(())
+    }
+}
+
+
+impl<R: Read> Read for ElasticTabsReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.consumed == self.block.len() {
+            self.fill_block()?;
+        }
+
+        let remaining = &self.block[self.consumed..];
+        let amount = remaining.len().min(buf.len());
+        buf[..amount].copy_from_slice(&remaining[..amount]);
+        self.consumed += amount;
+        Ok(amount)
+    }
+}
+
+
+/// Replace the tabs in a block of lines with enough spaces to line up each column. Lines which
+/// aren't valid UTF-8 are left alone.
+fn align_block(lines: &[Vec<u8>], tab_size: usize, ambiguous_width: usize) -> Vec<u8> {
+    let lines: Vec<Result<Vec<&str>, &[u8]>> = lines.iter()
+        .map(|line| match std::str::from_utf8(line) {
+            Ok(line) => Ok(line.split('\t').collect()),
+            Err(_) => Err(line.as_slice()),
+        })
+        .collect();
+
+    // The last cell of each line isn't followed by a tab, so doesn't affect the column widths
+    let mut widths: Vec<usize> = Vec::new();
+    for cells in lines.iter().flatten() {
+        for (i, cell) in cells.iter().enumerate().take(cells.len() - 1) {
+            if i == widths.len() {
+                widths.push(0);
+            }
+            widths[i] = widths[i].max(visible_width(cell, ambiguous_width));
+        }
+    }
+
+    // Each column ends where a tab after its widest cell would
+    let tab_size = tab_size.max(1);
+    let widths: Vec<usize> = widths.into_iter()
+        .map(|width| (width / tab_size + 1) * tab_size)
+        .collect();
+
+    let mut block = Vec::new();
+    for line in lines {
+        match line {
+            Ok(cells) => {
+                for (i, cell) in cells.iter().enumerate() {
+                    block.extend_from_slice(cell.as_bytes());
+                    if i + 1 < cells.len() {
+                        let padding = widths[i] - visible_width(cell, ambiguous_width);
+                        block.resize(block.len() + padding, b' ');
+                    }
+                }
+            },
+            Err(raw) => block.extend_from_slice(raw),
+        }
+    }
+    block
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn align(input: &str, tab_size: usize, ambiguous_width: usize) -> String {
+        let mut output = String::new();
+        ElasticTabsReader::new(input.as_bytes(), 100, tab_size, ambiguous_width)
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn columns_line_up() {
+        assert_eq!(align("a\tb\nlonger\tc\n", 4, 1), "a       b\nlonger  c\n");
+        assert_eq!(align("全角\tb\nx\tc\n", 4, 1), "全角    b\nx       c\n");
+        assert_eq!(align("\u{1B}[31mred\u{1B}[0m\tb\n", 4, 1), "\u{1B}[31mred\u{1B}[0m b\n");
+    }
+
+    #[test]
+    fn ambiguous_width_is_followed() {
+        assert_eq!(align("①②③\tb\nx\tc\n", 4, 1), "①②③ b\nx   c\n");
+        assert_eq!(align("①②③\tb\nx\tc\n", 4, 2), "①②③  b\nx       c\n");
+    }
+}
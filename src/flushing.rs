@@ -0,0 +1,119 @@
+//! Output which is flushed in the background, for live input such as `tail -f`
+use std::io;
+use std::io::{BufWriter, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+
+/// State shared between a [TimedFlushWriter] and its flushing thread
+struct State<W: Write> {
+    writer: BufWriter<W>,
+    /// Time of the first write since the last flush, if anything is waiting to be flushed
+    dirty_since: Option<Instant>,
+    closed: bool,
+}
+
+
+struct Shared<W: Write> {
+    state: Mutex<State<W>>,
+    wake: Condvar,
+}
+
+
+impl<W: Write> Shared<W> {
+    fn lock(&self) -> MutexGuard<'_, State<W>> {
+        // A panic while holding the lock can't leave the writer in a worse state than any other
+        // failed write, so carry on regardless
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+
+/// Buffered writer which is also flushed once output has been waiting for a given time. This way
+/// partial lines still show up promptly, while full bursts of output are buffered as usual.
+pub struct TimedFlushWriter<W: Write + Send + 'static> {
+    shared: Arc<Shared<W>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+
+impl<W: Write + Send + 'static> TimedFlushWriter<W> {
+    pub fn new(inner: W, timeout: Duration) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                writer: BufWriter::new(inner),
+                dirty_since: None,
+                closed: false,
+            }),
+            wake: Condvar::new(),
+        });
+
+        let thread = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || flush_periodically(&shared, timeout))
+        };
+
+        Self {
+            shared,
+            thread: Some(thread),
+        }
+    }
+}
+
+
+/// Body of the flushing thread, which runs until the writer is closed
+fn flush_periodically<W: Write>(shared: &Shared<W>, timeout: Duration) {
+    let mut state = shared.lock();
+    while !state.closed {
+        let dirty_since = state.dirty_since;
+        state = match dirty_since {
+            Some(since) => {
+                let elapsed = since.elapsed();
+                if elapsed >= timeout {
+                    // Errors will show up again on the next write, where they can be reported
+                    let _ = state.writer.flush();
+                    state.dirty_since = None;
+                    state
+                } else {
+                    shared.wake.wait_timeout(state, timeout - elapsed)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            },
+            None => shared.wake.wait(state).unwrap_or_else(|e| e.into_inner()),
+        };
+    }
+}
+
+
+impl<W: Write + Send + 'static> Write for TimedFlushWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.shared.lock();
+        let amount = state.writer.write(buf)?;
+        if state.dirty_since.is_none() {
+            state.dirty_since = Some(Instant::now());
+            self.shared.wake.notify_one();
+        }
+        Ok(amount)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut state = self.shared.lock();
+        state.dirty_since = None;
+        state.writer.flush()
+    }
+}
+
+
+impl<W: Write + Send + 'static> Drop for TimedFlushWriter<W> {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.wake.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        // The BufWriter flushes itself once the last reference is dropped
+    }
+}
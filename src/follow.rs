@@ -0,0 +1,95 @@
+//! Following files as they grow, like `tail -f`
+use std::io;
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+
+/// How long to wait before checking a file for new data again
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Set once the user asks us to stop, with Ctrl-C
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+
+/// Reader which waits for more data at the end of its input, rather than ending. The input only
+/// ends once interrupted. Ctrl-C is caught from the first read until the reader is dropped, so it
+/// still kills the process as usual while any earlier input is read.
+pub struct FollowReader<R> {
+    inner: R,
+    interrupt: Option<InterruptGuard>,
+}
+
+
+impl<R: Read> FollowReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            interrupt: None,
+        }
+    }
+}
+
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.interrupt.get_or_insert_with(catch_interrupt);
+        loop {
+            let amount = self.inner.read(buf)?;
+            if amount > 0 || buf.is_empty() || INTERRUPTED.load(Ordering::Relaxed) {
+                return Ok(amount);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+
+/// Restores the previous handling of Ctrl-C when dropped
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+
+#[cfg(unix)]
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // SAFETY: the previous handler was installed before, so is still valid
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+        }
+    }
+}
+
+
+/// Make Ctrl-C end any followed input or animation, rather than killing the process, so that the
+/// output is flushed and we exit as usual. This lasts until the returned guard is dropped
+#[cfg(unix)]
+pub fn catch_interrupt() -> InterruptGuard {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    let previous = unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as *const () as libc::sighandler_t)
+    };
+    InterruptGuard {
+        previous,
+    }
+}
+
+
+#[cfg(not(unix))]
+pub fn catch_interrupt() -> InterruptGuard {
+    // Ctrl-C falls back to ending the process
+    InterruptGuard {}
+}
+
+
+/// True once Ctrl-C has been pressed, after [catch_interrupt] is called
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
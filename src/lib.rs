@@ -0,0 +1,17 @@
+//! Colorizing text streams for the terminal, with flags, images and other patterns. This is the
+//! library behind the prettycat command.
+pub mod stream_colors;
+pub mod console;
+pub mod presets;
+pub mod flushing;
+pub mod sinks;
+pub mod terminal;
+pub mod elastic;
+pub mod registry;
+pub mod manifest;
+pub mod follow;
+pub mod animate;
+#[cfg(feature = "encoding")]
+pub mod decoding;
+#[cfg(feature = "watch")]
+pub mod watch;
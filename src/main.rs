@@ -1,21 +1,40 @@
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::io::{BufReader, BufWriter, Read, stderr, stdin, stdout, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind, IsTerminal, Read, stderr, stdin, stdout, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
 use std::str::FromStr;
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
 
 use clap::{Args, Parser};
-use image::{open, RgbImage};
-use image::imageops::{FilterType, resize};
-
-use crate::console::RESET_CODE;
-use crate::console::Color;
-use crate::presets::{default_flag_preset, flag_by_name, iter_flag_presets};
-use crate::stream_colors::{ColorizerConfig, Flag, Image, Noop, StreamColorizer};
-
-mod stream_colors;
-mod console;
-mod presets;
+use color_quant::NeuQuant;
+use image::{open, Rgb, RgbImage, Rgba, RgbaImage};
+use image::imageops::{crop_imm, FilterType, resize};
+use rand::{thread_rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use prettycat::console::{iter_named_colors, DEFAULT_READ_BUFFER_SIZE, RESET_CODE};
+use prettycat::elastic::ElasticTabsReader;
+use prettycat::animate::{load_gif_frames, play_animation};
+use prettycat::follow::{catch_interrupt, FollowReader};
+use prettycat::flushing::TimedFlushWriter;
+use prettycat::sinks::{json_string, ColorMode, ColorTarget, NewlineStyle, OutputFormat};
+use prettycat::terminal::{cell_aspect_ratio, color_mode_from_env, colorfgbg_background, probe_truecolor, query_background_color};
+use prettycat::console::Color;
+use prettycat::registry::{ModeParams, ModeRegistry};
+use prettycat::manifest::{Manifest, ManifestRule};
+use prettycat::presets::{default_flag_preset, flag_by_name, iter_flag_presets, load_user_presets, random_flag_preset};
+use prettycat::stream_colors::{BlendSpace, CarriageReturnMode, Category, CharCategory, ColorizerConfig, Flag, Gradient, GradientDirection, Grid, HalfBlockImage, Image, Noop, PaletteMetric, RadialGradient, Rainbow, RunningPalette, StreamColorizer, VerticalGradient, WideWrapMode};
+#[cfg(feature = "encoding")]
+use prettycat::decoding;
+#[cfg(feature = "watch")]
+use prettycat::watch::{watch_presets, WatchedFlag};
 
 
 #[derive(Parser, Debug)]
@@ -28,14 +47,352 @@ struct Opt {
     #[arg(default_values = ["-"])]
     files: Vec<PathBuf>,
 
-    /// Disallow the use of 24-bit rgb ANSI codes. This may improve support on terminals
-    /// that don't support these codes. NOTE: Color reproduction is very poor at the moment!
+    /// Name shown in place of a file name when reading from stdin, such as in error messages
+    #[arg(long, default_value="standard input", value_name="LABEL")]
+    stdin_name: PathBuf,
+
+    /// Keep reading the last file once it ends, colorizing new data as it is appended, like
+    /// `tail -f`. Stop with Ctrl-C
+    #[arg(short, long, conflicts_with="loop_presets")]
+    follow: bool,
+
+    /// A toml file choosing the colorizer for each file by its path, as a list of [[rule]] tables
+    /// with a path pattern, and either a preset flag or a mode with params. The first matching
+    /// rule is used, while files matching no rule use the usual colorizer
+    #[arg(long)]
+    manifest: Option<PathBuf>,
+
+    /// Write output to the given file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Refuse to write to the --output file if it already exists
+    #[arg(long)]
+    no_clobber: bool,
+
+    /// Rename an existing --output file to "file~" before writing over it
+    #[arg(long, conflicts_with="no_clobber")]
+    backup: bool,
+
+    /// Deprecated - equivalent to --color-mode palette8
     #[arg(short, long)]
     disable_rgb24: bool,
 
-    /// Override terminal width with the given value
+    /// Kind of color codes to use, either "truecolor" for 24-bit rgb, "palette256" for the xterm
+    /// 256 color palette, or "palette8" for the 8 basic colors, which may improve support on older
+    /// terminals. NOTE: Color reproduction is very poor with palette8 at the moment! Defaults to
+    /// a guess from the COLORTERM and TERM environment variables, or truecolor if neither is set
+    #[arg(long)]
+    color_mode: Option<ColorMode>,
+
+    /// Part of each cell to color: "foreground" for the text, "background" for the cell behind
+    /// it, or "both" to turn text such as ascii art into solid blocks of color
+    #[arg(long, default_value="foreground")]
+    target: ColorTarget,
+
+    /// Snap every color to the nearest in a palette taken from --palette-from, before outputting
+    /// it as usual. The terminal's own palette can't be changed, so this only limits which colors
+    /// are used, and is best combined with truecolor output
+    #[arg(long, requires="palette_from")]
+    quantize_to_palette: bool,
+
+    /// Image to take the palette for --quantize-to-palette from, using its most prominent colors
+    #[arg(long, value_name="IMAGE", requires="quantize_to_palette")]
+    palette_from: Option<PathBuf>,
+
+    /// Output at most this many distinct colors. The first N colors are used as they are, and any
+    /// others are replaced with the nearest of those
+    #[arg(long, value_name="N", value_parser=clap::value_parser!(u64).range(1..))]
+    max_colors: Option<u64>,
+
+    /// Number of colors to take from the --palette-from image
+    #[arg(long, default_value="16", value_parser=clap::value_parser!(u16).range(2..=256))]
+    palette_size: u16,
+
+    /// How --quantize-to-palette finds the nearest color: "rgb" for plain distance, "weighted"
+    /// (the default) to weight each channel by how sensitive vision is to it, or "delta-e" for
+    /// perceptual distance in CIELAB, which is the most accurate but slowest
+    #[arg(long, default_value="weighted", requires="quantize_to_palette")]
+    palette_metric: PaletteMetric,
+
+    /// When to output colors: "always", "never", or "auto" to only output colors when writing to
+    /// a terminal. Defaults to always, unless the NO_COLOR environment variable is set
+    #[arg(long)]
+    color_when: Option<ColorWhen>,
+
+    /// Ask the terminal whether it supports 24-bit color, rather than assuming it does. If the
+    /// terminal doesn't answer, 24-bit color is used as usual. Only done when writing to a terminal
+    #[arg(long, conflicts_with_all=["disable_rgb24", "color_mode"])]
+    probe_terminal: bool,
+
+    /// Guess the color mode from the COLORTERM and TERM environment variables when it isn't given.
+    /// This is the default
+    #[arg(long, overrides_with="no_color_detect")]
+    color_detect: bool,
+
+    /// Always default to truecolor rather than guessing the color mode from the environment
+    #[arg(long, overrides_with="color_detect")]
+    no_color_detect: bool,
+
+    /// Override terminal width with the given value. This may be larger than the real terminal,
+    /// in which case colors are computed for the given width and the terminal wraps lines itself
     #[arg(short, long)]
     width_override: Option<usize>,
+
+    /// Indent lines which a long line wraps onto by this many colored spaces, so that they stand
+    /// out from new lines
+    #[arg(long, value_name="N", default_value="0")]
+    wrap_indent: usize,
+
+    /// Drop carriage returns from the input, rather than moving back to the start of the line.
+    /// Either "reset" to still color the rest of the line as if starting from the first column,
+    /// or "continue" to color it as if the carriage return never existed
+    #[arg(long, num_args=0..=1, default_missing_value="reset")]
+    strip_cr: Option<StripCr>,
+
+    /// First line of each file to output, starting from 1. Earlier lines are not shown, but colors
+    /// still match those of the full file
+    #[arg(long, value_parser=clap::value_parser!(u64).range(1..))]
+    from_line: Option<u64>,
+
+    /// Last line of each file to output, inclusive. The rest of the file is not read
+    #[arg(long, value_parser=clap::value_parser!(u64).range(1..))]
+    to_line: Option<u64>,
+
+    /// Color the lines selected by --from-line as if they were at the top of the file
+    #[arg(long)]
+    recolor_range: bool,
+
+    /// Only sample colors every N cells, so that blocks of NxN cells share a color, for a blocky
+    /// retro look
+    #[arg(long, default_value="1", value_parser=clap::value_parser!(u32).range(1..))]
+    sample_rate: u32,
+
+    /// Swap the rows and columns of the color pattern, turning horizontal stripes vertical and
+    /// vice versa. Works for any colorizer, including images and gradients
+    #[arg(long)]
+    transpose: bool,
+
+    /// Repeat the flag or image pattern every N cells, rather than fitting it to the terminal
+    /// width. For flags this sets the frequency, so it can't be used with --hf or --vf. For
+    /// images this replaces --image-width, with the image tiled every N cells
+    #[arg(long, value_name="N", conflicts_with_all=["hf", "vf", "frequency_auto"], value_parser=clap::value_parser!(u64).range(1..))]
+    pattern_width: Option<u64>,
+
+    /// Color as if the output started at the given column and row of the screen, given as "x,y",
+    /// so that it lines up with surrounding output
+    #[arg(long, value_name="X,Y")]
+    at_position: Option<Position>,
+
+    /// Print the color given to the cell at column X and row Y as a hex code, then exit without
+    /// reading any input. Useful for checking what a colorizer does
+    #[arg(long, value_name="X,Y")]
+    color_at: Option<Position>,
+
+    /// Text to output between consecutive files, colorized like the files themselves. Escapes
+    /// such as "\n" are supported, and "rule" draws a horizontal line across the terminal
+    #[arg(long, value_name="TEXT")]
+    between_files: Option<String>,
+
+    /// Replace tabs with spaces so that tab-separated columns line up, with each column as wide as
+    /// its widest cell. Lines are aligned in blocks, so very long inputs can be shown as they are
+    /// read
+    #[arg(long)]
+    elastic_tabs: bool,
+
+    /// Number of lines aligned together by --elastic-tabs
+    #[arg(long, default_value="1000", requires="elastic_tabs", value_parser=clap::value_parser!(u64).range(1..))]
+    elastic_block_lines: u64,
+
+    /// Number of cells taken up by East Asian characters of ambiguous width. Terminals set up for
+    /// CJK locales often show these as 2 cells wide
+    #[arg(long, default_value="1", value_parser=clap::value_parser!(u8).range(1..=2))]
+    ambiguous_width: u8,
+
+    /// How to handle a wide character which doesn't fit at the end of a line: "pad" leaves the
+    /// terminal to move it onto the next line, while "push" fills the rest of the line with
+    /// colored spaces first
+    #[arg(long, default_value="pad")]
+    truncate_wide: WideWrapMode,
+
+    /// Color space to blend between colors in: "srgb", or "oklab" which avoids washed out grey
+    /// midpoints between very different colors, at a small cost in speed
+    #[arg(long, default_value="srgb")]
+    blend_space: BlendSpace,
+
+    /// Display each tab as the given text, such as "→", padded with spaces up to the next tab stop
+    #[arg(long, value_name="TEXT")]
+    replace_tabs_with: Option<String>,
+
+    /// Display each space as a visible character, "·" by default
+    #[arg(long, num_args=0..=1, default_missing_value="·", value_name="CHAR")]
+    show_spaces: Option<char>,
+
+    /// Convert overstrike sequences, as used by man pages, into real bold and underlined text
+    #[arg(long)]
+    render_overstrike: bool,
+
+    /// After a reset code in the input, wait for the next character before applying our color
+    /// again. Avoids a burst of color codes from input which resets frequently, such as some
+    /// progress bars
+    #[arg(long)]
+    inherit_color_across_ansi: bool,
+
+    /// Treat a code erasing the whole line or screen as moving back to the start of the line, for
+    /// input which redraws a line without a carriage return
+    #[arg(long)]
+    erase_resets_column: bool,
+
+    /// Run the usual text processing, such as wrapping and --from-line, but output plain text with
+    /// no colors. Any ansi codes in the input are stripped as well
+    #[arg(long, alias="strip-ansi-output")]
+    no_color: bool,
+
+    /// Format to write colorized text in: "ansi" for a terminal, "html" for a <pre> block of
+    /// colored spans, "svg" for an image, or "jsonl" for one json object per grapheme. Each input
+    /// file produces its own html block or svg image
+    #[arg(long, default_value="ansi")]
+    output_format: OutputFormat,
+
+    /// Line terminator to write for each newline, regardless of the input: "lf", "crlf" or "cr".
+    /// With the default of "lf", windows line endings in the input are kept as they are
+    #[arg(long, default_value="lf")]
+    newline_style: NewlineStyle,
+
+    /// Flush output once it has been waiting this many milliseconds, even mid-line. This keeps
+    /// live input such as `tail -f` responsive. Only used when writing to a terminal
+    #[arg(long, value_name="MS")]
+    flush_timeout: Option<u64>,
+
+    /// Number of bytes of input to read at a time. Larger buffers mean fewer reads for big files
+    #[arg(long, value_name="BYTES", default_value="8192", value_parser=clap::value_parser!(u64).range(1..))]
+    read_buffer_size: u64,
+
+    /// Once done, print a summary to stderr of the bytes, lines and graphemes read, the number of
+    /// distinct colors output, and how many escape sequences were forwarded or discarded
+    #[arg(long)]
+    stats: bool,
+
+    /// Start the output with a "#" comment line giving the command line, color mode and width
+    /// used, so that a saved transcript records how to reproduce it
+    #[arg(long)]
+    emit_palette_comment: bool,
+
+    /// Format of error messages written to stderr, either "human" or "json". JSON errors are
+    /// written one per line, as {"error": ..., "path": ...}
+    #[arg(long, default_value="human")]
+    error_format: ErrorFormat,
+
+    /// Encoding of the input files, such as "latin1" or "shift_jis". Input is transcoded to UTF-8
+    /// before being colorized. Defaults to UTF-8
+    #[cfg(feature = "encoding")]
+    #[arg(long)]
+    input_encoding: Option<String>,
+}
+
+
+/// A cell position, parsed from "x,y"
+#[derive(Debug, Clone, Copy)]
+struct Position(usize, usize);
+
+
+impl FromStr for Position {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some((x, y)) = s.split_once(',')
+            else {
+                return Err(anyhow!("Expected x,y, got {s}"));
+            };
+
+        let x = x.trim().parse().map_err(|e| anyhow!("Invalid column {x}: {e}"))?;
+        let y = y.trim().parse().map_err(|e| anyhow!("Invalid row {y}: {e}"))?;
+        Ok(Self(x, y))
+    }
+}
+
+
+/// When to output colors, for --color-when
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorWhen {
+    Always,
+    Never,
+    Auto,
+}
+
+
+impl FromStr for ColorWhen {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("always") {
+            Ok(Self::Always)
+        } else if s.eq_ignore_ascii_case("never") {
+            Ok(Self::Never)
+        } else if s.eq_ignore_ascii_case("auto") {
+            Ok(Self::Auto)
+        } else {
+            Err(anyhow!("Invalid value {s}! - Expected always, never or auto"))
+        }
+    }
+}
+
+
+/// Format used when reporting errors
+#[derive(Debug, Clone, Copy)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
+
+
+impl FromStr for ErrorFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("human") {
+            Ok(Self::Human)
+        } else if s.eq_ignore_ascii_case("json") {
+            Ok(Self::Json)
+        } else {
+            Err(anyhow!("Invalid error format {s}! - Expected \"human\" or \"json\""))
+        }
+    }
+}
+
+
+/// Ways of stripping carriage returns, for --strip-cr
+#[derive(Debug, Clone, Copy)]
+enum StripCr {
+    Reset,
+    Continue,
+}
+
+
+impl FromStr for StripCr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("reset") {
+            Ok(Self::Reset)
+        } else if s.eq_ignore_ascii_case("continue") {
+            Ok(Self::Continue)
+        } else {
+            Err(anyhow!("Invalid value {s}! - Expected \"reset\" or \"continue\""))
+        }
+    }
+}
+
+
+impl StripCr {
+    /// How carriage returns are handled, given the --strip-cr option if any
+    fn carriage_return_mode(strip_cr: Option<StripCr>) -> CarriageReturnMode {
+        match strip_cr {
+            None => CarriageReturnMode::Forward,
+            Some(StripCr::Reset) => CarriageReturnMode::StripAndReset,
+            Some(StripCr::Continue) => CarriageReturnMode::Strip,
+        }
+    }
 }
 
 
@@ -51,6 +408,44 @@ struct ColorizerOpts {
 
     #[clap(flatten)]
     image: ImageOpts,
+
+    #[clap(flatten)]
+    category: CategoryOpts,
+
+    #[clap(flatten)]
+    gradient: GradientOpts,
+
+    #[clap(flatten)]
+    radial: RadialOpts,
+
+    #[clap(flatten)]
+    rainbow: RainbowOpts,
+
+    /// Select a colorizer by name, configured using --mode-param. Overrides the other colorizer
+    /// options. View all modes using --modes
+    #[arg(long)]
+    mode: Option<String>,
+
+    /// A key=value parameter for the --mode colorizer. May be given multiple times
+    #[arg(long, requires="mode")]
+    mode_param: Vec<ModeParam>,
+
+    /// List all modes, along with their parameters
+    #[arg(long)]
+    modes: bool,
+
+    /// List the color names which may be used in place of hex codes. Optionally only lists names
+    /// containing the given text
+    #[arg(long, num_args=0..=1, default_missing_value="", value_name="FILTER")]
+    list_colors: Option<String>,
+
+    /// Don't print informational messages, such as which random preset was chosen
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Debugging aid - overrides the colorizer with a checkerboard showing the expected cell grid
+    #[arg(long, hide = true)]
+    grid: bool,
 }
 
 
@@ -59,6 +454,25 @@ impl ColorizerOpts {
     /// possible. Returns Ok(false) if no such behaviour is possible, otherwise Ok(true) or any error
     /// is returned.
     fn try_early_exit(&self) -> Result<bool> {
+        if self.modes {
+            let mut stdout = stdout().lock();
+            for (name, description) in ModeRegistry::default().iter_modes() {
+                writeln!(stdout, "{name:<10} | {description}")?;
+            }
+            return Ok(true);
+        }
+
+        if let Some(filter) = &self.list_colors {
+            let filter = filter.to_ascii_lowercase();
+            let mut stdout = stdout().lock();
+            for (name, color) in iter_named_colors().filter(|(name, _)| name.contains(&filter)) {
+                write!(stdout, "{name:<20} | ")?;
+                color.write_as_24bit_ansi(&mut stdout)?;
+                writeln!(stdout, "\u{2588}\u{2588}\u{2588}\u{2588}{RESET_CODE} {color}")?;
+            }
+            return Ok(true);
+        }
+
         self.flag.maybe_print_presets()
     }
 
@@ -67,18 +481,52 @@ impl ColorizerOpts {
     /// Config is *not* passed to the colorizer, this must happen when calling copy_colorized.
     /// Instead, config is used to prepare certain resources such as resizing images beforehand
     fn try_into_colorizer(self, config: &ColorizerConfig) -> Result<SomeColorizer> {
+        if self.grid {
+            return Ok(SomeColorizer::Grid(Grid {
+                even: Color::from_rgb(0xFF, 0x00, 0xFF),
+                odd: Color::from_rgb(0x00, 0xFF, 0x00),
+            }));
+        }
+
+        if let Some(mode) = &self.mode {
+            let mut params = ModeParams::default();
+            for ModeParam(key, value) in self.mode_param {
+                params.insert(key, value);
+            }
+            return Ok(SomeColorizer::Dyn(ModeRegistry::default().build(mode, &params, config)?));
+        }
+
+        // Frequency options still apply to the default flag
+        let default_flag = self.flag.build(Flag::from_preset(&default_flag_preset()), config);
+
         self.noop.into_colorizer()
-            .or(self.flag.into_colorizer())
+            .or(self.flag.into_colorizer(config, self.quiet))
             .or(self.image.into_colorizer(config))
+            .or(self.category.into_colorizer())
+            .or(self.gradient.into_colorizer(config, self.quiet))
+            .or(self.radial.into_colorizer(config))
+            .or(self.rainbow.into_colorizer())
 
-            .unwrap_or_else(|| {
-                Ok(SomeColorizer::Flag(Flag {
-                    hf: 0.05,
-                    vf: 0.05,
-                    stripes: default_flag_preset().stripes.to_vec(),
-                    deadzone: 0.6,
-                }))
-            })
+            .unwrap_or_else(|| Ok(SomeColorizer::Flag(default_flag)))
+    }
+}
+
+
+/// A parameter for --mode, parsed from "key=value"
+#[derive(Debug, Clone)]
+struct ModeParam(String, String);
+
+
+impl FromStr for ModeParam {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some((key, value)) = s.split_once('=')
+            else {
+                return Err(anyhow!("Expected key=value, got {s}"));
+            };
+
+        Ok(Self(key.trim().to_string(), value.trim().to_string()))
     }
 }
 
@@ -104,9 +552,11 @@ impl NoopOpts {
 
 
 /// Options for the striped flag colorizer
-#[derive(Debug, Args)]
+#[derive(Debug, Clone, Args)]
 struct FlagOpts {
-    /// Output a flag from a preset. View all presets using --presets
+    /// Output a flag from a preset. View all presets using --presets. Frequencies and deadzone
+    /// may be given after the name, as in "pride@hf=0.1,vf=0", taking precedence over --hf,
+    /// --vf and --deadzone
     #[arg(long)]
     flag: Option<String>,
 
@@ -114,22 +564,89 @@ struct FlagOpts {
     #[arg(long)]
     presets: bool,
 
+    /// Load extra presets from a file. Each line is either "name, alias, ... = color, color * weight,
+    /// ...", an "@include path" of another preset file, or a "#" comment. Files ending in ".toml"
+    /// instead hold [[preset]] tables with a name, a list of stripes, and optionally aliases and
+    /// weights
+    #[arg(long)]
+    preset_file: Option<PathBuf>,
+
+    /// Reload the preset file whenever it changes, so that edits to the preset chosen by --flag
+    /// take effect from the next line
+    #[cfg(feature = "watch")]
+    #[arg(long, alias="preset-file-watch", requires="preset_file")]
+    watch_presets: bool,
+
+    /// Use a random preset flag, printing its name to stderr
+    #[arg(long, conflicts_with_all=["flag", "custom"])]
+    preset_random: bool,
+
+    /// Seed for --preset-random, to pick the same flag every time
+    #[arg(long, requires="preset_random")]
+    seed: Option<u64>,
+
+    /// Pick a random preset flag for each file, seeded from its path, so that the same file always
+    /// gets the same flag. Only used when no other colorizer is chosen
+    #[arg(long, conflicts_with_all=["flag", "custom", "preset_random", "loop_presets"])]
+    color_seed_from_path: bool,
+
     /// Use a custom comma seperated sequence of colours to form a striped flag. Colors can be
-    /// specified using hex codes
+    /// specified using hex codes, optionally followed by ":weight" to give the stripe a relative
+    /// width, e.g. "FF0000:2,00FF00,0000FF:2"
     #[arg(long, value_delimiter=',')]
-    custom: Option<Vec<Color>>,
+    custom: Option<Vec<WeightedColor>>,
+
+    /// Comma separated relative widths of each --custom stripe, e.g. "2,1,2". Overrides any
+    /// weights given alongside the colors
+    #[arg(long, value_delimiter=',', requires="custom")]
+    weights: Option<Vec<f32>>,
+
+    /// Horizontal frequency, in stripes/column. Chosen automatically if not given
+    #[arg(long)]
+    hf: Option<f32>,
 
-    /// Horizontal frequency, in stripes/column
-    #[arg(long, default_value="0.05")]
-    hf: f32,
+    /// Vertical flag frequency, in stripes/row. Chosen automatically if not given
+    #[arg(long)]
+    vf: Option<f32>,
+
+    /// Automatically choose frequencies so that one repetition of the flag spans around 1.5 times
+    /// the terminal width, with a gentle diagonal. This is the default unless --hf or --vf is given
+    #[arg(long, conflicts_with_all=["hf", "vf"])]
+    frequency_auto: bool,
 
-    /// Vertical flag frequency, in stripes/row
-    #[arg(long, default_value="0.05")]
-    vf: f32,
+    /// Direction the stripes run in: "diagonal" (the default), "vertical" for vertical bars, which
+    /// sets --vf to 0, "horizontal" for horizontal bands, which sets --hf to 0, or an angle in
+    /// degrees, where 0 is vertical and 90 is horizontal. --hf and --vf still take precedence
+    #[arg(long, conflicts_with="frequency_auto")]
+    flag_direction: Option<FlagDirection>,
 
     /// Fraction of a stripe after reaching a new stripe before beginning to blend into the next
-    #[arg(long, default_value="0.6")]
-    deadzone: f32
+    #[arg(long, default_value_t=Flag::DEFAULT_DEADZONE)]
+    deadzone: f32,
+
+    /// How sharp the edges between stripes are, from 0 for a smooth gradient across each whole
+    /// stripe, to 1 for hard edges with no blending. This is the same as --deadzone, but clamped
+    /// to 0..=1
+    #[arg(long, value_name="0..1", conflicts_with="deadzone")]
+    stripe_sharpness: Option<f32>,
+
+    /// Reverse the order of the stripes, flipping the flag
+    #[arg(long)]
+    invert_stripes: bool,
+
+    /// Repeat the sequence of stripes this many times within one repetition of the flag, making
+    /// each stripe proportionally thinner
+    #[arg(long, default_value="1", value_parser=clap::value_parser!(u32).range(1..))]
+    repeat_stripes: u32,
+
+    /// Colorize the input once with each preset in turn, printing the name of each preset first
+    #[arg(long, conflicts_with_all=["flag", "custom", "preset_random"])]
+    loop_presets: bool,
+
+    /// Pause between presets with --loop-presets, in milliseconds. Only used when writing to a
+    /// terminal
+    #[arg(long, value_name="MS", requires="loop_presets")]
+    loop_delay: Option<u64>,
 }
 
 
@@ -157,7 +674,7 @@ impl FlagOpts {
                     }
                 }
 
-                write!(stdout, "\n")?;
+                writeln!(stdout)?;
             }
             Ok(true)
         } else {
@@ -165,32 +682,135 @@ impl FlagOpts {
         }
     }
 
-    fn into_colorizer(self) -> Option<Result<SomeColorizer>> {
+    /// Apply the frequency options to a flag
+    fn build(&self, mut flag: Flag, config: &ColorizerConfig) -> Flag {
+        if self.invert_stripes {
+            flag.stripes.reverse();
+            if let Some(weights) = &mut flag.weights {
+                weights.reverse();
+            }
+        }
+
+        let auto = match config.pattern_width {
+            Some(width) => flag.stripes.len() as f32 / width as f32,
+            None => Flag::auto_frequency(flag.stripes.len(), config.wraps_after),
+        };
+        let (hf, vf) = if self.frequency_auto {
+            (auto, auto)
+        } else {
+            let (hf, vf) = self.flag_direction.unwrap_or(FlagDirection::Diagonal).frequencies(auto);
+            (self.hf.unwrap_or(hf), self.vf.unwrap_or(vf))
+        };
+
+        // Repeating the stripes makes a longer flag, so increase the frequency to compensate
+        let repeats = self.repeat_stripes as usize;
+
+        Flag::new(flag.stripes.repeat(repeats))
+            .hf(hf * repeats as f32)
+            .vf(vf * repeats as f32)
+            .weights(flag.weights.map(|weights| weights.repeat(repeats)))
+            .deadzone(self.stripe_sharpness.map_or(self.deadzone, |sharpness| sharpness.clamp(0f32, 1f32)))
+            .blend_space(config.blend_space)
+    }
+
+    /// Apply overrides given after a preset name, in the form "key=value,...". Each key is one of
+    /// hf, vf or deadzone.
+    fn apply_overrides(&mut self, overrides: &str) -> Result<()> {
+        for entry in overrides.split(',') {
+            let Some((key, value)) = entry.split_once('=')
+                else {
+                    return Err(anyhow!("Expected key=value after preset name, got {entry}"));
+                };
+
+            let key = key.trim();
+            let value: f32 = value.trim().parse()
+                .map_err(|e| anyhow!("Invalid value for {key}: {e}"))?;
+
+            if key.eq_ignore_ascii_case("hf") {
+                self.hf = Some(value);
+                self.frequency_auto = false;
+            } else if key.eq_ignore_ascii_case("vf") {
+                self.vf = Some(value);
+                self.frequency_auto = false;
+            } else if key.eq_ignore_ascii_case("deadzone") {
+                self.deadzone = value;
+                self.stripe_sharpness = None;
+            } else {
+                return Err(anyhow!("Invalid preset option {key}! - Expected hf, vf or deadzone"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the preset name given by --flag, applying any overrides which follow it
+    fn take_preset_name(&mut self) -> Result<Option<String>> {
+        let Some(spec) = self.flag.take()
+            else {
+                return Ok(None);
+            };
+
+        match spec.split_once('@') {
+            Some((name, overrides)) => {
+                self.apply_overrides(overrides)?;
+                Ok(Some(name.to_string()))
+            },
+            None => Ok(Some(spec)),
+        }
+    }
+
+    fn into_colorizer(mut self, config: &ColorizerConfig, quiet: bool) -> Option<Result<SomeColorizer>> {
         // First check if a preset name has been given
-        if let Some(name) = &self.flag {
+        let name = match self.take_preset_name() {
+            Ok(name) => name,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if let Some(name) = name {
+            #[cfg(feature = "watch")]
+            if self.watch_presets {
+                let config = config.clone();
+                let flag = WatchedFlag::new(name, move |preset| self.build(Flag::from_preset(preset), &config));
+                return Some(flag.map(|flag| SomeColorizer::Dyn(Box::new(flag))));
+            }
+
             // Fetch the preset or return an appropriate error message
             // TODO: use match for clarity?
-            let Some(preset) = flag_by_name(name)
+            let Some(preset) = flag_by_name(&name)
                 else {
                     return Some(Err(anyhow!("Invalid preset name {name}! - Use --presets to list all available flag presets")));
                 };
 
-            let pattern = preset.stripes.to_vec();
-
-            Some(Ok(SomeColorizer::Flag(Flag {
-                hf: self.hf,
-                vf: self.vf,
-                stripes: pattern,
-                deadzone: self.deadzone,
-            })))
+            Some(Ok(SomeColorizer::Flag(self.build(Flag::from_preset(&preset), config))))
         // Otherwise check if a custom pattern has been given
-        } else if let Some(pattern) = self.custom {
-            Some(Ok(SomeColorizer::Flag(Flag {
-                hf: self.hf,
-                vf: self.vf,
-                stripes: pattern,
-                deadzone: self.deadzone
-            })))
+        } else if let Some(pattern) = &self.custom {
+            let stripes = pattern.iter().map(|stripe| stripe.color).collect();
+            let weights: Vec<_> = match &self.weights {
+                Some(weights) if weights.len() != pattern.len() => {
+                    return Some(Err(anyhow!("Got {} weights for {} stripes! - Give one weight per --custom color", weights.len(), pattern.len())));
+                },
+                Some(weights) if weights.iter().any(|&w| !(w > 0f32 && w.is_finite())) => {
+                    return Some(Err(anyhow!("Invalid stripe weights! - Weights must be positive")));
+                },
+                Some(weights) => weights.clone(),
+                None => pattern.iter().map(|stripe| stripe.weight).collect(),
+            };
+
+            // Only bother with weights if they actually make a difference
+            let weights = weights.iter().any(|&w| w != 1.0).then_some(weights);
+
+            Some(Ok(SomeColorizer::Flag(self.build(Flag::new(stripes).weights(weights), config))))
+        // Or pick a random preset
+        } else if self.preset_random {
+            let preset = match self.seed {
+                Some(seed) => random_flag_preset(&mut StdRng::seed_from_u64(seed)),
+                None => random_flag_preset(&mut thread_rng()),
+            };
+
+            if !quiet {
+                eprintln!("Using the {} flag", preset.name);
+            }
+
+            Some(Ok(SomeColorizer::Flag(self.build(Flag::from_preset(&preset), config))))
         } else {
             None
         }
@@ -198,6 +818,88 @@ impl FlagOpts {
 }
 
 
+/// Direction that the stripes of a flag run in
+#[derive(Debug, Clone, Copy)]
+enum FlagDirection {
+    Horizontal,
+    Vertical,
+    Diagonal,
+    /// Angle in degrees, where 0 is vertical and 90 is horizontal
+    Angle(f32),
+}
+
+
+impl FlagDirection {
+    /// Horizontal and vertical frequencies for stripes in this direction
+    fn frequencies(self, base_frequency: f32) -> (f32, f32) {
+        match self {
+            FlagDirection::Horizontal => (0f32, base_frequency),
+            FlagDirection::Vertical => (base_frequency, 0f32),
+            FlagDirection::Diagonal => (base_frequency, base_frequency),
+            FlagDirection::Angle(degrees) => {
+                let (sin, cos) = degrees.to_radians().sin_cos();
+                (base_frequency * cos, base_frequency * sin)
+            },
+        }
+    }
+}
+
+
+impl FromStr for FlagDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("horizontal") {
+            Ok(Self::Horizontal)
+        } else if s.eq_ignore_ascii_case("vertical") {
+            Ok(Self::Vertical)
+        } else if s.eq_ignore_ascii_case("diagonal") {
+            Ok(Self::Diagonal)
+        } else if let Ok(degrees) = s.trim_end_matches("deg").parse::<f32>() {
+            if degrees.is_finite() {
+                Ok(Self::Angle(degrees))
+            } else {
+                Err(anyhow!("Invalid angle {s}!"))
+            }
+        } else {
+            Err(anyhow!("Invalid flag direction {s}! - Expected horizontal, vertical, diagonal or an angle in degrees"))
+        }
+    }
+}
+
+
+/// A stripe color for a custom flag, along with its relative width. Parsed from "color:weight",
+/// where the weight is optional and defaults to 1
+#[derive(Debug, Clone, Copy)]
+struct WeightedColor {
+    color: Color,
+    weight: f32,
+}
+
+
+impl FromStr for WeightedColor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (color, weight) = match s.split_once(':') {
+            Some((color, weight)) => (color, weight.parse()?),
+            None => (s, 1f32),
+        };
+
+        if !(weight > 0f32 && weight.is_finite()) {
+            return Err(anyhow!("Invalid stripe weight {weight} for {color}! - Weights must be positive"));
+        }
+
+        let color = color.parse().map_err(|e| anyhow!("Invalid color {color}: {e}"))?;
+
+        Ok(Self {
+            color,
+            weight,
+        })
+    }
+}
+
+
 /// Image width, either fixed, the original width, or automatically scaled to the width of the terminal
 #[derive(Debug, Clone)]
 enum ImageWidth {
@@ -246,6 +948,29 @@ impl FromStr for ImageHeight {
 }
 
 
+/// Whether an image is used to recolor the input text, or is displayed itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageMode {
+    Recolor,
+    Render,
+}
+
+
+impl FromStr for ImageMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("recolor") {
+            Ok(Self::Recolor)
+        } else if s.eq_ignore_ascii_case("render") {
+            Ok(Self::Render)
+        } else {
+            Err(anyhow!("Invalid image mode {s}! - Expected \"recolor\" or \"render\""))
+        }
+    }
+}
+
+
 /// Options for the image colorizer
 #[derive(Debug, Args)]
 struct ImageOpts {
@@ -253,11 +978,26 @@ struct ImageOpts {
     #[arg(long)]
     image: Option<PathBuf>,
 
+    /// Either "recolor" to color the input text using the image, or "render" to display the image
+    /// itself using half-block characters. Input files are not read when rendering
+    #[arg(long, default_value="recolor")]
+    image_mode: ImageMode,
+
     /// Cell aspect ratio, defined as width/height per cell. Used only when image-height is set to
     /// "ratio"
     #[arg(long, default_value="0.7")]
     cell_aspect_ratio: f64,
 
+    /// Which cell of each block of cells sharing a color (see --sample-rate) the image is sampled
+    /// at, either "center" or "topleft"
+    #[arg(long, default_value="center")]
+    image_sample_point: SamplePoint,
+
+    /// Work out the cell aspect ratio from the terminal's size in pixels, for terminals which
+    /// report it. Falls back to --cell-aspect-ratio otherwise
+    #[arg(long)]
+    image_aspect_correct: bool,
+
     /// Width of the image in pixels, or "fit" to fit the console width
     #[arg(long, default_value="fit")]
     image_width: ImageWidth,
@@ -265,59 +1005,736 @@ struct ImageOpts {
     /// Height of the image in pixels, or "ratio" to maintain the aspect ratio
     #[arg(long, default_value="ratio")]
     image_height: ImageHeight,
+
+    /// Vary the lightness of the image's colors along a diagonal, so that flat regions of the
+    /// image still show some variation. Optionally takes a strength from 0 to 1
+    #[arg(long, num_args=0..=1, default_missing_value="0.5")]
+    image_luma_modulate: Option<f32>,
+
+    /// Only use a region of the image, given in source pixels as "x,y,width,height". Cropping
+    /// happens before resizing, so the other image options apply to the cropped region
+    #[arg(long)]
+    image_crop: Option<CropRect>,
+
+    /// Resize the image straight to the grid of cells, keeping its aspect ratio using
+    /// --cell-aspect-ratio. The number of rows is rounded to the nearest whole cell, rather than
+    /// rounded down, and --image-height is ignored
+    #[arg(long)]
+    image_resize_to_cells: bool,
+
+    /// Gamma applied to each channel of the source image before sampling. Values above 1 darken
+    /// and add contrast to washed out images, while values below 1 brighten them
+    #[arg(long, default_value="1.0")]
+    image_gamma: f32,
+
+    /// Color to flatten transparent images onto before they are resized and sampled, so that
+    /// partly transparent pixels blend into it. "auto" asks the terminal for its background
+    /// color, falling back to COLORFGBG, then --background and then to black
+    #[arg(long, default_value="000000")]
+    image_background: ImageBackground,
+
+    /// Background color of the terminal, used by "--image-background auto" when the terminal
+    /// can't be asked for it
+    #[arg(long)]
+    background: Option<Color>,
+
+    /// Pixelate the image into blocks of N by N cells, for a deliberately chunky look. Happens
+    /// after cropping and resizing
+    #[arg(long, value_name="N", default_value="1", value_parser=clap::value_parser!(u32).range(1..))]
+    image_pixelate: u32,
+
+    /// Filter used to resize the image: "nearest" keeps the hard edges of pixel art, "box"
+    /// averages the pixels covered by each cell, while "triangle", "catmull-rom", "gaussian" and
+    /// "lanczos3" smooth it to varying degrees
+    #[arg(long, default_value="gaussian")]
+    image_filter: ImageFilter,
+
+    /// Play each frame of an animated gif in a loop, redrawing the input colored by each frame in
+    /// turn until interrupted with Ctrl-C. The whole input is read before the animation starts
+    #[arg(long, requires="image", conflicts_with_all=["image_mode", "follow", "loop_presets"])]
+    animate: bool,
+
+    /// Dither the image using Floyd-Steinberg error diffusion, so that gradients are kept rather
+    /// than turning into flat bands. Only used with the palette256 and palette8 color modes
+    #[arg(long)]
+    dither: bool,
 }
 
 
 impl ImageOpts {
+    /// True if the image should be used to recolor the input text
+    fn is_recoloring(&self) -> bool {
+        self.image.is_some() && self.image_mode == ImageMode::Recolor
+    }
+
+    /// True if the image itself should be displayed
+    fn is_rendering(&self) -> bool {
+        self.image.is_some() && self.image_mode == ImageMode::Render
+    }
+
     fn into_colorizer(self, config: &ColorizerConfig) -> Option<Result<SomeColorizer>> {
-        let path = self.image?;
+        if self.image_mode != ImageMode::Recolor {
+            return None;
+        }
 
-        let img = match open(path) {
-            Ok(img) => img.to_rgb8(),
-            Err(e) => return Some(Err(e.into())),
-        };
+        // The background may be queried from the terminal, so only do so once
+        let background = self.image_background.resolve(self.background);
+        match self.load_resized(background, config, 1.0)? {
+            Ok(img) => Some(Ok(SomeColorizer::Image(self.build(img, background, config)))),
+            Err(e) => Some(Err(e)),
+        }
+    }
 
-        // Determine width
-        let width = match self.image_width {
-            ImageWidth::Original => img.width() as usize,  // Yes, it's a bit silly to upcast to usize then back to u32
-            ImageWidth::Fixed(x) => x,
-            ImageWidth::Fit => config.wraps_after.unwrap_or(80),
+    /// Load each frame of an animated image, along with how long it is shown for, as colorizers
+    fn load_frames(&self, config: &ColorizerConfig) -> Result<Vec<(Image<RgbImage>, Duration)>> {
+        let path = self.image.as_ref()
+            .ok_or_else(|| anyhow!("--animate requires an --image"))?;
+
+        // The background may be queried from the terminal, so only do so once
+        let background = self.image_background.resolve(self.background);
+        load_gif_frames(path)?
+            .into_iter()
+            .map(|(img, delay)| Ok((self.build(self.fit_to_cells(&img, background, config, 1.0)?, background, config), delay)))
+            .collect()
+    }
+
+    /// Wrap a resized image in an [Image] colorizer, showing the background through any
+    /// transparency
+    fn build(&self, img: RgbImage, background: Color, config: &ColorizerConfig) -> Image<RgbImage> {
+        // The center of a block of cells is half way across it, which for a single cell is half
+        // way across that cell
+        let sample_offset = match self.image_sample_point {
+            SamplePoint::Center => config.sample_rate as f32 * 0.5,
+            SamplePoint::TopLeft => 0f32,
         };
-        // Convert it to u32, or return an appropriate error
+        Image::new(img)
+            .with_luma_modulation(self.image_luma_modulate.unwrap_or(0f32))
+            .with_sample_offset(sample_offset)
+            .with_background(background)
+    }
+
+    /// Convert to a [HalfBlockImage] if the image should be rendered rather than used for recoloring
+    fn try_into_render(&self, config: &ColorizerConfig) -> Option<Result<HalfBlockImage<RgbImage>>> {
+        if self.image_mode != ImageMode::Render {
+            return None;
+        }
+
+        // Each cell displays two pixels stacked vertically
+        match self.load_resized(self.image_background.resolve(self.background), config, 2.0)? {
+            Ok(img) => Some(Ok(HalfBlockImage::new(img))),
+            Err(e) => Some(Err(e)),
+        }
+    }
+
+    /// Open the image, and resize it so that each pixel corresponds to a cell. pixels_per_row
+    /// gives the number of pixels stacked vertically in each cell.
+    fn load_resized(&self, background: Color, config: &ColorizerConfig, pixels_per_row: f64) -> Option<Result<RgbImage>> {
+        let path = self.image.as_ref()?;
+
+        match open(path) {
+            Ok(img) => Some(self.fit_to_cells(&img.to_rgba8(), background, config, pixels_per_row)),
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+
+    /// Flatten an image onto the background, then crop, adjust and resize it so that each pixel
+    /// corresponds to a cell
+    fn fit_to_cells(&self, img: &RgbaImage, background: Color, config: &ColorizerConfig, pixels_per_row: f64) -> Result<RgbImage> {
+        let img = flatten_onto(img, background);
+
+        // Crop, making sure the region actually lies within the image
+        let img = match self.image_crop {
+            Some(crop) => {
+                crop.check_within(img.width(), img.height())?;
+                crop_imm(&img, crop.x, crop.y, crop.width, crop.height).to_image()
+            },
+            None => img,
+        };
+
+        // Apply gamma using a lookup table, since there are only 256 possible values per channel
+        let mut img = img;
+        if self.image_gamma <= 0f32 {
+            return Err(anyhow!("Image gamma must be greater than zero"));
+        } else if self.image_gamma != 1f32 {
+            let table: Vec<u8> = (0..=255u8)
+                .map(|v| ((v as f32 / 255f32).powf(self.image_gamma) * 255f32).round() as u8)
+                .collect();
+            for channel in img.iter_mut() {
+                *channel = table[*channel as usize];
+            }
+        }
+
+        // Determine width
+        let width = match config.pattern_width {
+            Some(width) => width,
+            None => match self.image_width {
+                ImageWidth::Original => img.width() as usize,  // Yes, it's a bit silly to upcast to usize then back to u32
+                ImageWidth::Fixed(x) => x,
+                ImageWidth::Fit => config.wraps_after.unwrap_or(80),
+            },
+        };
+        // Convert it to u32, or return an appropriate error
         let width = match width.try_into() {
             Ok(w) => w,
-            Err(_) => return Some(Err(anyhow!("Image width {width} is too large!"))),
+            Err(_) => return Err(anyhow!("Image width {width} is too large!")),
         };
 
         // Similar for height
+        let cell_aspect_ratio = if self.image_aspect_correct {
+            cell_aspect_ratio().unwrap_or(self.cell_aspect_ratio)
+        } else {
+            self.cell_aspect_ratio
+        };
         let height = match self.image_height {
+            // Work out the number of whole rows of cells first, so that each row of cells gets
+            // exactly pixels_per_row pixels, and there is always at least one row
+            _ if self.image_resize_to_cells => {
+                let aspect = img.height() as f64 / img.width() as f64;
+                let rows = (width as f64 * aspect * cell_aspect_ratio).round().max(1f64);
+                (rows * pixels_per_row) as usize
+            },
             ImageHeight::Original => img.height() as usize,
             ImageHeight::Fixed(x) => x,
             ImageHeight::Ratio => {
                 // Maintain the aspect ratio by copying the same scale factor from the width,
                 // taking differing ppc/ppr into account
                 let scale_ratio = (width as f64) / (img.width() as f64);
-                (img.height() as f64 * scale_ratio * self.cell_aspect_ratio) as usize
+                (img.height() as f64 * scale_ratio * cell_aspect_ratio * pixels_per_row) as usize
             }
         };
         let height = match height.try_into() {
             Ok(h) => h,
-            Err(_) => return Some(Err(anyhow!("Image height {height} is too large!"))),
+            Err(_) => return Err(anyhow!("Image height {height} is too large!")),
         };
 
         // Resize
-        let img = resize(&img, width, height, FilterType::Gaussian);
+        let img = self.image_filter.resize(&img, width, height);
+
+        // Each pixel takes the color of the top left pixel in its block, which is the same as
+        // downsampling then upsampling with nearest neighbour filtering
+        let block = self.image_pixelate;
+        let mut img = if block > 1 {
+            RgbImage::from_fn(width, height, |x, y| {
+                *img.get_pixel(x / block * block, y / block * block)
+            })
+        } else {
+            img
+        };
+
+        if self.dither && config.color_mode != ColorMode::Truecolor {
+            dither(&mut img, config.color_mode);
+        }
+
+        Ok(img)
+    }
+}
+
+
+/// Filter used to resize images. Wraps [FilterType], which can't be parsed from a string, along
+/// with a box filter of our own
+#[derive(Debug, Clone, Copy)]
+enum ImageFilter {
+    /// Average every pixel covered by each new pixel
+    Box,
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+
+impl FromStr for ImageFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("box") {
+            Ok(Self::Box)
+        } else if s.eq_ignore_ascii_case("nearest") {
+            Ok(Self::Nearest)
+        } else if s.eq_ignore_ascii_case("triangle") {
+            Ok(Self::Triangle)
+        } else if s.eq_ignore_ascii_case("catmull-rom") || s.eq_ignore_ascii_case("catmullrom") {
+            Ok(Self::CatmullRom)
+        } else if s.eq_ignore_ascii_case("gaussian") {
+            Ok(Self::Gaussian)
+        } else if s.eq_ignore_ascii_case("lanczos3") {
+            Ok(Self::Lanczos3)
+        } else {
+            Err(anyhow!("Invalid image filter {s}! - Expected box, nearest, triangle, catmull-rom, gaussian or lanczos3"))
+        }
+    }
+}
+
+
+impl ImageFilter {
+    /// Resize an image to the given size using this filter
+    fn resize(self, img: &RgbImage, width: u32, height: u32) -> RgbImage {
+        let filter = match self {
+            ImageFilter::Box => return box_resize(img, width, height),
+            ImageFilter::Nearest => FilterType::Nearest,
+            ImageFilter::Triangle => FilterType::Triangle,
+            ImageFilter::CatmullRom => FilterType::CatmullRom,
+            ImageFilter::Gaussian => FilterType::Gaussian,
+            ImageFilter::Lanczos3 => FilterType::Lanczos3,
+        };
+        resize(img, width, height, filter)
+    }
+}
+
+
+/// Resize an image by giving each new pixel the average of the pixels it covers. When enlarging,
+/// each new pixel covers part of a single pixel, so this is the same as nearest neighbour
+fn box_resize(img: &RgbImage, width: u32, height: u32) -> RgbImage {
+    // Range of source pixels covered by a new pixel, which always includes at least one
+    let covered = |i: u32, new_size: u32, old_size: u32| {
+        let start = (i as u64 * old_size as u64 / new_size as u64) as u32;
+        let end = ((i as u64 + 1) * old_size as u64 / new_size as u64) as u32;
+        start..end.max(start + 1).min(old_size)
+    };
+
+    let mut colors = Vec::new();
+    RgbImage::from_fn(width, height, |x, y| {
+        colors.clear();
+        for sy in covered(y, height, img.height()) {
+            for sx in covered(x, width, img.width()) {
+                let &Rgb([r, g, b]) = img.get_pixel(sx, sy);
+                colors.push(Color::from_rgb(r, g, b));
+            }
+        }
+        let (r, g, b) = Color::mix_many(&colors).to_rgb();
+        Rgb([r, g, b])
+    })
+}
+
+
+/// Where blocks of cells sharing a color sample the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SamplePoint {
+    Center,
+    TopLeft,
+}
+
+
+impl FromStr for SamplePoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("center") {
+            Ok(Self::Center)
+        } else if s.eq_ignore_ascii_case("topleft") {
+            Ok(Self::TopLeft)
+        } else {
+            Err(anyhow!("Invalid sample point {s}! - Expected center or topleft"))
+        }
+    }
+}
+
+
+/// Background color for transparent images
+#[derive(Debug, Clone, Copy)]
+enum ImageBackground {
+    Fixed(Color),
+    /// Match the terminal's background
+    Auto,
+}
+
+
+impl ImageBackground {
+    /// Find the background color. fallback is used if the terminal's background is wanted, but
+    /// can't be found
+    fn resolve(self, fallback: Option<Color>) -> Color {
+        match self {
+            ImageBackground::Fixed(color) => color,
+            ImageBackground::Auto => query_background_color()
+                .or_else(colorfgbg_background)
+                .or(fallback)
+                .unwrap_or(Color::BLACK),
+        }
+    }
+}
+
+
+impl FromStr for ImageBackground {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            Ok(Self::Auto)
+        } else {
+            let color = s.parse().map_err(|e| anyhow!("Invalid color {s}: {e}"))?;
+            Ok(Self::Fixed(color))
+        }
+    }
+}
+
+
+/// Composite an image with transparency over a solid background color
+fn flatten_onto(img: &RgbaImage, background: Color) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let Rgba([r, g, b, a]) = *img.get_pixel(x, y);
+        let (r, g, b) = Color::from_rgb(r, g, b)
+            .blend_over(background, a as f32 / 255f32)
+            .to_rgb();
+        Rgb([r, g, b])
+    })
+}
+
+
+/// Snap each pixel to the colors the terminal can show in the given color mode, spreading the
+/// error onto the pixels to the right and below using Floyd-Steinberg dithering
+fn dither(img: &mut RgbImage, color_mode: ColorMode) {
+    let width = img.width() as usize;
+
+    // Error carried onto the current and next rows, with a pixel of padding either side
+    let mut current = vec![[0f32; 3]; width + 2];
+    let mut next = vec![[0f32; 3]; width + 2];
+
+    for y in 0..img.height() {
+        for x in 0..img.width() {
+            let pixel = img.get_pixel_mut(x, y);
+            let x = x as usize;
+            let wanted = [0, 1, 2].map(|i| (pixel.0[i] as f32 + current[x + 1][i]).clamp(0f32, 255f32));
+
+            let (r, g, b) = color_mode
+                .nearest(Color::from_rgb(wanted[0] as u8, wanted[1] as u8, wanted[2] as u8))
+                .to_rgb();
+            *pixel = Rgb([r, g, b]);
+
+            for (i, (want, shown)) in wanted.into_iter().zip([r, g, b]).enumerate() {
+                let error = want - shown as f32;
+                current[x + 2][i] += error * 7f32 / 16f32;
+                next[x][i] += error * 3f32 / 16f32;
+                next[x + 1][i] += error * 5f32 / 16f32;
+                next[x + 2][i] += error / 16f32;
+            }
+        }
+
+        std::mem::swap(&mut current, &mut next);
+        next.fill([0f32; 3]);
+    }
+}
+
+
+/// A region of an image, parsed from "x,y,width,height"
+#[derive(Debug, Clone, Copy)]
+struct CropRect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+
+impl CropRect {
+    /// Check that the region is non-empty and lies within an image of the given size
+    fn check_within(&self, width: u32, height: u32) -> Result<()> {
+        let right = self.x.checked_add(self.width);
+        let bottom = self.y.checked_add(self.height);
+
+        if self.width == 0 || self.height == 0 {
+            Err(anyhow!("Image crop region must not be empty"))
+        } else if right.is_none_or(|r| r > width) || bottom.is_none_or(|b| b > height) {
+            Err(anyhow!(
+                "Image crop region {}x{} at {},{} doesn't fit within the {width}x{height} image",
+                self.width, self.height, self.x, self.y))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+
+impl FromStr for CropRect {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let values = s.split(',')
+            .map(|v| v.trim().parse().map_err(|e| anyhow!("Invalid crop value {v}: {e}")))
+            .collect::<Result<Vec<u32>>>()?;
+
+        let [x, y, width, height] = values[..]
+            else {
+                return Err(anyhow!("Expected x,y,width,height, got {s}"));
+            };
+
+        Ok(Self {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+}
+
 
-        Some(Ok(SomeColorizer::Image(Image::new(img))))
+/// A color for one category of characters, parsed from "category=color"
+#[derive(Debug, Clone, Copy)]
+struct CategoryColor {
+    category: CharCategory,
+    color: Color,
+}
+
+
+impl FromStr for CategoryColor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some((category, color)) = s.split_once('=')
+            else {
+                return Err(anyhow!("Expected category=color, got {s}"));
+            };
+
+        let category = if category.eq_ignore_ascii_case("letter") {
+            CharCategory::Letter
+        } else if category.eq_ignore_ascii_case("digit") {
+            CharCategory::Digit
+        } else if category.eq_ignore_ascii_case("punctuation") {
+            CharCategory::Punctuation
+        } else if category.eq_ignore_ascii_case("other") {
+            CharCategory::Other
+        } else {
+            return Err(anyhow!("Invalid category {category}! - Expected letter, digit, punctuation or other"));
+        };
+
+        let color = color.parse().map_err(|e| anyhow!("Invalid color {color}: {e}"))?;
+
+        Ok(Self {
+            category,
+            color,
+        })
+    }
+}
+
+
+/// Options for the character category colorizer
+#[derive(Debug, Args)]
+struct CategoryOpts {
+    /// Color each character based on whether it is a letter, digit, punctuation or something else,
+    /// rather than on its position
+    #[arg(long)]
+    category: bool,
+
+    /// Comma separated category=color pairs, overriding the default color of a category. Valid
+    /// categories are letter, digit, punctuation and other. Implies --category
+    #[arg(long, value_delimiter=',')]
+    category_colors: Vec<CategoryColor>,
+}
+
+
+impl CategoryOpts {
+    fn into_colorizer(self) -> Option<Result<SomeColorizer>> {
+        if !self.category && self.category_colors.is_empty() {
+            return None;
+        }
+
+        let mut letter = Color::from_rgb(0xF5, 0xA9, 0xB8);
+        let mut digit = Color::from_rgb(0x5B, 0xCE, 0xFA);
+        let mut punctuation = Color::from_rgb(0xFF, 0xED, 0x00);
+        let mut other = Color::WHITE;
+
+        for CategoryColor { category, color } in self.category_colors {
+            match category {
+                CharCategory::Letter => letter = color,
+                CharCategory::Digit => digit = color,
+                CharCategory::Punctuation => punctuation = color,
+                CharCategory::Other => other = color,
+                CharCategory::Whitespace => unreachable!("Whitespace can't be parsed as a category"),
+            }
+        }
+
+        Some(Ok(SomeColorizer::Category(Category::new(letter, digit, punctuation, other))))
+    }
+}
+
+
+/// A pair of colors, parsed from "color,color"
+#[derive(Debug, Clone, Copy)]
+struct ColorPair(Color, Color);
+
+
+impl FromStr for ColorPair {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some((a, b)) = s.split_once(',')
+            else {
+                return Err(anyhow!("Expected two comma separated colors, got {s}"));
+            };
+
+        let a = a.parse().map_err(|e| anyhow!("Invalid color {a}: {e}"))?;
+        let b = b.parse().map_err(|e| anyhow!("Invalid color {b}: {e}"))?;
+        Ok(Self(a, b))
+    }
+}
+
+
+/// Options for the gradient colorizers
+#[derive(Debug, Args)]
+struct GradientOpts {
+    /// Fade once from one color to another across the output, given as "start,end". The width
+    /// is taken from --wrap if given, and the height as for --gradient-vertical
+    #[arg(long, conflicts_with="gradient_vertical")]
+    gradient: Option<ColorPair>,
+
+    /// Direction for --gradient to fade in: "horizontal" (the default) from left to right,
+    /// "vertical" from top to bottom, or "diagonal" from the top left to the bottom right
+    #[arg(long, requires="gradient", default_value="horizontal")]
+    gradient_direction: GradientDirection,
+
+    /// Number of columns for --gradient to span, overriding the wrap width. Defaults to 80 if the
+    /// output doesn't wrap
+    #[arg(long, requires="gradient")]
+    gradient_width: Option<usize>,
+
+    /// Number of rows for --gradient to span, overriding the height of the output. Defaults to
+    /// 24 if the height isn't known
+    #[arg(long, requires="gradient")]
+    gradient_height: Option<usize>,
+
+    /// Fade from one color at the top of the output to another at the bottom, given as
+    /// "from,to". The height is taken from the input if it is a single file, or otherwise the
+    /// terminal. If neither is known, the colors fade back and forth instead
+    #[arg(long)]
+    gradient_vertical: Option<ColorPair>,
+}
+
+
+impl GradientOpts {
+    fn into_colorizer(self, config: &ColorizerConfig, quiet: bool) -> Option<Result<SomeColorizer>> {
+        if let Some(ColorPair(start, end)) = self.gradient {
+            return Some(Ok(SomeColorizer::Gradient(Gradient {
+                start,
+                end,
+                direction: self.gradient_direction,
+                width: self.gradient_width.or(config.wraps_after).unwrap_or(Gradient::DEFAULT_WIDTH),
+                height: self.gradient_height.or(config.height).unwrap_or(Gradient::DEFAULT_HEIGHT),
+                blend_space: config.blend_space,
+            })));
+        }
+
+        let ColorPair(from, to) = self.gradient_vertical?;
+
+        if config.height.is_none() && !quiet {
+            eprintln!("Height of the output is unknown, so the gradient will repeat instead");
+        }
+
+        Some(Ok(SomeColorizer::VerticalGradient(VerticalGradient {
+            from,
+            to,
+            height: config.height,
+            fallback_frequency: 0.05,
+            blend_space: config.blend_space,
+        })))
     }
 }
 
 
-/// Enum over stream colorizers, [StreamColorizer] is not object safe.
+/// Options for the radial gradient colorizer
+#[derive(Debug, Args)]
+struct RadialOpts {
+    /// Fade from one color at the center of the output out to another in circles, given as
+    /// "inner,outer"
+    #[arg(long)]
+    radial: Option<ColorPair>,
+
+    /// Cell at the center of --radial, given as "column,row". Defaults to the middle of the wrap
+    /// width (or 80 columns), and halfway down --radial-height
+    #[arg(long, requires="radial")]
+    radial_center: Option<Position>,
+
+    /// Distance from the center of --radial to where the outer color is reached, in columns.
+    /// Defaults to half the wrap width
+    #[arg(long, requires="radial")]
+    radial_radius: Option<f32>,
+
+    /// Height assumed for the output when centering --radial vertically, as streamed input can be
+    /// any length. Defaults to the height of the input if it is a single file, or otherwise the
+    /// terminal, or 24 rows if neither is known
+    #[arg(long, requires="radial")]
+    radial_height: Option<usize>,
+}
+
+
+impl RadialOpts {
+    fn into_colorizer(self, config: &ColorizerConfig) -> Option<Result<SomeColorizer>> {
+        let ColorPair(inner, outer) = self.radial?;
+
+        let width = config.wraps_after.unwrap_or(Gradient::DEFAULT_WIDTH) as f32;
+        let height = self.radial_height.or(config.height).unwrap_or(Gradient::DEFAULT_HEIGHT) as f32;
+        let center = match self.radial_center {
+            Some(Position(x, y)) => (x as f32, y as f32),
+            None => ((width - 1f32).max(0f32) / 2f32, (height - 1f32).max(0f32) / 2f32),
+        };
+
+        let radius = self.radial_radius.unwrap_or(width / 2f32);
+        if radius <= 0f32 || !radius.is_finite() {
+            return Some(Err(anyhow!("Invalid radial radius {radius}! - Must be greater than 0")));
+        }
+
+        Some(Ok(SomeColorizer::RadialGradient(RadialGradient {
+            center,
+            inner,
+            outer,
+            radius,
+            blend_space: config.blend_space,
+        })))
+    }
+}
+
+
+/// Options for the rainbow colorizer
+#[derive(Debug, Args)]
+struct RainbowOpts {
+    /// Cycle smoothly through every hue in diagonal bands, like lolcat, rather than using the
+    /// stripes of a flag
+    #[arg(long)]
+    rainbow: bool,
+
+    /// Change in hue from one row to the next, in radians. Implies --rainbow
+    #[arg(long)]
+    rainbow_freq: Option<f32>,
+
+    /// Number of columns over which the hue changes by one radian, so larger values give wider
+    /// bands. Implies --rainbow
+    #[arg(long)]
+    rainbow_spread: Option<f32>,
+}
+
+
+impl RainbowOpts {
+    fn into_colorizer(self) -> Option<Result<SomeColorizer>> {
+        if !self.rainbow && self.rainbow_freq.is_none() && self.rainbow_spread.is_none() {
+            return None;
+        }
+
+        let spread = self.rainbow_spread.unwrap_or(Rainbow::DEFAULT_SPREAD);
+        if spread <= 0f32 || !spread.is_finite() {
+            return Some(Err(anyhow!("Invalid rainbow spread {spread}! - Must be greater than 0")));
+        }
+
+        Some(Ok(SomeColorizer::Rainbow(Rainbow {
+            freq: self.rainbow_freq.unwrap_or(Rainbow::DEFAULT_FREQUENCY),
+            spread,
+            phase: 0f32,
+        })))
+    }
+}
+
+
+/// Enum over stream colorizers. Colorizers chosen by name are boxed, while the rest avoid dynamic
+/// dispatch.
 enum SomeColorizer {
     Noop(Noop),
     Flag(Flag),
     Image(Image<RgbImage>),
+    Category(Category),
+    Grid(Grid),
+    VerticalGradient(VerticalGradient),
+    Gradient(Gradient),
+    RadialGradient(RadialGradient),
+    Rainbow(Rainbow),
+    Dyn(Box<dyn StreamColorizer>),
 }
 
 
@@ -329,57 +1746,957 @@ impl StreamColorizer for SomeColorizer {
             SomeColorizer::Noop(x) => x.copy_colorized(input, output, config),
             SomeColorizer::Flag(x) => x.copy_colorized(input, output, config),
             SomeColorizer::Image(x) => x.copy_colorized(input, output, config),
+            SomeColorizer::Category(x) => x.copy_colorized(input, output, config),
+            SomeColorizer::Grid(x) => x.copy_colorized(input, output, config),
+            SomeColorizer::VerticalGradient(x) => x.copy_colorized(input, output, config),
+            SomeColorizer::Gradient(x) => x.copy_colorized(input, output, config),
+            SomeColorizer::RadialGradient(x) => x.copy_colorized(input, output, config),
+            SomeColorizer::Rainbow(x) => x.copy_colorized(input, output, config),
+            SomeColorizer::Dyn(x) => x.copy_colorized(input, output, config),
         }
     }
+
+    fn copy_colorized_dyn(&mut self, input: &mut dyn Read, output: &mut dyn Write, config: &ColorizerConfig) -> std::io::Result<()> {
+        self.copy_colorized(input, output, config)
+    }
+
+    fn color_at(&mut self, position: (usize, usize), config: &ColorizerConfig) -> Option<Color> {
+        match self {
+            SomeColorizer::Noop(x) => x.color_at(position, config),
+            SomeColorizer::Flag(x) => x.color_at(position, config),
+            SomeColorizer::Image(x) => x.color_at(position, config),
+            SomeColorizer::Category(x) => x.color_at(position, config),
+            SomeColorizer::Grid(x) => x.color_at(position, config),
+            SomeColorizer::VerticalGradient(x) => x.color_at(position, config),
+            SomeColorizer::Gradient(x) => x.color_at(position, config),
+            SomeColorizer::RadialGradient(x) => x.color_at(position, config),
+            SomeColorizer::Rainbow(x) => x.color_at(position, config),
+            SomeColorizer::Dyn(x) => x.color_at(position, config),
+        }
+    }
+}
+
+
+
+/// Wraps a reader, counting the number of bytes read through it
+struct CountingReader<R> {
+    inner: R,
+    count: usize,
 }
 
 
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let amount = self.inner.read(buf)?;
+        self.count += amount;
+        Ok(amount)
+    }
+}
 
-fn open_path(path: impl AsRef<Path>) -> Result<Box<dyn Read>> {
+
+fn open_path(path: impl AsRef<Path>) -> std::io::Result<Box<dyn Read>> {
     if path.as_ref() == Path::new("-") {
         Ok(Box::new(stdin().lock()))
     } else {
-        match fs::File::open(path.as_ref()) {
-            Ok(file) => Ok(Box::new(BufReader::new(file))),
-            Err(e) => Err(anyhow!("\"{}\": {e}\n", path.as_ref().display()))
+        let file = fs::File::open(path.as_ref())?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+
+/// Print an error to stderr in the requested format. Errors relating to a particular file should
+/// give its path.
+fn report_error(format: ErrorFormat, error: &anyhow::Error, path: Option<&Path>) {
+    let message = match format {
+        ErrorFormat::Human => match path {
+            Some(path) => format!("\"{}\": {error}", path.display()),
+            None => format!("Error: {error:?}"),
+        },
+        ErrorFormat::Json => {
+            let path = match path {
+                Some(path) => json_string(&path.display().to_string()),
+                None => "null".to_string(),
+            };
+            format!("{{\"error\": {}, \"path\": {path}}}", json_string(&format!("{error:#}")))
+        },
+    };
+
+    // There's nowhere left to report a failure to write to stderr
+    let _ = writeln!(stderr(), "{message}");
+}
+
+
+/// Create the file given by --output. With no_clobber an existing file is an error, while with
+/// backup it is first renamed to "file~".
+fn open_output_file(path: &Path, no_clobber: bool, backup: bool) -> Result<File> {
+    if no_clobber {
+        return OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                ErrorKind::AlreadyExists => anyhow!("Output file {} already exists", path.display()),
+                _ => e.into(),
+            });
+    }
+
+    if backup && path.exists() {
+        let mut backup_path = path.as_os_str().to_owned();
+        backup_path.push("~");
+        fs::rename(path, &backup_path)
+            .with_context(|| format!("Couldn't back up output file {}", path.display()))?;
+    }
+
+    Ok(File::create(path)?)
+}
+
+
+/// Expand a --between-files value into the text to output. "rule" is a full width horizontal line,
+/// and otherwise backslash escapes are expanded.
+fn expand_separator(separator: &str, width: Option<usize>) -> String {
+    if separator == "rule" {
+        return "─".repeat(width.unwrap_or(80)) + "\n";
+    }
+
+    let mut expanded = String::with_capacity(separator.len());
+    let mut chars = separator.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => expanded.push('\n'),
+            Some('t') => expanded.push('\t'),
+            Some('\\') => expanded.push('\\'),
+            Some(other) => {
+                expanded.push('\\');
+                expanded.push(other);
+            },
+            None => expanded.push('\\'),
+        }
+    }
+    expanded
+}
+
+
+/// Name to show for an input path, which is the given label for stdin
+fn display_name<'a>(path: &'a Path, stdin_name: &'a Path) -> &'a Path {
+    if path == Path::new("-") {
+        stdin_name
+    } else {
+        path
+    }
+}
+
+
+/// Find the most prominent colors in an image, by quantizing it down to the given number of colors
+fn extract_palette(path: &Path, size: usize) -> Result<Vec<Color>> {
+    let img = open(path)
+        .with_context(|| format!("Couldn't open palette image {}", path.display()))?
+        .to_rgba8();
+
+    // Sample every 10th pixel, which is plenty for finding a small palette
+    let quantizer = NeuQuant::new(10, size, img.as_raw());
+    Ok(quantizer.color_map_rgb()
+        .chunks_exact(3)
+        .map(|rgb| Color::from_rgb(rgb[0], rgb[1], rgb[2]))
+        .collect())
+}
+
+
+/// Build the colorizer chosen by a manifest rule. Frequency options still apply to flags.
+fn manifest_colorizer(rule: &ManifestRule, flag_opts: &FlagOpts, config: &ColorizerConfig) -> Result<SomeColorizer> {
+    if let Some(name) = &rule.flag {
+        let preset = flag_by_name(name)
+            .ok_or_else(|| anyhow!("Invalid preset name {name} in manifest! - Use --presets to list all available flag presets"))?;
+        return Ok(SomeColorizer::Flag(flag_opts.build(Flag::from_preset(&preset), config)));
+    }
+
+    let mode = rule.mode.as_deref().unwrap_or("flag");
+    let mut params = ModeParams::default();
+    for (key, value) in &rule.params {
+        params.insert(key.clone(), value.clone());
+    }
+    Ok(SomeColorizer::Dyn(ModeRegistry::default().build(mode, &params, config)?))
+}
+
+
+/// The comment line written by --emit-palette-comment. Arguments are quoted where needed so the
+/// command can be pasted back into a shell. Arguments which aren't valid unicode are shown lossily.
+fn palette_comment<I>(args: I, config: &ColorizerConfig) -> String
+    where I: IntoIterator<Item = OsString> {
+    let command = args.into_iter()
+        .map(|arg| {
+            let arg = arg.to_string_lossy().into_owned();
+            let plain = !arg.is_empty() && arg.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.,/=:@+%".contains(c));
+            if plain {
+                arg
+            } else {
+                format!("'{}'", arg.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let width = config.wraps_after.map_or("none".to_string(), |width| width.to_string());
+    format!("# {command} (color mode {}, width {width}){}", config.color_mode, config.newline_style.terminator())
+}
+
+
+/// Seed for a random number generator derived from a path, which is the same on every run
+fn path_seed(path: &Path) -> u64 {
+    // FNV-1a, since the standard library's hashers aren't guaranteed to be stable
+    path.as_os_str().as_encoded_bytes().iter()
+        .fold(0xCBF2_9CE4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3))
+}
+
+
+/// True if the path refers to a regular file, rather than stdin, a pipe or a device
+fn is_regular_file(path: &Path) -> bool {
+    path != Path::new("-") && fs::metadata(path).is_ok_and(|metadata| metadata.is_file())
+}
+
+
+/// Count the lines in a file, if it is a regular file that can be read. Pipes and the like are
+/// never read, since their contents would then be used up
+fn count_lines(path: &Path) -> Option<usize> {
+    if !is_regular_file(path) {
+        return None;
+    }
+
+    let mut file = File::open(path).ok()?;
+    let mut buffer = vec![0; DEFAULT_READ_BUFFER_SIZE];
+    let mut newlines = 0;
+    let mut last = None;
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => {
+                newlines += buffer[..n].iter().filter(|&&b| b == b'\n').count();
+                last = Some(buffer[n - 1]);
+            },
+            Err(e) if e.kind() == ErrorKind::Interrupted => {},
+            Err(_) => return None,
         }
     }
+
+    // The last line may not end in a newline
+    if last.is_some_and(|b| b != b'\n') {
+        Some(newlines + 1)
+    } else {
+        Some(newlines)
+    }
+}
+
+
+/// Determine the number of lines the output will span. This is the length of the input if it is a
+/// single file, or otherwise the height of the terminal if writing to one. A followed file is
+/// still growing, so its current length isn't counted.
+fn resolve_height(files: &[PathBuf], follow: bool, detected: Option<usize>) -> Option<usize> {
+    match files {
+        [path] if !follow && path != Path::new("-") => count_lines(path),
+        _ if stdout().is_terminal() => detected,
+        _ => None,
+    }
+}
+
+
+/// Modes which are sized to the height of the output
+const HEIGHT_MODES: &[&str] = &["gradient", "linear-gradient", "radial"];
+
+
+/// True if the chosen colorizer, or any colorizer chosen by the manifest, is sized to the height
+/// of the output. Working out the height can mean reading the whole input an extra time, so it is
+/// skipped otherwise
+fn needs_height(colorizer: &ColorizerOpts, manifest: Option<&Manifest>) -> bool {
+    let height_mode = |mode: &str| HEIGHT_MODES.iter().any(|name| name.eq_ignore_ascii_case(mode));
+
+    if manifest.is_some_and(|manifest| manifest.modes().any(height_mode)) {
+        return true;
+    }
+
+    if colorizer.grid {
+        return false;
+    }
+    if let Some(mode) = &colorizer.mode {
+        return height_mode(mode);
+    }
+
+    let gradient = &colorizer.gradient;
+    let radial = &colorizer.radial;
+    gradient.gradient_vertical.is_some()
+        || (gradient.gradient.is_some() && gradient.gradient_height.is_none()
+            && gradient.gradient_direction != GradientDirection::Horizontal)
+        || (radial.radial.is_some() && radial.radial_height.is_none() && radial.radial_center.is_none())
 }
 
 
-fn main() -> Result<()> {
+/// Work out the color mode, and whether colors are output at all, from the various flags that
+/// affect them. Flags which directly contradict each other are an error, while flags that are
+/// made redundant by others only give a warning.
+fn resolve_color_flags(args: &Opt) -> Result<(ColorMode, bool)> {
+    // --disable-rgb24 is kept as an alias of --color-mode palette8
+    let color_mode = match (args.color_mode, args.disable_rgb24) {
+        (Some(ColorMode::Truecolor), true) => {
+            return Err(anyhow!("--color-mode truecolor contradicts --disable-rgb24"));
+        },
+        (Some(mode), _) => mode,
+        (None, true) => ColorMode::Palette8,
+        // Only trust the terminal's answer if it gives one, which is more reliable than guessing
+        (None, false) => match args.probe_terminal.then(probe_truecolor).flatten() {
+            Some(true) => ColorMode::Truecolor,
+            Some(false) => ColorMode::Palette8,
+            None if args.color_detect || !args.no_color_detect => color_mode_from_env().unwrap_or_default(),
+            None => ColorMode::default(),
+        },
+    };
+
+    // An explicit --color-when takes precedence over NO_COLOR
+    let color_when = match args.color_when {
+        Some(when) => when,
+        None if env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) => ColorWhen::Never,
+        None => ColorWhen::Always,
+    };
+
+    if args.no_color && args.color_when == Some(ColorWhen::Always) {
+        return Err(anyhow!("--no-color contradicts --color-when always"));
+    }
+
+    let emit_ansi = !args.no_color && match color_when {
+        ColorWhen::Always => true,
+        ColorWhen::Never => false,
+        ColorWhen::Auto => stdout().is_terminal() && args.output.is_none(),
+    };
+
+    if !emit_ansi && (args.color_mode.is_some() || args.disable_rgb24) && !args.colorizer.quiet {
+        eprintln!("Colors are disabled, so the color mode has no effect");
+    }
+
+    Ok((color_mode, emit_ansi))
+}
+
+
+/// Colorize the input once with each preset in turn, each after a line with the preset's name,
+/// pausing for the given number of milliseconds between presets
+fn loop_presets<O>(flag_opts: &FlagOpts, input: &[u8], mut output: O, config: &ColorizerConfig, delay: Option<u64>) -> Result<()>
+    where O: Write {
+    for (i, preset) in iter_flag_presets().enumerate() {
+        if let Some(delay) = delay.filter(|_| i > 0) {
+            output.flush()?;
+            thread::sleep(Duration::from_millis(delay));
+        }
+
+        // The name shouldn't take on the last color of the previous preset
+        if config.emit_ansi && config.output_format == OutputFormat::Ansi {
+            write!(output, "{RESET_CODE}")?;
+        }
+        write!(output, "{}{}", preset.name, config.newline_style.terminator())?;
+
+        let mut flag = flag_opts.build(Flag::from_preset(&preset), config);
+        flag.copy_colorized(input, &mut output, config)?;
+    }
+
+    Ok(())
+}
+
+
+/// Widths larger than this are assumed to be nonsense, as reported by some terminals mid-resize
+const MAX_PLAUSIBLE_WIDTH: usize = 100_000;
+
+
+/// Determine the column after which lines wrap. An override always wins, even if it is larger than
+/// the detected terminal width, since the user may want colors to span a wider logical line. A
+/// width of 0 means lines don't wrap at all.
+fn resolve_wrap_width(width_override: Option<usize>, detected: Option<usize>) -> Option<usize> {
+    let detected = detected.map(|width| width.min(MAX_PLAUSIBLE_WIDTH));
+
+    width_override.or(detected)
+        .filter(|&width| width > 0)
+}
+
+
+fn main() -> ExitCode {
     let args = Opt::parse();
+    let error_format = args.error_format;
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            report_error(error_format, &e, None);
+            ExitCode::FAILURE
+        },
+    }
+}
+
+
+fn run(args: Opt) -> Result<()> {
+    let (color_mode, emit_ansi) = resolve_color_flags(&args)?;
+    let manifest = args.manifest.as_deref().map(Manifest::load).transpose()?;
 
     // Construct colorizer config
     let config = ColorizerConfig {
-        wraps_after: args.width_override
-            .or_else(|| term_size::dimensions()
-                .map(|x| x.0)),
+        wraps_after: resolve_wrap_width(
+            args.width_override,
+            term_size::dimensions().map(|x| x.0)),
+
+        pattern_width: args.pattern_width.map(|width| width as usize),
+
+        origin: args.at_position.map_or((0, 0), |Position(x, y)| (x, y)),
+
+        height: needs_height(&args.colorizer, manifest.as_ref())
+            .then(|| resolve_height(&args.files, args.follow, term_size::dimensions().map(|x| x.1)))
+            .flatten(),
+
+        color_mode,
+        color_target: args.target,
+
+        carriage_return: StripCr::carriage_return_mode(args.strip_cr),
+
+        // Lines are numbered from 1 on the command line
+        from_line: args.from_line.map_or(0, |line| line as usize - 1),
+        to_line: args.to_line.map(|line| line as usize - 1),
+        recolor_range: args.recolor_range,
+
+        sample_rate: args.sample_rate as usize,
+        transpose: args.transpose,
+
+        render_overstrike: args.render_overstrike,
+        inherit_color_across_ansi: args.inherit_color_across_ansi,
+        erase_resets_column: args.erase_resets_column,
+
+        ambiguous_width: args.ambiguous_width as usize,
+        wrap_indent: args.wrap_indent,
+        wide_wrap: args.truncate_wide,
 
-        supports_rgb24: !args.disable_rgb24,
+        blend_space: args.blend_space,
+
+        tab_replacement: args.replace_tabs_with,
+        space_replacement: args.show_spaces.map(String::from),
+        emit_ansi,
+        output_format: args.output_format,
+        newline_style: args.newline_style,
+        read_buffer_size: args.read_buffer_size as usize,
+        stats: args.stats.then(Default::default),
+        quantize_palette: args.palette_from.as_deref()
+            .map(|path| extract_palette(path, args.palette_size as usize))
+            .transpose()?,
+        palette_metric: args.palette_metric,
+        running_palette: args.max_colors.map(|n| Rc::new(RefCell::new(RunningPalette::new(n as usize)))),
 
         ..Default::default()
     };
 
+    // User presets need to be available when listing presets too
+    if let Some(path) = &args.colorizer.flag.preset_file {
+        load_user_presets(path)?;
+    }
+
+    // Presets are only reloaded for as long as the watcher is kept around
+    #[cfg(feature = "watch")]
+    let _watcher = match &args.colorizer.flag.preset_file {
+        Some(path) if args.colorizer.flag.watch_presets => Some(watch_presets(path)?),
+        _ => None,
+    };
+
     // Try for early exit before locking stdout (since early exit behavior probably uses it) and
     // before opening input files (since they will never be used)
     if args.colorizer.try_early_exit()? {
         return Ok(());
     }
 
-    // Lock output now, it doesn't need to be relocked repeatedly
-    let mut output = BufWriter::new(stdout().lock());
+    // Other formats rely on knowing the color of each grapheme, so only apply to recoloring
+    let recoloring = !args.colorizer.noop.noop && !args.colorizer.image.is_rendering();
+    if config.output_format != OutputFormat::Ansi && !recoloring {
+        return Err(anyhow!("--output-format can't be used with --noop or --image-mode render"));
+    }
+
+    // Lock output now, it doesn't need to be relocked repeatedly. Timed flushes happen on
+    // another thread, so that output is locked on each write instead
+    let mut output: Box<dyn Write> = match (&args.output, args.flush_timeout.filter(|_| stdout().is_terminal())) {
+        (Some(path), _) => Box::new(BufWriter::new(open_output_file(path, args.no_clobber, args.backup)?)),
+        (None, Some(timeout)) => Box::new(TimedFlushWriter::new(stdout(), Duration::from_millis(timeout))),
+        (None, None) => Box::new(BufWriter::new(stdout().lock())),
+    };
+
+    // Rendering an image doesn't read any input, so it replaces colorizing entirely
+    if let Some(render) = args.colorizer.image.try_into_render(&config) {
+        render?.render(&mut output, &config)?;
+        return Ok(());
+    }
+
+    let suggest_render = args.colorizer.image.is_recoloring() && !args.colorizer.quiet;
+
+    // The comment isn't valid in the other output formats, and there is no palette without colors
+    if args.emit_palette_comment && recoloring && config.output_format == OutputFormat::Ansi {
+        write!(output, "{}", palette_comment(env::args_os(), &config))?;
+    }
+
+    // Only the last file can be followed, as the files are read one after another
+    let follow_last = args.follow && args.files.last().is_some_and(|path| is_regular_file(path));
 
     let input = args.files.iter()
-        .map(open_path);
+        .enumerate()
+        .map(|(i, path)| {
+            let f = open_path(path);
+            if follow_last && i + 1 == args.files.len() {
+                (path, f.map(|f| -> Box<dyn Read> { Box::new(FollowReader::new(f)) }))
+            } else {
+                (path, f)
+            }
+        });
+
+    #[cfg(feature = "encoding")]
+    let input = {
+        let encoding = args.input_encoding.as_deref()
+            .map(decoding::encoding_by_label)
+            .transpose()?;
+
+        input.map(move |(path, i)| match encoding {
+            Some(encoding) => (path, i.map(|f| decoding::decode_input(f, encoding))),
+            None => (path, i),
+        })
+    };
+
+    // Columns are aligned before colorizing, so that colors follow the padded layout
+    let block_lines = args.elastic_block_lines as usize;
+    let input = input.map(|(path, i)| if args.elastic_tabs {
+        let aligned = i.map(|f| -> Box<dyn Read> {
+            Box::new(ElasticTabsReader::new(f, block_lines, config.tab_size, config.ambiguous_width))
+        });
+        (path, aligned)
+    } else {
+        (path, i)
+    });
+
+    if args.colorizer.image.animate {
+        if config.output_format != OutputFormat::Ansi {
+            return Err(anyhow!("--animate can only be used with --output-format ansi"));
+        }
+        let mut frames = args.colorizer.image.load_frames(&config)?;
+
+        // Each frame colors the same input, so read it all up front
+        let mut buffered = Vec::new();
+        for (path, i) in input {
+            match i {
+                Ok(mut f) => {
+                    f.read_to_end(&mut buffered)?;
+                },
+                Err(e) => report_error(args.error_format, &e.into(), Some(display_name(path, &args.stdin_name))),
+            }
+        }
+
+        let _interrupt = catch_interrupt();
+        play_animation(&mut frames, &buffered, &mut output, &config)?;
+        return Ok(());
+    }
+
+    if args.colorizer.flag.loop_presets {
+        // Each preset colors the same input, so read it all up front
+        let mut buffered = Vec::new();
+        for (path, i) in input {
+            match i {
+                Ok(mut f) => {
+                    f.read_to_end(&mut buffered)?;
+                },
+                Err(e) => report_error(args.error_format, &e.into(), Some(display_name(path, &args.stdin_name))),
+            }
+        }
+
+        let delay = args.colorizer.flag.loop_delay.filter(|_| stdout().is_terminal());
+        loop_presets(&args.colorizer.flag, &buffered, &mut output, &config, delay)?;
+        return Ok(());
+    }
+
+    let separator = args.between_files.as_deref()
+        .map(|separator| expand_separator(separator, config.wraps_after));
+    // The whole separator is shown, whichever lines of the files are selected
+    let separator_config = ColorizerConfig {
+        from_line: 0,
+        to_line: None,
+        ..config.clone()
+    };
 
+    let flag_opts = args.colorizer.flag.clone();
+    let quiet = args.colorizer.quiet;
     let mut colorizer = args.colorizer.try_into_colorizer(&config)?;
-    for i in input {
+
+    if let Some(Position(x, y)) = args.color_at {
+        let Some(color) = colorizer.color_at((x, y), &config)
+            else {
+                return Err(anyhow!("The chosen colorizer doesn't color by position"));
+            };
+        writeln!(output, "{color}")?;
+        return Ok(());
+    }
+    // Only the default flag is replaced, as any other flag must have been chosen explicitly
+    let seed_from_path = flag_opts.color_seed_from_path && matches!(colorizer, SomeColorizer::Flag(_));
+    let mut total_read = 0;
+    let mut first_file = true;
+    for (path, i) in input {
         match i {
-            Ok(f) => colorizer.copy_colorized(f, &mut output, &config)?,
-            Err(e) => {write!(stderr(), "{e}")?},
+            Ok(f) => {
+                if seed_from_path {
+                    let name = display_name(path, &args.stdin_name);
+                    let preset = random_flag_preset(&mut StdRng::seed_from_u64(path_seed(name)));
+                    if !quiet {
+                        eprintln!("Using the {} flag for {}", preset.name, name.display());
+                    }
+                    colorizer = SomeColorizer::Flag(flag_opts.build(Flag::from_preset(&preset), &config));
+                }
+
+                // Files matching the manifest get their own colorizer
+                let name = display_name(path, &args.stdin_name);
+                let mut file_colorizer = match manifest.as_ref().and_then(|manifest| manifest.rule_for(name)) {
+                    Some(rule) => Some(manifest_colorizer(rule, &flag_opts, &config)?),
+                    None => None,
+                };
+                let colorizer = file_colorizer.as_mut().unwrap_or(&mut colorizer);
+
+                if let Some(separator) = separator.as_ref().filter(|_| !first_file) {
+                    colorizer.copy_colorized(separator.as_bytes(), &mut output, &separator_config)?;
+                }
+                first_file = false;
+
+                let mut counted = CountingReader { inner: f, count: 0 };
+                colorizer.copy_colorized(&mut counted, &mut output, &config)?;
+                total_read += counted.count;
+            },
+            Err(e) => report_error(args.error_format, &e.into(), Some(display_name(path, &args.stdin_name))),
         }
     }
 
+    if let Some(stats) = &config.stats {
+        output.flush()?;
+        let stats = stats.borrow();
+        eprintln!("Bytes read:          {total_read}");
+        eprintln!("Lines:               {}", stats.lines);
+        eprintln!("Graphemes:           {}", stats.graphemes);
+        eprintln!("Non UTF-8 bytes:     {}", stats.non_utf8_bytes);
+        eprintln!("Distinct colors:     {}", stats.distinct_colors());
+        eprintln!("Input colors:        {}", stats.distinct_input_colors());
+        eprintln!("Escapes forwarded:   {}", stats.ansi_forwarded);
+        eprintln!("Escapes discarded:   {}", stats.ansi_discarded);
+    }
+
+    // Recoloring nothing probably means the user wanted to see the image itself
+    if suggest_render && total_read == 0 {
+        writeln!(stderr(), "No input text was given to recolor - use --image-mode render to display the image itself")?;
+    }
+
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prettycat::stream_colors::PositionalRecolorizer;
+
+    /// Parse command line arguments, as given after the program name
+    fn opts(args: &[&str]) -> Opt {
+        Opt::try_parse_from(["prettycat"].iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn box_resize_averages_covered_pixels() {
+        let img = RgbImage::from_fn(4, 2, |x, _| if x < 2 { Rgb([0, 0, 0]) } else { Rgb([200, 100, 50]) });
+
+        let halved = box_resize(&img, 2, 1);
+        assert_eq!(halved.get_pixel(0, 0), &Rgb([0, 0, 0]));
+        assert_eq!(halved.get_pixel(1, 0), &Rgb([200, 100, 50]));
+
+        let whole = box_resize(&img, 1, 1);
+        assert_eq!(whole.get_pixel(0, 0), &Rgb([100, 50, 25]));
+
+        // Enlarging repeats pixels
+        let doubled = box_resize(&img, 8, 4);
+        assert_eq!(doubled.get_pixel(3, 3), &Rgb([0, 0, 0]));
+        assert_eq!(doubled.get_pixel(4, 3), &Rgb([200, 100, 50]));
+    }
+
+    #[test]
+    fn palette_comment_quotes_arguments() {
+        let config = ColorizerConfig {
+            wraps_after: Some(80),
+            ..Default::default()
+        };
+        let args = ["prettycat", "--flag", "trans", "it's here.txt"].map(OsString::from);
+
+        assert_eq!(palette_comment(args, &config),
+                   "# prettycat --flag trans 'it'\\''s here.txt' (color mode truecolor, width 80)\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn palette_comment_allows_non_unicode_arguments() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let config = ColorizerConfig::default();
+        let args = [OsString::from("prettycat"), OsString::from_vec(b"bad\xFF.txt".to_vec())];
+
+        assert_eq!(palette_comment(args, &config),
+                   "# prettycat 'bad\u{FFFD}.txt' (color mode truecolor, width none)\n");
+    }
+
+    #[test]
+    fn weighted_colors_default_to_one() {
+        let stripe: WeightedColor = "FF0000:2.5".parse().unwrap();
+        assert_eq!(stripe.color, Color::from_rgb(0xFF, 0, 0));
+        assert_eq!(stripe.weight, 2.5);
+
+        let stripe: WeightedColor = "00FF00".parse().unwrap();
+        assert_eq!(stripe.weight, 1f32);
+
+        assert!("FF0000:0".parse::<WeightedColor>().is_err());
+        assert!("FF0000:-1".parse::<WeightedColor>().is_err());
+        assert!("FF0000:x".parse::<WeightedColor>().is_err());
+        assert!("nope:2".parse::<WeightedColor>().is_err());
+    }
+
+    #[test]
+    fn strip_cr_chooses_a_carriage_return_mode() {
+        assert_eq!(StripCr::carriage_return_mode(None), CarriageReturnMode::Forward);
+        assert_eq!(StripCr::carriage_return_mode(Some("reset".parse().unwrap())), CarriageReturnMode::StripAndReset);
+        assert_eq!(StripCr::carriage_return_mode(Some("CONTINUE".parse().unwrap())), CarriageReturnMode::Strip);
+        assert!("sometimes".parse::<StripCr>().is_err());
+    }
+
+    #[test]
+    fn width_override_beats_the_terminal() {
+        assert_eq!(resolve_wrap_width(Some(200), Some(80)), Some(200));
+        assert_eq!(resolve_wrap_width(Some(40), Some(80)), Some(40));
+        assert_eq!(resolve_wrap_width(None, Some(80)), Some(80));
+        assert_eq!(resolve_wrap_width(None, None), None);
+    }
+
+    #[test]
+    fn absurd_terminal_widths_are_limited() {
+        assert_eq!(resolve_wrap_width(None, Some(usize::MAX)), Some(MAX_PLAUSIBLE_WIDTH));
+        assert_eq!(resolve_wrap_width(None, Some(0)), None);
+
+        // Overrides are taken as they are
+        assert_eq!(resolve_wrap_width(Some(MAX_PLAUSIBLE_WIDTH + 1), None), Some(MAX_PLAUSIBLE_WIDTH + 1));
+        assert_eq!(resolve_wrap_width(Some(0), Some(80)), None);
+    }
+
+    #[test]
+    fn repeat_stripes_keeps_the_flag_length() {
+        let stripes = vec![Color::WHITE, Color::BLACK];
+        let config = ColorizerConfig::default();
+
+        let once = opts(&["--hf", "0.1", "--vf", "0"]).colorizer.flag
+            .build(Flag::new(stripes.clone()).weights(Some(vec![2f32, 1f32])), &config);
+        let thrice = opts(&["--hf", "0.1", "--vf", "0", "--repeat-stripes", "3"]).colorizer.flag
+            .build(Flag::new(stripes).weights(Some(vec![2f32, 1f32])), &config);
+
+        assert_eq!(thrice.stripes.len(), 6);
+        assert_eq!(thrice.weights.as_deref(), Some(&[2f32, 1f32, 2f32, 1f32, 2f32, 1f32][..]));
+        assert!((thrice.hf - once.hf * 3f32).abs() < 1e-6);
+
+        assert!(Opt::try_parse_from(["prettycat", "--repeat-stripes", "0"]).is_err());
+    }
+
+    #[test]
+    fn positions_are_column_then_row() {
+        let Position(x, y) = " 3, 4".parse().unwrap();
+        assert_eq!((x, y), (3, 4));
+        assert!("3".parse::<Position>().is_err());
+        assert!("3,-4".parse::<Position>().is_err());
+        assert!(Opt::try_parse_from(["prettycat", "--at-position", "1,2"]).is_ok());
+    }
+
+    #[test]
+    fn pattern_width_overrides_fitting_to_the_terminal() {
+        let config = ColorizerConfig {
+            wraps_after: Some(80),
+            pattern_width: Some(10),
+            ..Default::default()
+        };
+
+        // Two stripes every 10 cells
+        let flag = opts(&[]).colorizer.flag.build(Flag::new(vec![Color::WHITE, Color::BLACK]), &config);
+        assert!((flag.hf - 0.2).abs() < 1e-6);
+        assert!((flag.vf - 0.2).abs() < 1e-6);
+
+        let img = RgbaImage::new(4, 2);
+        let fitted = opts(&["--image", "unused.png"]).colorizer.image
+            .fit_to_cells(&img, Color::BLACK, &config, 1f64)
+            .unwrap();
+        assert_eq!(fitted.width(), 10);
+
+        assert!(Opt::try_parse_from(["prettycat", "--pattern-width", "10", "--hf", "1"]).is_err());
+        assert!(Opt::try_parse_from(["prettycat", "--pattern-width", "0"]).is_err());
+    }
+
+    #[test]
+    fn flag_names_can_carry_overrides() {
+        let mut flag = opts(&["--flag", "pride"]).colorizer.flag;
+        assert_eq!(flag.take_preset_name().unwrap().as_deref(), Some("pride"));
+        assert_eq!((flag.hf, flag.vf), (None, None));
+
+        let mut flag = opts(&["--flag", "pride@hf=0.1, VF=0,deadzone=0.5"]).colorizer.flag;
+        assert_eq!(flag.take_preset_name().unwrap().as_deref(), Some("pride"));
+        assert_eq!((flag.hf, flag.vf, flag.deadzone), (Some(0.1), Some(0f32), 0.5));
+
+        for malformed in ["pride@hf", "pride@hf=fast", "pride@speed=1", "pride@"] {
+            let mut flag = opts(&["--flag", malformed]).colorizer.flag;
+            assert!(flag.take_preset_name().is_err(), "{malformed}");
+        }
+    }
+
+    #[test]
+    fn invert_stripes_flips_the_flag() {
+        let config = ColorizerConfig::default();
+        let args = ["--hf", "0", "--vf", "0.5", "--stripe-sharpness", "1"];
+        let flag = || Flag::new(vec![Color::WHITE, Color::BLACK]).weights(Some(vec![3f32, 1f32]));
+
+        let mut upright = opts(&args).colorizer.flag.build(flag(), &config);
+        assert_eq!(upright.get_color((0, 0)), Color::WHITE);
+
+        let mut inverted = opts(&[&args[..], &["--invert-stripes"]].concat()).colorizer.flag.build(flag(), &config);
+        assert_eq!(inverted.stripes, [Color::BLACK, Color::WHITE]);
+        assert_eq!(inverted.weights.as_deref(), Some(&[1f32, 3f32][..]));
+        assert_eq!(inverted.get_color((0, 0)), Color::BLACK);
+        assert_eq!(inverted.get_color((0, 2)), Color::WHITE);
+    }
+
+    #[test]
+    fn resize_to_cells_rounds_to_whole_rows() {
+        let config = ColorizerConfig::default();
+        let args = ["--image", "unused.png", "--image-width", "10", "--cell-aspect-ratio", "0.5"];
+        let fit = |extra: &[&str], img: &RgbaImage, pixels_per_row: f64| {
+            opts(&[&args[..], extra].concat()).colorizer.image
+                .fit_to_cells(img, Color::BLACK, &config, pixels_per_row)
+                .unwrap()
+        };
+
+        // 1.5 rows of cells is rounded up, where keeping the ratio rounds down
+        let img = RgbaImage::new(10, 3);
+        assert_eq!(fit(&[], &img, 1f64).dimensions(), (10, 1));
+        assert_eq!(fit(&["--image-resize-to-cells"], &img, 1f64).dimensions(), (10, 2));
+        assert_eq!(fit(&["--image-resize-to-cells"], &img, 2f64).dimensions(), (10, 4));
+
+        // There is always at least one row, whatever the height given
+        let wide = RgbaImage::new(100, 1);
+        assert_eq!(fit(&["--image-resize-to-cells", "--image-height", "7"], &wide, 1f64).dimensions(), (10, 1));
+    }
+
+    #[test]
+    fn stdin_is_shown_by_its_label() {
+        let args = opts(&[]);
+        assert_eq!(display_name(Path::new("-"), &args.stdin_name), Path::new("standard input"));
+
+        let args = opts(&["--stdin-name", "build log", "-", "notes.txt"]);
+        assert_eq!(display_name(Path::new("-"), &args.stdin_name), Path::new("build log"));
+        assert_eq!(display_name(Path::new("notes.txt"), &args.stdin_name), Path::new("notes.txt"));
+        assert_eq!(display_name(Path::new("./-"), &args.stdin_name), Path::new("./-"));
+    }
+
+    #[test]
+    fn color_flags_resolve_by_precedence() {
+        let resolve = |args: &[&str]| resolve_color_flags(&opts(args)).ok();
+
+        // --disable-rgb24 is an alias, which only contradicts an explicit truecolor
+        assert_eq!(resolve(&["--disable-rgb24", "--color-when", "always"]), Some((ColorMode::Palette8, true)));
+        assert_eq!(resolve(&["--disable-rgb24", "--color-mode", "palette256", "--color-when", "always"]),
+                   Some((ColorMode::Palette256, true)));
+        assert_eq!(resolve(&["--disable-rgb24", "--color-mode", "truecolor"]), None);
+
+        // Disabling colors keeps the color mode, which just goes unused
+        assert_eq!(resolve(&["--color-mode", "palette256", "--color-when", "never", "--quiet"]),
+                   Some((ColorMode::Palette256, false)));
+        assert_eq!(resolve(&["--no-color", "--color-mode", "palette8", "--quiet"]), Some((ColorMode::Palette8, false)));
+        assert_eq!(resolve(&["--no-color", "--color-when", "always"]), None);
+    }
+
+    #[test]
+    fn loop_presets_shows_every_preset() {
+        let config = ColorizerConfig {
+            emit_ansi: false,
+            ..Default::default()
+        };
+        let mut output = Vec::new();
+        loop_presets(&opts(&["--loop-presets"]).colorizer.flag, b"hello\n", &mut output, &config, None).unwrap();
+
+        let expected: String = iter_flag_presets()
+            .map(|preset| format!("{}\nhello\n", preset.name))
+            .collect();
+        assert_eq!(String::from_utf8(output).unwrap(), expected);
+    }
+
+    #[test]
+    fn paths_always_seed_the_same_flag() {
+        let preset_for = |path: &str| random_flag_preset(&mut StdRng::seed_from_u64(path_seed(Path::new(path)))).name;
+
+        // The hash is fixed, rather than changing between runs or builds
+        assert_eq!(path_seed(Path::new("a")), 0xAF63_DC4C_8601_EC8C);
+        assert_ne!(path_seed(Path::new("src/main.rs")), path_seed(Path::new("src/lib.rs")));
+        assert_eq!(preset_for("notes.txt"), preset_for("notes.txt"));
+    }
+
+    #[test]
+    fn pixelate_repeats_blocks_of_pixels() {
+        let config = ColorizerConfig::default();
+        let img = RgbaImage::from_fn(5, 5, |x, y| Rgba([x as u8 * 40, y as u8 * 40, 0, 255]));
+        let pixelated = opts(&["--image", "unused.png", "--image-width", "5", "--image-height", "5",
+                               "--image-filter", "nearest", "--image-pixelate", "2"])
+            .colorizer.image
+            .fit_to_cells(&img, Color::BLACK, &config, 1f64)
+            .unwrap();
+
+        // Each 2x2 block takes its top left pixel, with a partial block at the edges
+        assert_eq!(pixelated.dimensions(), (5, 5));
+        for (x, y, pixel) in pixelated.enumerate_pixels() {
+            assert_eq!(pixel, &Rgb([x as u8 / 2 * 80, y as u8 / 2 * 80, 0]), "({x}, {y})");
+        }
+
+        assert!(Opt::try_parse_from(["prettycat", "--image", "a.png", "--image-pixelate", "0"]).is_err());
+    }
+
+    #[test]
+    fn stripe_sharpness_sets_where_blending_starts() {
+        let config = ColorizerConfig::default();
+        let flag_with = |sharpness: &str| opts(&["--hf", "0.25", "--vf", "0", "--stripe-sharpness", sharpness])
+            .colorizer.flag
+            .build(Flag::new(vec![Color::WHITE, Color::BLACK]), &config);
+        let midpoint = config.blend_space.interpolate(Color::WHITE, Color::BLACK, 0.5);
+
+        // Cells 0 to 3 cover the first stripe, with the boundary between cells 3 and 4. Blending
+        // uses fixed point, so can be off by one in each channel
+        let mut smooth = flag_with("0");
+        assert!(smooth.get_color((0, 0)).is_close_to(Color::WHITE, 2));
+        assert!(smooth.get_color((2, 0)).is_close_to(midpoint, 2));
+
+        let mut half = flag_with("0.5");
+        assert!(half.get_color((2, 0)).is_close_to(Color::WHITE, 2));
+        assert!(half.get_color((3, 0)).is_close_to(midpoint, 2));
+
+        let mut hard = flag_with("1");
+        assert_eq!(hard.get_color((3, 0)), Color::WHITE);
+        assert_eq!(hard.get_color((4, 0)), Color::BLACK);
+
+        // Sharpness outside 0 to 1 is clamped
+        assert_eq!(flag_with("7").deadzone, 1f32);
+    }
+
+    #[test]
+    fn color_at_reports_the_flag_color() {
+        let config = ColorizerConfig::default();
+        let args = opts(&["--custom", "FF0000,0000FF", "--hf", "0.5", "--vf", "0.25",
+                          "--stripe-sharpness", "1", "--color-at", "2,0"]);
+        let Some(Position(x, y)) = args.color_at
+            else {
+                panic!("--color-at wasn't parsed");
+            };
+        let mut colorizer = args.colorizer.try_into_colorizer(&config).unwrap();
+
+        assert_eq!(colorizer.color_at((x, y), &config).unwrap().to_string(), "0000FF");
+        assert_eq!(colorizer.color_at((0, 0), &config), Some(Color::from_rgb(0xFF, 0, 0)));
+        assert_eq!(colorizer.color_at((0, 4), &config), Some(Color::from_rgb(0, 0, 0xFF)));
+        assert_eq!(colorizer.color_at((4, 0), &config), Some(Color::from_rgb(0xFF, 0, 0)));
+        assert_eq!(colorizer.color_at((2, 4), &config), Some(Color::from_rgb(0xFF, 0, 0)));
+    }
+}
@@ -0,0 +1,177 @@
+//! Manifests which choose a colorizer for each file, based on its path
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+
+/// A list of rules, read from a toml file such as:
+///
+/// ```toml
+/// [[rule]]
+/// path = "readme.md"
+/// flag = "pride"
+///
+/// [[rule]]
+/// path = "*.rs"
+/// mode = "gradient"
+/// params = { from = "000080", to = "00FFFF" }
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(default, rename = "rule")]
+    rules: Vec<ManifestRule>,
+}
+
+
+/// Colorizer to use for files matching a path pattern. Exactly one of flag or mode is given.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestRule {
+    /// Pattern where "*" matches any run of characters other than "/", and "?" matches any one of
+    /// them. Patterns without a "/" are matched against the file name alone.
+    pub path: String,
+    /// Name of a flag preset
+    pub flag: Option<String>,
+    /// Name of a mode, as listed by --modes
+    pub mode: Option<String>,
+    /// Parameters for the mode
+    #[serde(default)]
+    pub params: HashMap<String, String>,
+}
+
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read manifest {}", path.display()))?;
+        let manifest: Self = toml::from_str(&contents)
+            .with_context(|| format!("Invalid manifest {}", path.display()))?;
+
+        for rule in &manifest.rules {
+            if rule.flag.is_some() == rule.mode.is_some() {
+                return Err(anyhow!("Manifest rule for {} must give exactly one of flag or mode", rule.path));
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Names of the modes used by any rule
+    pub fn modes(&self) -> impl Iterator<Item = &str> {
+        self.rules.iter().filter_map(|rule| rule.mode.as_deref())
+    }
+
+    /// Find the first rule matching a path
+    pub fn rule_for(&self, path: &Path) -> Option<&ManifestRule> {
+        let path = path.to_string_lossy();
+        let file_name = path.rsplit('/').next().unwrap_or(&path);
+
+        self.rules.iter().find(|rule| {
+            if rule.path.contains('/') {
+                glob_matches(&rule.path, &path)
+            } else {
+                glob_matches(&rule.path, file_name)
+            }
+        })
+    }
+}
+
+
+/// Match text against a pattern of "*" and "?" wildcards, neither of which match "/"
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    // Backtrack to just after the last "*" on a mismatch, letting it swallow one more character
+    let (mut p, mut t) = (0, 0);
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            },
+            Some('?') if text[t] != '/' => {
+                p += 1;
+                t += 1;
+            },
+            Some(&c) if c != '?' && c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match star {
+                Some((star_p, star_t)) if text[star_t] != '/' => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                },
+                _ => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+        [[rule]]
+        path = "readme.md"
+        flag = "pride"
+
+        [[rule]]
+        path = "src/*.rs"
+        mode = "gradient"
+
+        [[rule]]
+        path = "*.rs"
+        flag = "trans"
+    "#;
+
+    /// Name of the flag or mode chosen for a path
+    fn chosen(manifest: &Manifest, path: &str) -> Option<String> {
+        manifest.rule_for(Path::new(path))
+            .map(|rule| rule.flag.clone().or(rule.mode.clone()).unwrap())
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let manifest: Manifest = toml::from_str(MANIFEST).unwrap();
+        assert_eq!(chosen(&manifest, "docs/readme.md").as_deref(), Some("pride"));
+        assert_eq!(chosen(&manifest, "src/main.rs").as_deref(), Some("gradient"));
+        assert_eq!(chosen(&manifest, "tests/cli.rs").as_deref(), Some("trans"));
+        assert_eq!(chosen(&manifest, "src/console/mod.rs").as_deref(), Some("trans"));
+        assert_eq!(chosen(&manifest, "Cargo.toml"), None);
+    }
+
+    #[test]
+    fn wildcards_stay_within_a_directory() {
+        assert!(glob_matches("*.rs", "main.rs"));
+        assert!(glob_matches("src/*.rs", "src/main.rs"));
+        assert!(!glob_matches("src/*.rs", "src/console/mod.rs"));
+        assert!(glob_matches("a?c*", "abcdef"));
+        assert!(!glob_matches("a?c", "a/c"));
+        assert!(!glob_matches("*.rs", "main.rs.bak"));
+    }
+
+    #[test]
+    fn rules_need_exactly_one_colorizer() {
+        let path = std::env::temp_dir().join(format!("prettycat-manifest-{}.toml", std::process::id()));
+        fs::write(&path, "[[rule]]\npath = \"*\"\nflag = \"pride\"\nmode = \"noop\"\n").unwrap();
+        assert!(Manifest::load(&path).is_err());
+
+        fs::write(&path, "[[rule]]\npath = \"*\"\n").unwrap();
+        assert!(Manifest::load(&path).is_err());
+
+        fs::write(&path, "[[rule]]\npath = \"*\"\nmode = \"noop\"\n").unwrap();
+        assert!(Manifest::load(&path).is_ok());
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,3 +1,12 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+#[cfg(feature = "watch")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use anyhow::{anyhow, Context, Result};
+use rand::Rng;
+use serde::Deserialize;
+
 use crate::console::Color;
 
 
@@ -6,7 +15,9 @@ use crate::console::Color;
 pub struct FlagPreset {
     pub name: &'static str,
     pub aliases: &'static [&'static str],
-    pub stripes: &'static [Color]
+    pub stripes: &'static [Color],
+    /// Relative width of each stripe, or None if all stripes have equal width
+    pub weights: Option<&'static [f32]>,
 }
 
 /// List of built-in preset flags. Currently only includes pride flags at the moment.
@@ -16,40 +27,312 @@ const FLAG_PRESETS: &[FlagPreset] = &[
         name: "Pride",
         aliases: &["Rainbow"],
         stripes: &hex_sequence([0xE40303, 0xFF8C00, 0xFFED00, 0x008026, 0x24408E, 0x732982]),
+        weights: None,
     },
     FlagPreset {
         name: "Progress",
         aliases: &[],
         stripes: &hex_sequence([0xE40303, 0xFF8C00, 0xFFED00, 0x008026, 0x24408E, 0x732982, 0x222222, 0x7c3f00, 0x5BCEFA, 0xF5A9B8, 0xFFFFFF]),
+        weights: None,
     },
     // "Sapphic" has a separate flag - should the stripes for this flag be added? (perhaps ignoring the flowers)
     FlagPreset {
         name: "Lesbian",
         aliases: &[],
         stripes: &hex_sequence([0xD52D00, 0xEF7627, 0xFF9A56, 0xFFFFFF, 0xD162A4, 0xB55690, 0xA30262]),
+        weights: None,
     },
     FlagPreset {
         name: "Gay",
         aliases: &[],
         stripes: &hex_sequence([0x078D70, 0x26CEAA, 0x98E8C1, 0xFFFFFF, 0x7BADE2, 0x5049CC, 0x3D1A78]),
+        weights: None,
     },
     FlagPreset {
         name: "Bi",
         aliases: &["Bisexual"],
-        stripes: &hex_sequence([0xD60270, 0xD60270, 0x9B4F96, 0x0038A8, 0x0038A8]),
+        stripes: &hex_sequence([0xD60270, 0x9B4F96, 0x0038A8]),
+        weights: Some(&[2.0, 1.0, 2.0]),
     },
     FlagPreset {
         name: "Trans",
         aliases: &["Transgender"],
         stripes: &hex_sequence([0x5BCEFA, 0xF5A9B8, 0xFFFFFF, 0xF5A9B8, 0x5BCEFA]),
+        weights: None,
+    },
+    FlagPreset {
+        name: "Pan",
+        aliases: &["Pansexual"],
+        stripes: &hex_sequence([0xFF218C, 0xFFD800, 0x21B1FF]),
+        weights: None,
+    },
+    FlagPreset {
+        name: "Ace",
+        aliases: &["Asexual"],
+        stripes: &hex_sequence([0x000000, 0xA3A3A3, 0xFFFFFF, 0x800080]),
+        weights: None,
+    },
+    FlagPreset {
+        name: "Aro",
+        aliases: &["Aromantic"],
+        stripes: &hex_sequence([0x3DA542, 0xA7D379, 0xFFFFFF, 0xA9A9A9, 0x000000]),
+        weights: None,
+    },
+    FlagPreset {
+        name: "Nonbinary",
+        aliases: &["NB", "Enby"],
+        stripes: &hex_sequence([0xFCF434, 0xFFFFFF, 0x9C59D1, 0x2C2C2C]),
+        weights: None,
+    },
+    FlagPreset {
+        name: "Agender",
+        aliases: &[],
+        stripes: &hex_sequence([0x000000, 0xBCC4C7, 0xFFFFFF, 0xB7F684, 0xFFFFFF, 0xBCC4C7, 0x000000]),
+        weights: None,
+    },
+    FlagPreset {
+        name: "Genderfluid",
+        aliases: &["Fluid"],
+        stripes: &hex_sequence([0xFF75A2, 0xFFFFFF, 0xBE18D6, 0x000000, 0x333EBD]),
+        weights: None,
     },
 ];
 
 
+/// Presets loaded from the user's preset files, which follow the built-in presets
+static USER_PRESETS: RwLock<Vec<FlagPreset>> = RwLock::new(Vec::new());
+
+/// Incremented whenever the user presets are reloaded, so that anything resolved from them can
+/// tell when it is out of date
+#[cfg(feature = "watch")]
+static PRESETS_GENERATION: AtomicUsize = AtomicUsize::new(0);
+
+/// Limit on how deeply preset files may include each other
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+
+/// Load flag presets from a file, so that they can be used like any built-in preset. Each line
+/// is either a preset in the form "name, alias, ... = color, color * weight, ...", an
+/// "@include path" of another preset file (relative to this one), a "#" comment, or blank.
+/// Aliases and weights are optional, and stripes without a weight have a weight of 1. Files
+/// ending in ".toml" are instead read as a [TomlPresets] file, which supports the same options.
+/// Presets with the same name as an existing preset replace it.
+pub fn load_user_presets(path: &Path) -> Result<()> {
+    let mut presets = Vec::new();
+    parse_preset_file(path, &mut Vec::new(), &mut presets)?;
+
+    let mut user_presets = USER_PRESETS.write().unwrap_or_else(|e| e.into_inner());
+    for preset in presets {
+        add_preset(&mut user_presets, preset);
+    }
+    Ok(())
+}
+
+
+/// Replace all user presets with those in a file, after it has changed. On error, the existing
+/// presets are kept.
+#[cfg(feature = "watch")]
+pub fn reload_user_presets(path: &Path) -> Result<()> {
+    let mut presets = Vec::new();
+    parse_preset_file(path, &mut Vec::new(), &mut presets)?;
+
+    *USER_PRESETS.write().unwrap_or_else(|e| e.into_inner()) = presets;
+    PRESETS_GENERATION.fetch_add(1, Ordering::Release);
+    Ok(())
+}
+
+
+/// Number of times the user presets have been reloaded
+#[cfg(feature = "watch")]
+pub fn presets_generation() -> usize {
+    PRESETS_GENERATION.load(Ordering::Acquire)
+}
+
+
+/// Parse a preset file and anything it includes. including lists the files currently being
+/// parsed, to detect cycles.
+fn parse_preset_file(path: &Path, including: &mut Vec<PathBuf>, presets: &mut Vec<FlagPreset>) -> Result<()> {
+    let canonical = fs::canonicalize(path)
+        .with_context(|| format!("Couldn't open preset file {}", path.display()))?;
+
+    if including.contains(&canonical) {
+        return Err(anyhow!("Preset file {} includes itself", path.display()));
+    }
+    if including.len() >= MAX_INCLUDE_DEPTH {
+        return Err(anyhow!("Preset files are nested more than {MAX_INCLUDE_DEPTH} includes deep, at {}", path.display()));
+    }
+
+    let contents = fs::read_to_string(&canonical)
+        .with_context(|| format!("Couldn't read preset file {}", path.display()))?;
+
+    if canonical.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+        let file: TomlPresets = toml::from_str(&contents)
+            .with_context(|| format!("Invalid preset file {}", path.display()))?;
+        for preset in file.presets {
+            let name = preset.name.clone();
+            add_preset(presets, preset.into_preset()
+                .with_context(|| format!("Invalid preset {name} in {}", path.display()))?);
+        }
+        return Ok(());
+    }
+
+    including.push(canonical.clone());
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        } else if let Some(include) = line.strip_prefix("@include").filter(|rest| rest.starts_with(char::is_whitespace)) {
+            let base = canonical.parent().unwrap_or(Path::new(""));
+            parse_preset_file(&base.join(include.trim()), including, presets)?;
+        } else {
+            let preset = parse_preset_line(line)
+                .with_context(|| format!("Invalid preset on line {} of {}", i + 1, path.display()))?;
+            add_preset(presets, preset);
+        }
+    }
+    including.pop();
+
+    Ok(())
+}
+
+
+/// Add a preset, replacing any earlier preset with the same name
+fn add_preset(presets: &mut Vec<FlagPreset>, preset: FlagPreset) {
+    presets.retain(|existing| !existing.name.eq_ignore_ascii_case(preset.name));
+    presets.push(preset);
+}
+
+
+/// Parse a preset of the form "name, alias, ... = color, color * weight, ...". Presets live for
+/// the rest of the program, so they are leaked to give them the same lifetime as the built-in
+/// presets.
+fn parse_preset_line(line: &str) -> Result<FlagPreset> {
+    let Some((names, stripes)) = line.split_once('=')
+        else {
+            return Err(anyhow!("Expected name = color, color, ..."));
+        };
+
+    let mut names = names.split(',').map(str::trim);
+    let name = names.next().unwrap_or_default();
+    if name.is_empty() {
+        return Err(anyhow!("Preset has no name"));
+    }
+    let aliases: Vec<&'static str> = names
+        .filter(|alias| !alias.is_empty())
+        .map(|alias| &*String::leak(alias.to_string()))
+        .collect();
+
+    let mut colors = Vec::new();
+    let mut weights = Vec::new();
+    for stripe in stripes.split(',') {
+        let (color, weight) = match stripe.split_once('*') {
+            Some((color, weight)) => {
+                let weight = weight.trim();
+                (color, weight.parse().map_err(|e| anyhow!("Invalid weight {weight}: {e}"))?)
+            },
+            None => (stripe, 1f32),
+        };
+        let color = color.trim().trim_start_matches('#');
+        colors.push(color.parse().map_err(|e| anyhow!("Invalid color {color}: {e}"))?);
+        weights.push(weight);
+    }
+
+    // Leave out weights when they are all the same, as for presets without any
+    let weights = if weights.iter().all(|&w| w == 1f32) {
+        None
+    } else {
+        check_weights(&weights, colors.len())?;
+        Some(&*Vec::leak(weights))
+    };
+
+    Ok(FlagPreset {
+        name: String::leak(name.to_string()),
+        aliases: Vec::leak(aliases),
+        stripes: Vec::leak(colors),
+        weights,
+    })
+}
+
+
+/// Check that there is one weight for each stripe, and that each is positive
+fn check_weights(weights: &[f32], stripes: usize) -> Result<()> {
+    if weights.len() != stripes {
+        return Err(anyhow!("Got {} weights for {stripes} stripes! - Give one weight per stripe", weights.len()));
+    }
+    if weights.iter().any(|&w| !(w > 0f32 && w.is_finite())) {
+        return Err(anyhow!("Invalid stripe weights! - Weights must be positive"));
+    }
+    Ok(())
+}
+
+
+/// A preset file in toml, such as:
+///
+/// ```toml
+/// [[preset]]
+/// name = "Sunset"
+/// aliases = ["Dusk"]
+/// stripes = ["FF5E00", "FF9E00", "#F0C"]
+/// weights = [2, 1, 1]
+/// ```
+///
+/// Aliases and weights are optional.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlPresets {
+    #[serde(default, rename = "preset")]
+    presets: Vec<TomlPreset>,
+}
+
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlPreset {
+    name: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    stripes: Vec<String>,
+    weights: Option<Vec<f32>>,
+}
+
+
+impl TomlPreset {
+    /// Check the preset, leaking it to give it the same lifetime as the built-in presets
+    fn into_preset(self) -> Result<FlagPreset> {
+        if self.name.trim().is_empty() {
+            return Err(anyhow!("Preset has no name"));
+        }
+        if self.stripes.is_empty() {
+            return Err(anyhow!("Preset has no stripes"));
+        }
+
+        let stripes = self.stripes.iter()
+            .map(|color| color.trim().parse().map_err(|e| anyhow!("Invalid color {color}: {e}")))
+            .collect::<Result<Vec<Color>>>()?;
+
+        if let Some(weights) = &self.weights {
+            check_weights(weights, stripes.len())?;
+        }
+
+        let aliases = self.aliases.into_iter()
+            .map(|alias| &*String::leak(alias))
+            .collect();
+
+        Ok(FlagPreset {
+            name: String::leak(self.name.trim().to_string()),
+            aliases: Vec::leak(aliases),
+            stripes: Vec::leak(stripes),
+            weights: self.weights.map(|weights| &*Vec::leak(weights)),
+        })
+    }
+}
+
+
 /// Convert a fixed-size array of u32s to colors, such that \[0xABCDEF, ...] => \[Color(0xAB, 0xCD, 0XEF), ...].
 /// This const function allows for preset flags to be written easily without resorting to macros.
 const fn hex_sequence<const N: usize>(hexes: [u32; N]) -> [Color; N] {
-    let mut output = [Color::from_rgb(0, 0, 0); N];
+    let mut output = [Color::BLACK; N];
 
     let mut i = 0;
 
@@ -69,18 +352,30 @@ const fn hex_sequence<const N: usize>(hexes: [u32; N]) -> [Color; N] {
 }
 
 
-/// Iterate over all flag presets
+/// Iterate over all flag presets, built-in presets first. Built-in presets replaced by a user
+/// preset of the same name are skipped.
 pub fn iter_flag_presets() -> impl Iterator<Item=FlagPreset> {
-    FLAG_PRESETS.into_iter().copied()
+    let user_presets = USER_PRESETS.read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone();
+
+    let built_in = FLAG_PRESETS.iter()
+        .filter(|preset| !user_presets.iter().any(|user| user.name.eq_ignore_ascii_case(preset.name)))
+        .copied()
+        .collect::<Vec<_>>();
+
+    built_in.into_iter().chain(user_presets)
 }
 
-/// Find a flag preset by either its given name or any of its aliases
+/// Find a flag preset by either its given name or any of its aliases. Later presets take
+/// precedence, so that user presets can replace built-in ones.
 pub fn flag_by_name(name: &str) -> Option<FlagPreset> {
     iter_flag_presets()
-        .find(|flag| {
+        .filter(|flag| {
             flag.name.eq_ignore_ascii_case(name)
                 || flag.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
         })
+        .last()
 }
 
 
@@ -88,3 +383,121 @@ pub fn flag_by_name(name: &str) -> Option<FlagPreset> {
 pub fn default_flag_preset() -> FlagPreset {
     flag_by_name("lesbian").expect("This is a built in flag")
 }
+
+
+/// Pick a flag preset uniformly at random
+pub fn random_flag_preset(rng: &mut impl Rng) -> FlagPreset {
+    // Take one snapshot, since the user presets may be reloaded at any moment
+    let mut presets: Vec<_> = iter_flag_presets().collect();
+    let index = rng.gen_range(0..presets.len());
+    presets.swap_remove(index)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write preset files into a fresh directory, returning the directory
+    fn preset_dir(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("prettycat-presets-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        dir
+    }
+
+    fn parse(path: &Path) -> Result<Vec<FlagPreset>> {
+        let mut presets = Vec::new();
+        parse_preset_file(path, &mut Vec::new(), &mut presets)?;
+        Ok(presets)
+    }
+
+    fn names(presets: &[FlagPreset]) -> Vec<&str> {
+        presets.iter().map(|preset| preset.name).collect()
+    }
+
+    #[test]
+    fn includes_are_followed() {
+        let dir = preset_dir("chain", &[
+            ("main", "# comment\n@include middle\nmain = FF0000, 00FF00\n"),
+            ("middle", "@include\tbase\nmiddle = 0000FF\n"),
+            ("base", "base = FFFFFF, 000000\n"),
+        ]);
+        assert_eq!(names(&parse(&dir.join("main")).unwrap()), ["base", "middle", "main"]);
+    }
+
+    #[test]
+    fn include_cycles_are_rejected() {
+        let dir = preset_dir("cycle", &[
+            ("a", "@include b\n"),
+            ("b", "@include a\n"),
+        ]);
+        let error = parse(&dir.join("a")).unwrap_err();
+        assert!(error.to_string().contains("includes itself"), "{error}");
+    }
+
+    #[test]
+    fn include_needs_whitespace_after_it() {
+        // Without a space, this is a preset line with an odd name rather than an include
+        let dir = preset_dir("keyword", &[
+            ("main", "@includebase\n"),
+            ("base", "base = FFFFFF\n"),
+        ]);
+        assert!(parse(&dir.join("main")).is_err());
+    }
+
+    #[test]
+    fn later_presets_replace_earlier_ones() {
+        let dir = preset_dir("dedupe", &[
+            ("main", "@include base\nsunset = 000000\n"),
+            ("base", "Sunset = FFFFFF, FF0000\nother = 00FF00\n"),
+        ]);
+        let presets = parse(&dir.join("main")).unwrap();
+        assert_eq!(names(&presets), ["other", "sunset"]);
+        assert_eq!(presets[1].stripes, [Color::BLACK]);
+    }
+
+    #[test]
+    fn line_presets_take_aliases_and_weights() {
+        let preset = parse_preset_line("Sunset, Dusk = FF5E00 * 2, #FF9E00, F0C").unwrap();
+        assert_eq!(preset.name, "Sunset");
+        assert_eq!(preset.aliases, ["Dusk"]);
+        assert_eq!(preset.stripes.len(), 3);
+        assert_eq!(preset.weights, Some(&[2f32, 1f32, 1f32][..]));
+
+        let preset = parse_preset_line("plain = FFFFFF, 000000").unwrap();
+        assert!(preset.aliases.is_empty());
+        assert_eq!(preset.weights, None);
+
+        assert!(parse_preset_line("bad = FFFFFF * 0").is_err());
+        assert!(parse_preset_line("bad = FFFFFF * x").is_err());
+        assert!(parse_preset_line(", alias = FFFFFF").is_err());
+    }
+
+    #[test]
+    fn both_formats_give_the_same_preset() {
+        let dir = preset_dir("formats", &[
+            ("presets", "Sunset, Dusk = FF5E00 * 2, FF9E00\n"),
+            ("presets.toml", "[[preset]]\nname = \"Sunset\"\naliases = [\"Dusk\"]\nstripes = [\"FF5E00\", \"FF9E00\"]\nweights = [2, 1]\n"),
+        ]);
+        let line = parse(&dir.join("presets")).unwrap();
+        let toml = parse(&dir.join("presets.toml")).unwrap();
+        assert_eq!(format!("{line:?}"), format!("{toml:?}"));
+    }
+
+    #[test]
+    fn random_presets_follow_the_seed() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let pick = |seed| random_flag_preset(&mut StdRng::seed_from_u64(seed)).name;
+        assert_eq!(pick(7), pick(7));
+
+        // Every preset can be picked
+        let picked: std::collections::HashSet<_> = (0..500).map(pick).collect();
+        assert_eq!(picked.len(), iter_flag_presets().count());
+    }
+}
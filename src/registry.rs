@@ -0,0 +1,208 @@
+//! Registry of colorizer modes, so that colorizers can be selected and configured by name
+use std::collections::HashMap;
+use std::str::FromStr;
+use anyhow::{anyhow, Result};
+
+use crate::console::Color;
+use crate::presets::{default_flag_preset, flag_by_name};
+use crate::stream_colors::{Category, ColorizerConfig, Flag, Gradient, GradientDirection, Grid, Noop, RadialGradient, Rainbow, StreamColorizer, VerticalGradient};
+
+
+/// Parameters for a mode, given as key=value pairs
+#[derive(Debug, Default)]
+pub struct ModeParams(HashMap<String, String>);
+
+
+impl ModeParams {
+    pub fn insert(&mut self, key: String, value: String) {
+        self.0.insert(key.to_ascii_lowercase(), value);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+
+    /// Parse a parameter, falling back to a default if it isn't given
+    pub fn parse_or<T>(&self, key: &str, default: T) -> Result<T>
+        where T: FromStr,
+              T::Err: std::fmt::Display {
+        match self.get(key) {
+            Some(value) => value.parse().map_err(|e| anyhow!("Invalid value {value} for {key}: {e}")),
+            None => Ok(default),
+        }
+    }
+}
+
+
+/// Builds a colorizer from its parameters
+pub type ModeConstructor = Box<dyn Fn(&ModeParams, &ColorizerConfig) -> Result<Box<dyn StreamColorizer>>>;
+
+
+/// Maps mode names to the constructors of their colorizers
+pub struct ModeRegistry {
+    modes: Vec<(&'static str, &'static str, ModeConstructor)>,
+}
+
+
+impl ModeRegistry {
+    pub fn new() -> Self {
+        Self {
+            modes: Vec::new(),
+        }
+    }
+
+    /// Register a mode, along with a short description of its parameters. A mode registered with
+    /// the same name as an earlier mode replaces it.
+    pub fn register<F>(&mut self, name: &'static str, description: &'static str, constructor: F)
+        where F: Fn(&ModeParams, &ColorizerConfig) -> Result<Box<dyn StreamColorizer>> + 'static {
+        self.modes.retain(|(existing, _, _)| !existing.eq_ignore_ascii_case(name));
+        self.modes.push((name, description, Box::new(constructor)));
+    }
+
+    /// Iterate over the name and description of each mode
+    pub fn iter_modes(&self) -> impl Iterator<Item=(&'static str, &'static str)> + '_ {
+        self.modes.iter().map(|&(name, description, _)| (name, description))
+    }
+
+    /// Build the colorizer for a mode
+    pub fn build(&self, name: &str, params: &ModeParams, config: &ColorizerConfig) -> Result<Box<dyn StreamColorizer>> {
+        let Some((_, _, constructor)) = self.modes.iter().find(|(mode, _, _)| mode.eq_ignore_ascii_case(name))
+            else {
+                let names: Vec<_> = self.iter_modes().map(|(name, _)| name).collect();
+                return Err(anyhow!("Invalid mode {name}! - Expected one of {}", names.join(", ")));
+            };
+
+        constructor(params, config)
+    }
+}
+
+
+impl Default for ModeRegistry {
+    /// A registry containing each of the built-in modes
+    fn default() -> Self {
+        let mut registry = Self::new();
+
+        registry.register("noop", "no parameters", |_, _| Ok(Box::new(Noop)));
+
+        registry.register("flag", "preset, hf, vf, deadzone", |params, config| {
+            let preset = match params.get("preset") {
+                Some(name) => flag_by_name(name)
+                    .ok_or_else(|| anyhow!("Invalid preset name {name}! - Use --presets to list all available flag presets"))?,
+                None => default_flag_preset(),
+            };
+
+            let auto = Flag::auto_frequency(preset.stripes.len(), config.wraps_after);
+            Ok(Box::new(Flag::from_preset(&preset)
+                .hf(params.parse_or("hf", auto)?)
+                .vf(params.parse_or("vf", auto)?)
+                .deadzone(params.parse_or("deadzone", Flag::DEFAULT_DEADZONE)?)))
+        });
+
+        registry.register("gradient", "from, to", |params, config| {
+            Ok(Box::new(VerticalGradient {
+                from: params.parse_or("from", Color::BLACK)?,
+                to: params.parse_or("to", Color::WHITE)?,
+                height: config.height,
+                fallback_frequency: 0.05,
+                blend_space: config.blend_space,
+            }))
+        });
+
+        registry.register("linear-gradient", "start, end, direction, width, height", |params, config| {
+            Ok(Box::new(Gradient {
+                start: params.parse_or("start", Color::BLACK)?,
+                end: params.parse_or("end", Color::WHITE)?,
+                direction: params.parse_or("direction", GradientDirection::Horizontal)?,
+                width: params.parse_or("width", config.wraps_after.unwrap_or(Gradient::DEFAULT_WIDTH))?,
+                height: params.parse_or("height", config.height.unwrap_or(Gradient::DEFAULT_HEIGHT))?,
+                blend_space: config.blend_space,
+            }))
+        });
+
+        registry.register("radial", "inner, outer, x, y, radius", |params, config| {
+            let width = config.wraps_after.unwrap_or(Gradient::DEFAULT_WIDTH) as f32;
+            let height = config.height.unwrap_or(Gradient::DEFAULT_HEIGHT) as f32;
+            let radius = params.parse_or("radius", width / 2f32)?;
+            if radius <= 0f32 || !radius.is_finite() {
+                return Err(anyhow!("Invalid radial radius {radius}! - Must be greater than 0"));
+            }
+
+            Ok(Box::new(RadialGradient {
+                center: (
+                    params.parse_or("x", (width - 1f32).max(0f32) / 2f32)?,
+                    params.parse_or("y", (height - 1f32).max(0f32) / 2f32)?,
+                ),
+                inner: params.parse_or("inner", Color::WHITE)?,
+                outer: params.parse_or("outer", Color::BLACK)?,
+                radius,
+                blend_space: config.blend_space,
+            }))
+        });
+
+        registry.register("rainbow", "freq, spread, phase", |params, _| {
+            let spread = params.parse_or("spread", Rainbow::DEFAULT_SPREAD)?;
+            if spread <= 0f32 || !spread.is_finite() {
+                return Err(anyhow!("Invalid rainbow spread {spread}! - Must be greater than 0"));
+            }
+
+            Ok(Box::new(Rainbow {
+                freq: params.parse_or("freq", Rainbow::DEFAULT_FREQUENCY)?,
+                spread,
+                phase: params.parse_or("phase", 0f32)?,
+            }))
+        });
+
+        registry.register("category", "letter, digit, punctuation, other", |params, _| {
+            Ok(Box::new(Category::new(
+                params.parse_or("letter", Color::from_rgb(0xF5, 0xA9, 0xB8))?,
+                params.parse_or("digit", Color::from_rgb(0x5B, 0xCE, 0xFA))?,
+                params.parse_or("punctuation", Color::from_rgb(0xFF, 0xED, 0x00))?,
+                params.parse_or("other", Color::WHITE)?)))
+        });
+
+        registry.register("grid", "even, odd", |params, _| {
+            Ok(Box::new(Grid {
+                even: params.parse_or("even", Color::from_rgb(0xFF, 0x00, 0xFF))?,
+                odd: params.parse_or("odd", Color::from_rgb(0x00, 0xFF, 0x00))?,
+            }))
+        });
+
+        registry
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> ModeParams {
+        let mut params = ModeParams::default();
+        for &(key, value) in pairs {
+            params.insert(key.to_string(), value.to_string());
+        }
+        params
+    }
+
+    #[test]
+    fn radial_radius_must_be_positive() {
+        let registry = ModeRegistry::default();
+        let config = ColorizerConfig::default();
+        assert!(registry.build("radial", &params(&[]), &config).is_ok());
+        assert!(registry.build("radial", &params(&[("radius", "3")]), &config).is_ok());
+        assert!(registry.build("radial", &params(&[("radius", "0")]), &config).is_err());
+        assert!(registry.build("radial", &params(&[("radius", "-2")]), &config).is_err());
+        assert!(registry.build("radial", &params(&[("radius", "inf")]), &config).is_err());
+    }
+
+    #[test]
+    fn rainbow_spread_must_be_positive() {
+        let registry = ModeRegistry::default();
+        let config = ColorizerConfig::default();
+        assert!(registry.build("rainbow", &params(&[]), &config).is_ok());
+        assert!(registry.build("rainbow", &params(&[("spread", "0.5")]), &config).is_ok());
+        assert!(registry.build("rainbow", &params(&[("spread", "0")]), &config).is_err());
+        assert!(registry.build("rainbow", &params(&[("spread", "-1")]), &config).is_err());
+        assert!(registry.build("rainbow", &params(&[("spread", "NaN")]), &config).is_err());
+    }
+}
@@ -0,0 +1,666 @@
+//! Output backends, which decide how colorized text is written out
+use std::fmt;
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+use anyhow::anyhow;
+
+use crate::console::{Color, ConsoleElem, BOLD_CODE, DEFAULT_BACKGROUND_CODE, NORMAL_INTENSITY_CODE, NO_UNDERLINE_CODE, UNDERLINE_CODE};
+
+
+/// Format that colorized text is written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Text with ansi color codes, for display in a terminal
+    #[default]
+    Ansi,
+    /// An html fragment, with each grapheme in a colored span
+    Html,
+    /// An svg image, with each grapheme positioned in its cell
+    Svg,
+    /// One json object per grapheme, giving its position and color
+    Jsonl,
+}
+
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("ansi") {
+            Ok(Self::Ansi)
+        } else if s.eq_ignore_ascii_case("html") {
+            Ok(Self::Html)
+        } else if s.eq_ignore_ascii_case("svg") {
+            Ok(Self::Svg)
+        } else if s.eq_ignore_ascii_case("jsonl") {
+            Ok(Self::Jsonl)
+        } else {
+            Err(anyhow!("Invalid output format {s}! - Expected ansi, html, svg or jsonl"))
+        }
+    }
+}
+
+
+/// Line terminator written for each newline in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NewlineStyle {
+    /// "\n", as used on unix
+    #[default]
+    Lf,
+    /// "\r\n", as used on windows
+    Crlf,
+    /// A lone "\r", as used on classic Mac OS
+    Cr,
+}
+
+
+impl NewlineStyle {
+    pub fn terminator(self) -> &'static str {
+        match self {
+            NewlineStyle::Lf => "\n",
+            NewlineStyle::Crlf => "\r\n",
+            NewlineStyle::Cr => "\r",
+        }
+    }
+}
+
+
+impl FromStr for NewlineStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("lf") {
+            Ok(Self::Lf)
+        } else if s.eq_ignore_ascii_case("crlf") {
+            Ok(Self::Crlf)
+        } else if s.eq_ignore_ascii_case("cr") {
+            Ok(Self::Cr)
+        } else {
+            Err(anyhow!("Invalid newline style {s}! - Expected lf, crlf or cr"))
+        }
+    }
+}
+
+
+/// Kind of ansi color codes supported by the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorMode {
+    /// 24-bit rgb colors
+    #[default]
+    Truecolor,
+    /// The xterm 256 color palette
+    Palette256,
+    /// The 8 basic colors. Color reproduction is very poor
+    Palette8,
+}
+
+
+impl ColorMode {
+    /// Write the code to set the foreground color
+    pub fn write_fg<O: Write>(self, color: Color, output: O) -> io::Result<()> {
+        match self {
+            ColorMode::Truecolor => color.write_as_24bit_ansi(output),
+            ColorMode::Palette256 => color.write_as_256_ansi(output),
+            ColorMode::Palette8 => color.write_as_paletted_ansi(output),
+        }
+    }
+
+    /// The color which the terminal actually shows for a color
+    pub fn nearest(self, color: Color) -> Color {
+        match self {
+            ColorMode::Truecolor => color,
+            ColorMode::Palette256 => Color::from_ansi_index(color.to_ansi_index()),
+            ColorMode::Palette8 => color.to_palette8(),
+        }
+    }
+
+    /// Write the code to set the background color
+    pub fn write_bg<O: Write>(self, color: Color, output: O) -> io::Result<()> {
+        match self {
+            ColorMode::Truecolor => color.write_as_24bit_ansi_bg(output),
+            ColorMode::Palette256 => color.write_as_256_ansi_bg(output),
+            ColorMode::Palette8 => color.write_as_paletted_ansi_bg(output),
+        }
+    }
+}
+
+
+impl FromStr for ColorMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("truecolor") {
+            Ok(Self::Truecolor)
+        } else if s.eq_ignore_ascii_case("palette256") {
+            Ok(Self::Palette256)
+        } else if s.eq_ignore_ascii_case("palette8") {
+            Ok(Self::Palette8)
+        } else {
+            Err(anyhow!("Invalid color mode {s}! - Expected truecolor, palette256 or palette8"))
+        }
+    }
+}
+
+
+impl fmt::Display for ColorMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ColorMode::Truecolor => "truecolor",
+            ColorMode::Palette256 => "palette256",
+            ColorMode::Palette8 => "palette8",
+        })
+    }
+}
+
+
+/// Which part of each cell is colored in ansi output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorTarget {
+    /// Color the text itself
+    #[default]
+    Foreground,
+    /// Color the cell behind the text, leaving the text in the terminal's default color
+    Background,
+    /// Color both, so that each cell is a solid block of color
+    Both,
+}
+
+
+impl ColorTarget {
+    fn foreground(self) -> bool {
+        matches!(self, ColorTarget::Foreground | ColorTarget::Both)
+    }
+
+    fn background(self) -> bool {
+        matches!(self, ColorTarget::Background | ColorTarget::Both)
+    }
+}
+
+
+impl FromStr for ColorTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("foreground") {
+            Ok(Self::Foreground)
+        } else if s.eq_ignore_ascii_case("background") {
+            Ok(Self::Background)
+        } else if s.eq_ignore_ascii_case("both") {
+            Ok(Self::Both)
+        } else {
+            Err(anyhow!("Invalid color target {s}! - Expected foreground, background or both"))
+        }
+    }
+}
+
+
+/// Text styles which may be applied on top of a color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextStyle {
+    Bold,
+    Underline,
+}
+
+
+/// Destination for colorized text. The caller works out where each grapheme lies and what color it
+/// should be, while the sink decides how that is written out.
+pub trait Sink {
+    /// Apply a color, even if it matches the color already in use
+    fn set_color(&mut self, color: Color) -> io::Result<()>;
+
+    /// Write a grapheme in the cell at the given position
+    fn grapheme(&mut self, position: (usize, usize), grapheme: &str, color: Color) -> io::Result<()>;
+
+    /// Write a non-printing element, such as a newline or tab
+    fn control(&mut self, elem: ConsoleElem) -> io::Result<()>;
+
+    /// Forward an escape sequence from the input
+    fn escape(&mut self, esc_sequence: &str) -> io::Result<()>;
+
+    /// The color in use is no longer known, such as after a reset, so it must be applied again
+    /// before the next grapheme. Sinks which color each grapheme separately can ignore this.
+    fn forget_color(&mut self) {}
+
+    fn set_style(&mut self, style: TextStyle, enabled: bool) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// Called once the stream has ended
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+
+/// Tracks which [TextStyle]s are enabled, for sinks which apply them per grapheme
+#[derive(Debug, Clone, Copy, Default)]
+struct StyleState {
+    bold: bool,
+    underline: bool,
+}
+
+
+impl StyleState {
+    fn set(&mut self, style: TextStyle, enabled: bool) {
+        match style {
+            TextStyle::Bold => self.bold = enabled,
+            TextStyle::Underline => self.underline = enabled,
+        }
+    }
+}
+
+
+/// Writes ansi codes, only changing color when needed
+pub struct AnsiSink<W> {
+    output: W,
+    color_mode: ColorMode,
+    color_target: ColorTarget,
+    /// When false, no codes are written at all, leaving plain text
+    emit_ansi: bool,
+    newline_style: NewlineStyle,
+    color: Option<Color>,
+}
+
+
+impl<W: Write> AnsiSink<W> {
+    pub fn new(output: W, color_mode: ColorMode, color_target: ColorTarget, emit_ansi: bool, newline_style: NewlineStyle) -> Self {
+        Self {
+            output,
+            color_mode,
+            color_target,
+            emit_ansi,
+            newline_style,
+            color: None,
+        }
+    }
+
+    /// Terminals fill the rest of a line with the current background when scrolling, so it is
+    /// cleared at the end of each line, and applied again by the next grapheme
+    fn clear_background(&mut self) -> io::Result<()> {
+        if self.emit_ansi && self.color_target.background() && self.color.is_some() {
+            self.color = None;
+            write!(self.output, "{DEFAULT_BACKGROUND_CODE}")?;
+        }
+        Ok(())
+    }
+}
+
+
+impl<W: Write> Sink for AnsiSink<W> {
+    fn set_color(&mut self, color: Color) -> io::Result<()> {
+        self.color = Some(color);
+        if self.emit_ansi {
+            if self.color_target.foreground() {
+                self.color_mode.write_fg(color, &mut self.output)?;
+            }
+            if self.color_target.background() {
+                self.color_mode.write_bg(color, &mut self.output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn grapheme(&mut self, _: (usize, usize), grapheme: &str, color: Color) -> io::Result<()> {
+        // In the paletted modes, colors which map to the same palette entry look the same, so
+        // don't need the code written again
+        let unchanged = self.color
+            .is_some_and(|current| self.color_mode.nearest(current) == self.color_mode.nearest(color));
+        if !unchanged {
+            self.set_color(color)?;
+        }
+        write!(self.output, "{grapheme}")
+    }
+
+    fn control(&mut self, elem: ConsoleElem) -> io::Result<()> {
+        match elem {
+            ConsoleElem::Newline => {
+                self.clear_background()?;
+                write!(self.output, "{}", self.newline_style.terminator())
+            },
+            ConsoleElem::CrlfNewline => {
+                self.clear_background()?;
+                // The input's own line ending is kept unless a different one was chosen
+                match self.newline_style {
+                    NewlineStyle::Lf => write!(self.output, "\r\n"),
+                    style => write!(self.output, "{}", style.terminator()),
+                }
+            },
+            ConsoleElem::CarriageReturn => write!(self.output, "\r"),
+            ConsoleElem::Tab => write!(self.output, "\t"),
+            ConsoleElem::Backspace => write!(self.output, "\u{0008}"),
+            ConsoleElem::OtherNonPrinting(c) => write!(self.output, "{c}"),
+            ConsoleElem::NonUTF8Data(b) => self.output.write_all(&[b]),
+            ConsoleElem::Ansi(esc_sequence) => self.escape(esc_sequence),
+            ConsoleElem::Grapheme(grapheme) => write!(self.output, "{grapheme}"),
+        }
+    }
+
+    fn escape(&mut self, esc_sequence: &str) -> io::Result<()> {
+        if self.emit_ansi {
+            write!(self.output, "{esc_sequence}")?;
+        }
+        Ok(())
+    }
+
+    fn forget_color(&mut self) {
+        self.color = None;
+    }
+
+    fn set_style(&mut self, style: TextStyle, enabled: bool) -> io::Result<()> {
+        let code = match (style, enabled) {
+            (TextStyle::Bold, true) => BOLD_CODE,
+            (TextStyle::Bold, false) => NORMAL_INTENSITY_CODE,
+            (TextStyle::Underline, true) => UNDERLINE_CODE,
+            (TextStyle::Underline, false) => NO_UNDERLINE_CODE,
+        };
+        self.escape(code)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.clear_background()
+    }
+}
+
+
+/// Escape text for use in html or xml
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+
+/// Escape a string for use in JSON, including the surrounding quotes
+pub fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+
+/// Writes a `<pre>` block of html, with each grapheme in its own colored span
+pub struct HtmlSink<W> {
+    output: W,
+    started: bool,
+}
+
+
+impl<W: Write> HtmlSink<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            started: false,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            write!(self.output, "<pre>")?;
+        }
+        Ok(())
+    }
+}
+
+
+impl<W: Write> Sink for HtmlSink<W> {
+    fn set_color(&mut self, _: Color) -> io::Result<()> {
+        // Each grapheme is given its color directly
+        self.start()
+    }
+
+    fn grapheme(&mut self, _: (usize, usize), grapheme: &str, color: Color) -> io::Result<()> {
+        self.start()?;
+        write!(self.output, "<span style=\"color:#{color}\">{}</span>", escape_xml(grapheme))
+    }
+
+    fn control(&mut self, elem: ConsoleElem) -> io::Result<()> {
+        self.start()?;
+        match elem {
+            ConsoleElem::Newline | ConsoleElem::CrlfNewline => writeln!(self.output),
+            ConsoleElem::Tab => write!(self.output, "\t"),
+            ConsoleElem::NonUTF8Data(_) => write!(self.output, "\u{FFFD}"),
+            // Nothing else means anything within html
+            _ => Ok(()),
+        }
+    }
+
+    fn escape(&mut self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_style(&mut self, style: TextStyle, enabled: bool) -> io::Result<()> {
+        self.start()?;
+        let tag = match style {
+            TextStyle::Bold => "b",
+            TextStyle::Underline => "u",
+        };
+        if enabled {
+            write!(self.output, "<{tag}>")
+        } else {
+            write!(self.output, "</{tag}>")
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.start()?;
+        writeln!(self.output, "</pre>")
+    }
+}
+
+
+/// Writes an svg image, with each grapheme positioned in its cell. The size of the image isn't
+/// known until the end, so graphemes are held until the stream finishes.
+pub struct SvgSink<W> {
+    output: W,
+    style: StyleState,
+    /// Text elements, and the size of the image in cells
+    elements: Vec<String>,
+    size: (usize, usize),
+}
+
+
+impl<W: Write> SvgSink<W> {
+    const CELL_WIDTH: usize = 10;
+    const CELL_HEIGHT: usize = 20;
+    const FONT_SIZE: usize = 16;
+
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            style: StyleState::default(),
+            elements: Vec::new(),
+            size: (0, 0),
+        }
+    }
+}
+
+
+impl<W: Write> Sink for SvgSink<W> {
+    fn set_color(&mut self, _: Color) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn grapheme(&mut self, (x, y): (usize, usize), grapheme: &str, color: Color) -> io::Result<()> {
+        self.size = (self.size.0.max(x + 1), self.size.1.max(y + 1));
+
+        let mut attributes = format!(
+            "x=\"{}\" y=\"{}\" fill=\"#{color}\"",
+            x * Self::CELL_WIDTH,
+            (y + 1) * Self::CELL_HEIGHT);
+        if self.style.bold {
+            attributes.push_str(" font-weight=\"bold\"");
+        }
+        if self.style.underline {
+            attributes.push_str(" text-decoration=\"underline\"");
+        }
+
+        self.elements.push(format!("<text {attributes}>{}</text>", escape_xml(grapheme)));
+        Ok(())
+    }
+
+    fn control(&mut self, _: ConsoleElem) -> io::Result<()> {
+        // Every grapheme is positioned explicitly
+        Ok(())
+    }
+
+    fn escape(&mut self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_style(&mut self, style: TextStyle, enabled: bool) -> io::Result<()> {
+        self.style.set(style, enabled);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let (width, height) = (self.size.0 * Self::CELL_WIDTH, self.size.1 * Self::CELL_HEIGHT);
+        writeln!(
+            self.output,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+            font-family=\"monospace\" font-size=\"{}\" xml:space=\"preserve\">",
+            Self::FONT_SIZE)?;
+        for element in self.elements.drain(..) {
+            writeln!(self.output, "{element}")?;
+        }
+        writeln!(self.output, "</svg>")?;
+        self.output.flush()
+    }
+}
+
+
+/// Writes one json object per line for each grapheme, giving its position, color and style
+pub struct JsonlSink<W> {
+    output: W,
+    style: StyleState,
+}
+
+
+impl<W: Write> JsonlSink<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            style: StyleState::default(),
+        }
+    }
+}
+
+
+impl<W: Write> Sink for JsonlSink<W> {
+    fn set_color(&mut self, _: Color) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn grapheme(&mut self, (x, y): (usize, usize), grapheme: &str, color: Color) -> io::Result<()> {
+        writeln!(
+            self.output,
+            "{{\"x\": {x}, \"y\": {y}, \"grapheme\": {}, \"color\": \"#{color}\", \"bold\": {}, \"underline\": {}}}",
+            json_string(grapheme),
+            self.style.bold,
+            self.style.underline)
+    }
+
+    fn control(&mut self, _: ConsoleElem) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn escape(&mut self, _: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_style(&mut self, style: TextStyle, enabled: bool) -> io::Result<()> {
+        self.style.set(style, enabled);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The output of an [AnsiSink] after writing each grapheme in its color
+    fn ansi_output(color_mode: ColorMode, graphemes: &[(&str, Color)]) -> String {
+        let mut sink = AnsiSink::new(Vec::new(), color_mode, ColorTarget::Foreground, true, NewlineStyle::Lf);
+        for (x, &(grapheme, color)) in graphemes.iter().enumerate() {
+            sink.grapheme((x, 0), grapheme, color).unwrap();
+        }
+        String::from_utf8(sink.output).unwrap()
+    }
+
+    #[test]
+    fn paletted_colors_are_written_once_per_palette_entry() {
+        // Nearby colors which map to the same entry share one code
+        let red = Color::from_rgb(0xE0, 0x10, 0x10);
+        let also_red = Color::from_rgb(0xD0, 0x20, 0x20);
+        assert_eq!(ansi_output(ColorMode::Palette8, &[("a", red), ("b", also_red)]).matches('\u{1B}').count(), 1);
+
+        // Close colors on either side of a palette boundary are both written
+        let below = Color::gray(157);
+        let above = Color::gray(158);
+        assert_ne!(ColorMode::Palette8.nearest(below), ColorMode::Palette8.nearest(above));
+        assert_eq!(ansi_output(ColorMode::Palette8, &[("a", below), ("b", above)]).matches('\u{1B}').count(), 2);
+
+        let before = Color::gray(113);
+        let after = Color::gray(114);
+        assert_ne!(ColorMode::Palette256.nearest(before), ColorMode::Palette256.nearest(after));
+        assert_eq!(ansi_output(ColorMode::Palette256, &[("a", before), ("b", after)]).matches('\u{1B}').count(), 2);
+    }
+
+    #[test]
+    fn truecolor_writes_every_change() {
+        let a = Color::from_rgb(10, 10, 10);
+        let b = Color::from_rgb(11, 10, 10);
+        assert_eq!(ansi_output(ColorMode::Truecolor, &[("a", a), ("b", a), ("c", b)]).matches('\u{1B}').count(), 2);
+    }
+
+    #[test]
+    fn paletted_backgrounds_keep_the_foreground() {
+        let mut sink = AnsiSink::new(Vec::new(), ColorMode::Palette8, ColorTarget::Both, true, NewlineStyle::Lf);
+        sink.grapheme((0, 0), "a", Color::from_rgb(0xE0, 0x10, 0x10)).unwrap();
+        sink.grapheme((1, 0), "b", Color::from_rgb(0x10, 0x10, 0xE0)).unwrap();
+        let output = String::from_utf8(sink.output).unwrap();
+
+        // Nothing after each foreground code resets it
+        assert_eq!(output, "\x1b[31m\x1b[41ma\x1b[34m\x1b[44mb");
+    }
+}
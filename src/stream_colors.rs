@@ -1,192 +1,956 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::io;
-use std::io::{copy, Read, Write};
-use image::{GenericImageView, Pixel, Rgb};
+use std::io::{copy, BufRead, BufReader, Read, Write};
+use std::ops::ControlFlow;
+use std::rc::Rc;
+use std::str::FromStr;
+use anyhow::anyhow;
+use image::{GenericImageView, Pixel, Primitive, Rgba};
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::console::{parse_ansi_type, AnsiCodeType, Color, ConsoleElem, for_each_console_element};
+use crate::presets::FlagPreset;
+use crate::console::{grapheme_width, parse_ansi_type, sgr_code, AnsiCodeType, Color, ConsoleElem, for_each_console_element, TextAttributes, DEFAULT_READ_BUFFER_SIZE, RESET_CODE};
+use crate::sinks::{AnsiSink, ColorMode, ColorTarget, HtmlSink, JsonlSink, NewlineStyle, OutputFormat, Sink, SvgSink, TextStyle};
 
 
 /// Generic trait for anything which can "colorize" a stream. What exactly this means depends on the
 /// implementation.
+///
+/// The trait is object safe, so different colorizers can be stored together as
+/// `Box<dyn StreamColorizer>`, which is itself a [StreamColorizer].
 pub trait StreamColorizer {
     fn copy_colorized<I, O>(&mut self, input: I, output: O, config: &ColorizerConfig) -> io::Result<()>
-        where I: Read, O: Write;
+        where I: Read, O: Write, Self: Sized;
+
+    /// Object safe version of [StreamColorizer::copy_colorized], so that colorizers can be boxed
+    fn copy_colorized_dyn(&mut self, input: &mut dyn Read, output: &mut dyn Write, config: &ColorizerConfig) -> io::Result<()>;
+
+    /// The color a grapheme in the given cell would be given, if the colorizer colors by position
+    fn color_at(&mut self, _position: (usize, usize), _config: &ColorizerConfig) -> Option<Color> {
+        None
+    }
+}
+
+
+impl StreamColorizer for Box<dyn StreamColorizer> {
+    fn copy_colorized<I, O>(&mut self, mut input: I, mut output: O, config: &ColorizerConfig) -> io::Result<()>
+        where I: Read, O: Write {
+        self.as_mut().copy_colorized_dyn(&mut input, &mut output, config)
+    }
+
+    fn copy_colorized_dyn(&mut self, input: &mut dyn Read, output: &mut dyn Write, config: &ColorizerConfig) -> io::Result<()> {
+        self.as_mut().copy_colorized_dyn(input, output, config)
+    }
+
+    fn color_at(&mut self, position: (usize, usize), config: &ColorizerConfig) -> Option<Color> {
+        self.as_mut().color_at(position, config)
+    }
+}
+
+
+/// How carriage returns in the input are handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CarriageReturnMode {
+    /// Forward the carriage return, moving the cursor to the start of the line
+    Forward,
+    /// Drop the carriage return, but still color the rest of the line as if the cursor had moved
+    /// to the start of the line
+    StripAndReset,
+    /// Drop the carriage return entirely, as if it never existed
+    Strip,
+}
+
+
+/// How a wide grapheme which doesn't fit at the end of a line is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WideWrapMode {
+    /// Leave the terminal to move the grapheme onto the next line, only tracking the cells it
+    /// leaves empty
+    #[default]
+    Pad,
+    /// Fill the rest of the line with colored spaces before moving the grapheme onto the next line
+    Push,
+}
+
+
+impl FromStr for WideWrapMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("pad") {
+            Ok(Self::Pad)
+        } else if s.eq_ignore_ascii_case("push") {
+            Ok(Self::Push)
+        } else {
+            Err(anyhow!("Invalid value {s}! - Expected pad or push"))
+        }
+    }
+}
+
+
+/// Color space that colors are blended in, such as between the stripes of a flag
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlendSpace {
+    /// Blend the sRGB channels directly. Fast, but complementary colors have grey midpoints
+    #[default]
+    Srgb,
+    /// Blend in OKLab, which keeps midpoints as bright and saturated as the ends
+    Oklab,
+}
+
+
+impl BlendSpace {
+    /// Blend from one color to another, where alpha ranges from 0 (from) to 1 (to)
+    pub fn interpolate(self, from: Color, to: Color, alpha: f32) -> Color {
+        match self {
+            BlendSpace::Srgb => from.rgb_interpolate(to, alpha),
+            BlendSpace::Oklab => from.oklab_interpolate(to, alpha),
+        }
+    }
+}
+
+
+impl FromStr for BlendSpace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("srgb") {
+            Ok(Self::Srgb)
+        } else if s.eq_ignore_ascii_case("oklab") {
+            Ok(Self::Oklab)
+        } else {
+            Err(anyhow!("Invalid blend space {s}! - Expected srgb or oklab"))
+        }
+    }
+}
+
+
+/// How the distance between colors is measured, when finding the nearest color in a palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaletteMetric {
+    /// Plain euclidean distance in sRGB, using [Color::distance_sq]
+    Rgb,
+    /// Euclidean distance weighted by how sensitive vision is to each channel, using
+    /// [Color::distance_weighted]
+    #[default]
+    Weighted,
+    /// Perceptual distance in CIELAB, using [Color::delta_e_cie76]. The most accurate, but slowest
+    DeltaE,
+}
+
+
+impl PaletteMetric {
+    /// Distance between two colors. Only comparisons between distances are meaningful, as each
+    /// metric has its own scale
+    pub fn distance(self, a: Color, b: Color) -> f32 {
+        match self {
+            PaletteMetric::Rgb => a.distance_sq(b) as f32,
+            PaletteMetric::Weighted => a.distance_weighted(b),
+            PaletteMetric::DeltaE => a.delta_e_cie76(b),
+        }
+    }
+
+    /// The color in the palette nearest to the given color, if the palette isn't empty
+    pub fn nearest(self, color: Color, palette: &[Color]) -> Option<Color> {
+        palette.iter().copied()
+            .min_by(|&a, &b| self.distance(color, a).total_cmp(&self.distance(color, b)))
+    }
+}
+
+
+impl FromStr for PaletteMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("rgb") {
+            Ok(Self::Rgb)
+        } else if s.eq_ignore_ascii_case("weighted") {
+            Ok(Self::Weighted)
+        } else if s.eq_ignore_ascii_case("delta-e") || s.eq_ignore_ascii_case("deltae") {
+            Ok(Self::DeltaE)
+        } else {
+            Err(anyhow!("Invalid palette metric {s}! - Expected rgb, weighted or delta-e"))
+        }
+    }
+}
+
+
+/// Counts of what was seen while colorizing, shared between each use of a config
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub lines: usize,
+    pub graphemes: usize,
+    pub non_utf8_bytes: usize,
+    pub ansi_forwarded: usize,
+    pub ansi_discarded: usize,
+    colors: HashSet<Color>,
+    input_colors: HashSet<Color>,
+}
+
+
+impl Stats {
+    /// Number of distinct colors given to graphemes in the output
+    pub fn distinct_colors(&self) -> usize {
+        self.colors.len()
+    }
+
+    /// Number of distinct foreground colors set by codes in the input
+    pub fn distinct_input_colors(&self) -> usize {
+        self.input_colors.len()
+    }
+}
+
+
+/// Palette built up from the colors output so far, limiting how many distinct colors are used
+#[derive(Debug)]
+pub struct RunningPalette {
+    max_colors: usize,
+    colors: Vec<Color>,
+}
+
+
+impl RunningPalette {
+    pub fn new(max_colors: usize) -> Self {
+        Self {
+            max_colors: max_colors.max(1),
+            colors: Vec::new(),
+        }
+    }
+
+    /// Add a color to the palette if there is room, otherwise snap it to the nearest color
+    /// already in the palette, measured using [Color::distance_sq]
+    pub fn snap(&mut self, color: Color) -> Color {
+        if self.colors.contains(&color) {
+            return color;
+        }
+        if self.colors.len() < self.max_colors {
+            self.colors.push(color);
+            return color;
+        }
+
+        self.nearest(color)
+    }
+
+    /// The color which [RunningPalette::snap] would give, without adding anything to the palette
+    pub fn nearest(&self, color: Color) -> Color {
+        if self.colors.len() < self.max_colors {
+            return color;
+        }
+
+        self.colors.iter().copied()
+            .min_by_key(|c| c.distance_sq(color))
+            .expect("Palette is full, so non-empty")
+    }
 }
 
 
 /// Configuration for a [StreamColorizer]
 #[derive(Debug, Clone)]
 pub struct ColorizerConfig {
-    pub supports_rgb24: bool,
+    pub color_mode: ColorMode,
+    /// Whether the text, the cell behind it, or both are colored in ansi output
+    pub color_target: ColorTarget,
     pub wraps_after: Option<usize>,
+    /// Position of the first cell, so that output placed elsewhere on screen matches up with
+    /// its surroundings
+    pub origin: (usize, usize),
+    /// Number of cells after which the pattern repeats horizontally, overriding fitting the
+    /// pattern to the terminal width
+    pub pattern_width: Option<usize>,
+    /// Number of lines the output is expected to span, if known
+    pub height: Option<usize>,
     pub tab_size: usize,
+    /// Number of bytes of input read at a time
+    pub read_buffer_size: usize,
+    /// Number of cells to indent lines wrapped onto by a long line
+    pub wrap_indent: usize,
+    /// Number of cells taken up by East Asian characters of ambiguous width, either 1 or 2
+    pub ambiguous_width: usize,
+    pub wide_wrap: WideWrapMode,
+    /// Color space used to blend between colors
+    pub blend_space: BlendSpace,
+    /// Text displayed in place of each tab, padded with spaces up to the tab stop
+    pub tab_replacement: Option<String>,
+    /// Grapheme displayed in place of each space
+    pub space_replacement: Option<String>,
     pub flush_on_newline: bool,
+    pub carriage_return: CarriageReturnMode,
+    /// Index of the first line to output. Earlier lines are still tracked, so colors match the
+    /// full input
+    pub from_line: usize,
+    /// Index of the last line to output, inclusive. Input is not read past this line
+    pub to_line: Option<usize>,
+    /// Color the output lines as if they started at the top of the input
+    pub recolor_range: bool,
+    /// Colors are only sampled every sample_rate cells, so that blocks of cells share a color
+    pub sample_rate: usize,
+    /// Swap the rows and columns of the pattern, so that horizontal stripes become vertical
+    pub transpose: bool,
+    /// Convert overstrike sequences (as used by man) into bold and underlined text
+    pub render_overstrike: bool,
+    /// After a reset in the input, only re-apply our color once the next grapheme is written
+    pub inherit_color_across_ansi: bool,
+    /// Treat erasing the whole line or screen as also returning to the start of the line
+    pub erase_resets_column: bool,
+    /// Write any ansi codes at all. When disabled, the text is still processed as usual, but both
+    /// our colors and any codes in the input are dropped, leaving plain text
+    pub emit_ansi: bool,
+    pub output_format: OutputFormat,
+    /// Line terminator written for each newline in ansi output, whatever the input used
+    pub newline_style: NewlineStyle,
+    /// Where to count what was seen while colorizing, if anywhere
+    pub stats: Option<Rc<RefCell<Stats>>>,
+    /// Colors are snapped to the nearest in this palette before being output
+    pub quantize_palette: Option<Vec<Color>>,
+    /// How the nearest color in quantize_palette is found
+    pub palette_metric: PaletteMetric,
+    /// Limits the number of distinct colors output, shared between each use of a config
+    pub running_palette: Option<Rc<RefCell<RunningPalette>>>,
 }
 
 
 impl Default for ColorizerConfig {
     fn default() -> Self {
         Self {
-            supports_rgb24: true,
+            color_mode: ColorMode::Truecolor,
+            color_target: ColorTarget::Foreground,
             wraps_after: None,
+            origin: (0, 0),
+            pattern_width: None,
+            height: None,
             tab_size: 8,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            wrap_indent: 0,
+            ambiguous_width: 1,
+            wide_wrap: WideWrapMode::Pad,
+            blend_space: BlendSpace::Srgb,
+            tab_replacement: None,
+            space_replacement: None,
             flush_on_newline: true,
+            carriage_return: CarriageReturnMode::Forward,
+            from_line: 0,
+            to_line: None,
+            recolor_range: false,
+            sample_rate: 1,
+            transpose: false,
+            render_overstrike: false,
+            inherit_color_across_ansi: false,
+            erase_resets_column: false,
+            emit_ansi: true,
+            output_format: OutputFormat::Ansi,
+            newline_style: NewlineStyle::Lf,
+            stats: None,
+            quantize_palette: None,
+            palette_metric: PaletteMetric::Weighted,
+            running_palette: None,
+        }
+    }
+}
+
+
+impl ColorizerConfig {
+    /// Map the position of a cell in the terminal to the position its color is sampled from
+    pub fn color_position(&self, (x, y): (usize, usize)) -> (usize, usize) {
+        let rate = self.sample_rate;
+        let (x, y) = (x / rate * rate, y / rate * rate);
+        if self.transpose {
+            (y, x)
+        } else {
+            (x, y)
+        }
+    }
+
+    /// Snap a color to the nearest in quantize_palette, if there is one, and then to the
+    /// running palette
+    pub fn quantize(&self, color: Color) -> Color {
+        let color = match &self.quantize_palette {
+            Some(palette) => self.palette_metric.nearest(color, palette).unwrap_or(color),
+            None => color,
+        };
+
+        match &self.running_palette {
+            Some(palette) => palette.borrow_mut().snap(color),
+            None => color,
+        }
+    }
+
+    /// Like [ColorizerConfig::quantize], but without adding the color to the running palette, for
+    /// colors which may never be output
+    pub fn peek_quantize(&self, color: Color) -> Color {
+        let color = match &self.quantize_palette {
+            Some(palette) => self.palette_metric.nearest(color, palette).unwrap_or(color),
+            None => color,
+        };
+
+        match &self.running_palette {
+            Some(palette) => palette.borrow().nearest(color),
+            None => color,
         }
     }
 }
 
 
 /// A trait which represents objects which can colorize a stream based on the (expected) location of
-/// each grapheme in the terminal.
+/// each grapheme in the terminal, along with the grapheme itself. No grapheme is given when the
+/// color is only being initialised.
 /// Implementing this trait automatically provides an implementation of [StreamColorizer]
+pub trait Recolorizer {
+    fn color_for(&mut self, position: (usize, usize), grapheme: Option<&str>) -> Color;
+}
+
+
+/// A simpler version of [Recolorizer], for colorizers which only depend on the position of each
+/// grapheme.
+/// Implementing this trait automatically provides an implementation of [Recolorizer]
 pub trait PositionalRecolorizer {
     fn get_color(&mut self, position: (usize, usize)) -> Color;
 }
 
 
-impl<T> StreamColorizer for T where T: PositionalRecolorizer {
-    fn copy_colorized<I, O>(&mut self, input: I, mut output: O, config: &ColorizerConfig) -> io::Result<()> where I: Read, O: Write {
-        let wrap_column = config.wraps_after.unwrap_or(usize::MAX);
+impl<T> Recolorizer for T where T: PositionalRecolorizer {
+    fn color_for(&mut self, position: (usize, usize), _: Option<&str>) -> Color {
+        self.get_color(position)
+    }
+}
+
+
+impl<T> StreamColorizer for T where T: Recolorizer {
+    fn copy_colorized<I, O>(&mut self, input: I, output: O, config: &ColorizerConfig) -> io::Result<()> where I: Read, O: Write {
+        match config.output_format {
+            OutputFormat::Ansi => paint(self, input, AnsiSink::new(output, config.color_mode, config.color_target, config.emit_ansi, config.newline_style), config),
+            OutputFormat::Html => paint(self, input, HtmlSink::new(output), config),
+            OutputFormat::Svg => paint(self, input, SvgSink::new(output), config),
+            OutputFormat::Jsonl => paint(self, input, JsonlSink::new(output), config),
+        }
+    }
+
+    fn copy_colorized_dyn(&mut self, input: &mut dyn Read, output: &mut dyn Write, config: &ColorizerConfig) -> io::Result<()> {
+        self.copy_colorized(input, output, config)
+    }
+
+    fn color_at(&mut self, position: (usize, usize), config: &ColorizerConfig) -> Option<Color> {
+        Some(config.quantize(self.color_for(config.color_position(position), None)))
+    }
+}
+
+
+/// Recolor a stream, writing the result to the given sink
+fn paint<R, I, S>(recolorizer: &mut R, input: I, sink: S, config: &ColorizerConfig) -> io::Result<()>
+    where R: Recolorizer,
+          I: Read,
+          S: Sink {
+    let mut painter = Painter::new(recolorizer, sink, config)?;
+    for_each_console_element(input, config.read_buffer_size, |elem| painter.handle(elem))?;
+    painter.finish()
+}
+
+
+/// A grapheme which may turn out to be the first half of an overstrike sequence
+#[derive(Debug, Default)]
+enum Overstrike {
+    #[default]
+    None,
+    Held(String),
+    HeldBackspace(String),
+}
+
+
+/// Tracks the state of a stream being recolored by a [Recolorizer], and drives a [Sink] with the
+/// position and color of each grapheme
+struct Painter<'a, R, S> {
+    recolorizer: &'a mut R,
+    sink: S,
+    config: &'a ColorizerConfig,
+    wrap_column: usize,
+    position: (usize, usize),
+    color: Color,
+    /// Attributes such as bold set by the input, which are kept across resets
+    attributes: TextAttributes,
+    /// Position saved by the input, to return to when it restores the cursor. Terminals have a
+    /// single slot, which each save overwrites and restoring leaves in place
+    saved_position: Option<(usize, usize)>,
+
+    /// Lines before from_line are processed as usual, but aren't sent to the sink
+    line: usize,
+    started: bool,
+    /// Set after wrapping onto a new line, until the line is indented
+    continuation: bool,
+
+    overstrike: Overstrike,
+}
+
+
+impl<'a, R, S> Painter<'a, R, S>
+    where R: Recolorizer,
+          S: Sink {
+    fn new(recolorizer: &'a mut R, sink: S, config: &'a ColorizerConfig) -> io::Result<Self> {
+        // Start at the origin, and initialise the color for this position. It only takes up a
+        // place in the running palette once a grapheme is drawn in it
+        let position = config.origin;
+        let color = config.peek_quantize(recolorizer.color_for(config.color_position(position), None));
+
+        let mut painter = Self {
+            recolorizer,
+            sink,
+            config,
+            wrap_column: config.wraps_after.unwrap_or(usize::MAX),
+            position,
+            color,
+            attributes: TextAttributes::default(),
+            saved_position: None,
+            line: 0,
+            started: config.from_line == 0,
+            continuation: false,
+            overstrike: Overstrike::None,
+        };
+
+        if painter.started {
+            painter.sink.set_color(color)?;
+        }
+
+        Ok(painter)
+    }
+
+    /// The color for the current position
+    fn color_here(&mut self, grapheme: Option<&str>) -> Color {
+        let color = self.recolorizer.color_for(self.config.color_position(self.position), grapheme);
+        self.config.quantize(color)
+    }
+
+    /// False for lines before from_line, which shouldn't be output
+    fn visible(&self) -> bool {
+        self.line >= self.config.from_line
+    }
+
+    /// Pass a non-printing element on to the sink, if it is visible
+    fn control(&mut self, elem: ConsoleElem) -> io::Result<()> {
+        if self.visible() {
+            self.sink.control(elem)?;
+        }
+        Ok(())
+    }
+
+    /// Count an element of the input, if stats are wanted
+    fn count(&self, elem: ConsoleElem) {
+        let Some(stats) = &self.config.stats
+            else {
+                return;
+            };
+
+        let mut stats = stats.borrow_mut();
+        match elem {
+            ConsoleElem::Newline | ConsoleElem::CrlfNewline => stats.lines += 1,
+            ConsoleElem::Grapheme(_) => stats.graphemes += 1,
+            ConsoleElem::NonUTF8Data(_) => stats.non_utf8_bytes += 1,
+            ConsoleElem::Ansi(esc_sequence) => {
+                let code = parse_ansi_type(esc_sequence);
+                if let AnsiCodeType::SetColor(Some(color)) | AnsiCodeType::SetAttributes { color: Some(color), .. } = code {
+                    stats.input_colors.insert(color);
+                }
+
+                if self.config.emit_ansi && !matches!(code, AnsiCodeType::SetColor(_)) {
+                    stats.ansi_forwarded += 1;
+                } else {
+                    stats.ansi_discarded += 1;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn handle(&mut self, elem: ConsoleElem) -> io::Result<ControlFlow<()>> {
+        self.count(elem);
 
-        // Start at the top-left, and initialise the color for this position
-        let mut position = (0, 0);
-        let mut color = self.get_color(position);
-        if config.supports_rgb24 {
-            color.write_as_24bit_ansi(&mut output)?;
+        // Apply our color once we start outputting
+        if self.visible() && !self.started {
+            self.started = true;
+            if self.config.recolor_range {
+                self.position = self.config.origin;
+                self.color = self.color_here(None);
+            }
+            self.sink.set_color(self.color)?;
+            self.restore_attributes()?;
+        }
+
+        if self.config.render_overstrike {
+            self.handle_overstrike(elem)?;
         } else {
-            color.write_as_paletted_ansi(&mut output)?;
+            self.handle_plain(elem)?;
         }
 
-        for_each_console_element(input, move |elem| {
-            match elem {
-                // Unix-style handling of carriage return - moves cursor to the beginning of the line
-                ConsoleElem::CarriageReturn => {
-                    position.0 = 0;
-                    write!(output, "\r")?;
-                },
+        // Stop reading input once past the last line
+        if self.config.to_line.is_some_and(|to_line| self.line > to_line) {
+            return Ok(ControlFlow::Break(()));
+        }
 
-                // Unix-style newline handling - move cursor to the beginning of the next line
-                ConsoleElem::Newline => {
-                    position.1 += 1;
-                    position.0 = 0;
-                    writeln!(output)?;
-                    if config.flush_on_newline {
-                        output.flush()?;
-                    }
-                },
+        Ok(ControlFlow::Continue(()))
+    }
 
-                // Tab snaps the cursor to the next multiple of tab_size
-                ConsoleElem::Tab => {
-                    position.0 = ((position.0 / config.tab_size)+1) * config.tab_size;
-                    if position.0 >= wrap_column {
-                        position.0 = wrap_column - 1;
-                    }
-                    write!(output, "\t")?;
+    /// Called once the stream has ended
+    fn finish(&mut self) -> io::Result<()> {
+        let held = std::mem::take(&mut self.overstrike);
+        self.flush_overstrike(held)?;
+        self.sink.finish()
+    }
+
+    /// Look for overstrike sequences, as used by man pages. "a\ba" is a bold a, while "_\ba" is an
+    /// underlined a. These are converted into the equivalent styles.
+    fn handle_overstrike(&mut self, elem: ConsoleElem) -> io::Result<()> {
+        match (std::mem::take(&mut self.overstrike), elem) {
+            (Overstrike::Held(first), ConsoleElem::Backspace) => {
+                self.overstrike = Overstrike::HeldBackspace(first);
+            },
+
+            (Overstrike::HeldBackspace(first), ConsoleElem::Grapheme(second)) => {
+                if first == second {
+                    self.write_styled_grapheme(second, TextStyle::Bold)?;
+                } else if first == "_" {
+                    self.write_styled_grapheme(second, TextStyle::Underline)?;
+                } else if second == "_" {
+                    self.write_styled_grapheme(&first, TextStyle::Underline)?;
+                } else {
+                    // Not an overstrike after all, but the second grapheme could start another
+                    self.handle_plain(ConsoleElem::Grapheme(&first))?;
+                    self.handle_plain(ConsoleElem::Backspace)?;
+                    self.overstrike = Overstrike::Held(second.to_string());
                 }
+            },
 
-                // We have to assume that each grapheme take up exactly one cell -
-                // really it's up to the terminal how it displays each grapheme
-                ConsoleElem::Grapheme(grapheme) => {
-                    let new_color = self.get_color(position);
-                    // TODO: More permissive equality when using paletted ansi
-                    if new_color != color {
-                        color = new_color;
-                        if config.supports_rgb24 {
-                            color.write_as_24bit_ansi(&mut output)?;
-                        } else {
-                            color.write_as_paletted_ansi(&mut output)?;
-                        }
-                    }
-                    write!(output, "{grapheme}")?;
-                    position.0 += 1;
-                    if position.0 >= wrap_column {
-                        position.0 -= wrap_column;
-                        position.1 += 1;
-                    }
-                },
+            (held, elem) => {
+                self.flush_overstrike(held)?;
+                if let ConsoleElem::Grapheme(grapheme) = elem {
+                    self.overstrike = Overstrike::Held(grapheme.to_string());
+                } else {
+                    self.handle_plain(elem)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Output anything held back while looking for an overstrike
+    fn flush_overstrike(&mut self, held: Overstrike) -> io::Result<()> {
+        match held {
+            Overstrike::None => Ok(()),
+            Overstrike::Held(grapheme) => self.handle_plain(ConsoleElem::Grapheme(&grapheme)),
+            Overstrike::HeldBackspace(grapheme) => {
+                self.handle_plain(ConsoleElem::Grapheme(&grapheme))?;
+                self.handle_plain(ConsoleElem::Backspace)
+            },
+        }
+    }
+
+    fn write_styled_grapheme(&mut self, grapheme: &str, style: TextStyle) -> io::Result<()> {
+        if self.visible() {
+            self.sink.set_style(style, true)?;
+        }
+        self.write_grapheme(grapheme)?;
+        if self.visible() {
+            self.sink.set_style(style, false)?;
+            // Turning our style off may also have turned off the input's own
+            self.restore_attributes()?;
+        }
+        Ok(())
+    }
+
+    /// Re-apply the input's attributes, such as after they were reset or turned off
+    fn restore_attributes(&mut self) -> io::Result<()> {
+        if let Some(code) = self.attributes.to_sgr() {
+            self.sink.escape(&code)?;
+        }
+        Ok(())
+    }
+
+    /// Re-apply our color after the input reset the style
+    fn restore_color(&mut self) -> io::Result<()> {
+        if self.config.inherit_color_across_ansi {
+            self.sink.forget_color();
+            Ok(())
+        } else {
+            self.sink.set_color(self.color)
+        }
+    }
+
+    fn write_grapheme(&mut self, grapheme: &str) -> io::Result<()> {
+        let config = self.config;
+        let grapheme = match &config.space_replacement {
+            Some(replacement) if grapheme == " " => replacement.as_str(),
+            _ => grapheme,
+        };
 
-                // Unspecified non-printing character, such as a bell
-                // coloring these doesn't make sense
-                ConsoleElem::OtherNonPrinting(c) => {
-                    write!(output, "{c}")?;
+        // Wide graphemes which don't fit at the end of a line are moved onto the next line
+        let width = grapheme_width(grapheme, config.ambiguous_width);
+        if width > 1 && self.position.0 > 0 && self.position.0.saturating_add(width) > self.wrap_column {
+            if config.wide_wrap == WideWrapMode::Push {
+                while self.position.0 < self.wrap_column {
+                    self.write_space()?;
                 }
+            }
+            self.position.0 = 0;
+            self.position.1 += 1;
+            self.continuation = true;
+        }
 
-                // Intercept ansi control sequences
-                ConsoleElem::Ansi(esc_sequence) => match parse_ansi_type(esc_sequence) {
-                    // We don't want the original source to be able to reset our coloring, so
-                    // cary out the reset style and then additionally re-apply our color
-                    AnsiCodeType::ResetStyle => {
-                        write!(output, "{esc_sequence}")?;
-                        if config.supports_rgb24 {
-                            color.write_as_24bit_ansi(&mut output)?;
-                        } else {
-                            color.write_as_paletted_ansi(&mut output)?;
-                        }
+        // Continuation lines are only indented once there is something to put on them, so that
+        // a line which exactly fills the terminal isn't followed by a line of spaces
+        if self.continuation && self.position.0 == 0 && width > 0 {
+            self.continuation = false;
+            let indent = config.wrap_indent.min(self.wrap_column.saturating_sub(1));
+            for _ in 0..indent {
+                self.write_space()?;
+            }
+        }
+
+        self.color = self.color_here(Some(grapheme));
+        if self.visible() {
+            self.sink.grapheme(self.position, grapheme, self.color)?;
+            if let Some(stats) = &config.stats {
+                stats.borrow_mut().colors.insert(self.color);
+            }
+        }
+        self.position.0 += width;
+        if self.position.0 >= self.wrap_column {
+            self.position.0 -= self.wrap_column;
+            self.position.1 += 1;
+            self.continuation = true;
+        }
+        Ok(())
+    }
+
+    /// Write a colored space in the current cell, which is assumed to be on the same line
+    fn write_space(&mut self) -> io::Result<()> {
+        self.color = self.color_here(Some(" "));
+        if self.visible() {
+            self.sink.grapheme(self.position, " ", self.color)?;
+        }
+        self.position.0 += 1;
+        Ok(())
+    }
+
+    fn handle_plain(&mut self, elem: ConsoleElem) -> io::Result<()> {
+        let config = self.config;
+
+        match elem {
+            // Unix-style handling of carriage return - moves cursor to the beginning of the line
+            ConsoleElem::CarriageReturn => match config.carriage_return {
+                CarriageReturnMode::Forward => {
+                    self.position.0 = 0;
+                    self.continuation = false;
+                    self.control(elem)?;
+                },
+                CarriageReturnMode::StripAndReset => self.position.0 = 0,
+                CarriageReturnMode::Strip => {/* discard */},
+            },
+
+            // Unix-style newline handling - move cursor to the beginning of the next line. The
+            // carriage return of a windows line ending is only kept if carriage returns are
+            // forwarded, and then only with the default line terminator
+            ConsoleElem::Newline | ConsoleElem::CrlfNewline => {
+                let elem = match config.carriage_return {
+                    CarriageReturnMode::Forward => elem,
+                    _ => ConsoleElem::Newline,
+                };
+                if self.visible() {
+                    self.sink.control(elem)?;
+                    if config.flush_on_newline {
+                        self.sink.flush()?;
                     }
+                }
+                self.line += 1;
+                self.position.1 += 1;
+                self.position.0 = 0;
+                self.continuation = false;
+            },
 
-                    // Simply prevent the original source from changing the color
-                    AnsiCodeType::SetColor => {/* discard */},
+            // Tab snaps the cursor to the next multiple of tab_size
+            ConsoleElem::Tab => {
+                let mut tab_stop = ((self.position.0 / config.tab_size)+1) * config.tab_size;
+                if tab_stop >= self.wrap_column {
+                    tab_stop = self.wrap_column - 1;
+                }
 
-                    // We allow cursor moves, so long as we can also track them. This way the color
-                    // will still match up after a cursor move
-                    AnsiCodeType::SetCursor(col, row) => {
-                        if let Some(c) = col {
-                            position.0 = c;
+                match &config.tab_replacement {
+                    // Fill the cells up to the tab stop by display width, so that following text
+                    // still lines up. Replacements wider than the gap are cut short. A tab at the
+                    // last column still takes up a cell, rather than disappearing
+                    Some(replacement) => {
+                        let cells = tab_stop.saturating_sub(self.position.0).max(1);
+                        let mut filled = 0;
+                        for grapheme in replacement.graphemes(true) {
+                            let width = grapheme_width(grapheme, config.ambiguous_width);
+                            if filled + width > cells {
+                                break;
+                            }
+                            self.write_grapheme(grapheme)?;
+                            filled += width;
                         }
-                        if let Some(r) = row {
-                            position.1 = r
+                        for _ in filled..cells {
+                            self.write_grapheme(" ")?;
                         }
-                        write!(output, "{esc_sequence}")?;
                     },
+                    None => {
+                        self.position.0 = tab_stop;
+                        self.control(elem)?;
+                    },
+                }
+            }
 
-                    // (See above)
-                    AnsiCodeType::MoveCursor(col, row) => {
-                        if let Some(d) = col {
-                            position.0 = if d > 0 {
-                                position.0.saturating_add(d as usize)
-                            } else {
-                                position.0.saturating_sub(d as usize)
-                            };
-                        }
-                        if let Some(d) = row {
-                            position.1 = if d > 0 {
-                                position.1.saturating_add(d as usize)
-                            } else {
-                                position.1.saturating_sub(d as usize)
-                            };
+            // Backspace moves the cursor back one cell, without erasing anything
+            ConsoleElem::Backspace => {
+                self.position.0 = self.position.0.saturating_sub(1);
+                self.control(elem)?;
+            }
+
+            ConsoleElem::Grapheme(grapheme) => self.write_grapheme(grapheme)?,
+
+            // Unspecified non-printing character, such as a bell
+            // coloring these doesn't make sense
+            ConsoleElem::OtherNonPrinting(_) => self.control(elem)?,
+
+            // Plain text output, so the input's own codes are dropped too. They aren't forwarded,
+            // so cursor moves needn't be tracked either
+            ConsoleElem::Ansi(_) if !config.emit_ansi => {/* discard */},
+
+            // Intercept ansi control sequences
+            ConsoleElem::Ansi(esc_sequence) => match parse_ansi_type(esc_sequence) {
+                // We don't want the original source to be able to reset our coloring, so
+                // cary out the reset style and then additionally re-apply our color. Re-applying
+                // it can be put off until the next grapheme, so that runs of resets don't each
+                // write a color
+                AnsiCodeType::ResetStyle => {
+                    self.attributes = TextAttributes::default();
+                    if self.visible() {
+                        self.sink.escape(esc_sequence)?;
+                        self.restore_color()?;
+                    }
+                }
+
+                // Attributes are kept, but any colors set alongside them are dropped. They are
+                // also tracked, so that they can be restored after we turn off our own styles
+                AnsiCodeType::SetAttributes { reset, codes, .. } => {
+                    if reset {
+                        self.attributes = TextAttributes::default();
+                    }
+                    for &code in &codes {
+                        self.attributes.apply(code);
+                    }
+                    if self.visible() {
+                        if reset {
+                            self.sink.escape(RESET_CODE)?;
+                            self.restore_color()?;
                         }
-                        write!(output, "{esc_sequence}")?;
+                        self.sink.escape(&sgr_code(&codes))?;
                     }
+                }
 
-                    // Ideally we'd also handle codes which move already printed characters,
-                    // but in doing so we'd need to track the entire terminal screen ourselves.
+                // Simply prevent the original source from changing the color
+                AnsiCodeType::SetColor(_) => {/* discard */},
 
-                    // Forward any other control sequence, hoping that it doesn't cause us any
-                    // issues
-                    _ => {
-                        write!(output, "{esc_sequence}")?;
-                    },
+                // We allow cursor moves, so long as we can also track them. This way the color
+                // will still match up after a cursor move
+                AnsiCodeType::SetCursor(col, row) => {
+                    if let Some(c) = col {
+                        self.position.0 = c;
+                    }
+                    if let Some(r) = row {
+                        self.position.1 = r
+                    }
+                    self.control(elem)?;
                 },
 
-                // Some raw binary data - not valid utf-8. Just send it on, and hope that
-                // the destination knows what to do with it.
-                ConsoleElem::NonUTF8Data(b) => {
-                    output.write_all(&[b])?;
+                // (See above)
+                AnsiCodeType::MoveCursor(col, row) => {
+                    if let Some(d) = col {
+                        self.position.0 = if d > 0 {
+                            self.position.0.saturating_add(d as usize)
+                        } else {
+                            self.position.0.saturating_sub(d as usize)
+                        };
+                    }
+                    if let Some(d) = row {
+                        self.position.1 = if d > 0 {
+                            self.position.1.saturating_add(d as usize)
+                        } else {
+                            self.position.1.saturating_sub(d as usize)
+                        };
+                    }
+                    self.control(elem)?;
                 }
-            }
 
-            Ok(())
-        })
+                // Erasing doesn't move the cursor, but erasing the whole line or screen can
+                // optionally be taken to start the line over
+                AnsiCodeType::EraseLine(2) | AnsiCodeType::EraseDisplay(2 | 3) if config.erase_resets_column => {
+                    self.position.0 = 0;
+                    self.control(elem)?;
+                },
+                AnsiCodeType::EraseLine(_) | AnsiCodeType::EraseDisplay(_) => self.control(elem)?,
+
+                // (See above.) Restoring without a saved position leaves the cursor where it is
+                AnsiCodeType::SaveCursor => {
+                    self.saved_position = Some(self.position);
+                    self.control(elem)?;
+                },
+                AnsiCodeType::RestoreCursor => {
+                    if let Some(position) = self.saved_position {
+                        self.position = position;
+                    }
+                    self.control(elem)?;
+                },
+
+                // Hyperlinks and titles are forwarded whole. Any text they wrap is printed
+                // separately, so only that advances the cursor
+                AnsiCodeType::OperatingSystemCommand => self.control(elem)?,
+
+                // Ideally we'd also handle codes which move already printed characters,
+                // but in doing so we'd need to track the entire terminal screen ourselves.
+
+                // Forward any other control sequence, hoping that it doesn't cause us any
+                // issues
+                _ => self.control(elem)?,
+            },
+
+            // Some raw binary data - not valid utf-8. Just send it on, and hope that
+            // the destination knows what to do with it.
+            ConsoleElem::NonUTF8Data(_) => self.control(elem)?,
+        }
+
+        Ok(())
     }
 }
 
 
-
 pub struct Noop;
 
 impl StreamColorizer for Noop {
-    fn copy_colorized<I, O>(&mut self, mut input: I, mut output: O, _: &ColorizerConfig) -> io::Result<()>
+    fn copy_colorized<I, O>(&mut self, mut input: I, mut output: O, config: &ColorizerConfig) -> io::Result<()>
         where I: Read, O: Write {
-        copy(&mut input, &mut output).map(|_| ())
+        if config.from_line == 0 && config.to_line.is_none() {
+            return copy(&mut input, &mut output).map(|_| ());
+        }
+
+        // Only the selected lines are copied, and nothing after them is read
+        let mut input = BufReader::new(input);
+        let mut line = Vec::new();
+        for number in 0.. {
+            if config.to_line.is_some_and(|to_line| number > to_line) {
+                break;
+            }
+
+            line.clear();
+            if input.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            if number >= config.from_line {
+                output.write_all(&line)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_colorized_dyn(&mut self, input: &mut dyn Read, output: &mut dyn Write, config: &ColorizerConfig) -> io::Result<()> {
+        self.copy_colorized(input, output, config)
     }
 }
 
@@ -196,20 +960,129 @@ pub struct Flag {
     pub hf: f32,
     pub vf: f32,
     pub stripes: Vec<Color>,
+    /// Relative width of each stripe, or None if all stripes have equal width
+    pub weights: Option<Vec<f32>>,
     pub deadzone: f32,
+    pub blend_space: BlendSpace,
 }
 
 
-impl PositionalRecolorizer for Flag {
-    fn get_color(&mut self, (x, y): (usize, usize)) -> Color {
-        let d = (x as f32) * self.hf + (y as f32) * self.vf;
+impl Flag {
+    /// Frequency used when no better one is known, in stripes/cell
+    pub const DEFAULT_FREQUENCY: f32 = 0.05;
+    /// Fraction of each stripe which is a solid color before blending into the next
+    pub const DEFAULT_DEADZONE: f32 = 0.6;
 
-        let base_index = d as usize;
-        let frac = d.fract();
+    /// Create a flag with the given stripes, all of equal width, and default frequencies
+    ///
+    /// ```
+    /// use prettycat::console::Color;
+    /// use prettycat::stream_colors::Flag;
+    ///
+    /// let flag = Flag::new(vec![Color::from_rgb(0xFF, 0x00, 0x00), Color::WHITE])
+    ///     .hf(0.1)
+    ///     .vf(0.02)
+    ///     .deadzone(0.8);
+    /// assert_eq!(flag.stripes.len(), 2);
+    /// assert_eq!(flag.hf, 0.1);
+    /// ```
+    pub fn new(stripes: Vec<Color>) -> Self {
+        Self {
+            hf: Self::DEFAULT_FREQUENCY,
+            vf: Self::DEFAULT_FREQUENCY,
+            stripes,
+            weights: None,
+            deadzone: Self::DEFAULT_DEADZONE,
+            blend_space: BlendSpace::Srgb,
+        }
+    }
+
+    /// Create a flag with the stripes of a preset
+    pub fn from_preset(preset: &FlagPreset) -> Self {
+        Self::new(preset.stripes.to_vec())
+            .weights(preset.weights.map(<[f32]>::to_vec))
+    }
+
+    /// Set the horizontal frequency, in stripes/column
+    pub fn hf(mut self, hf: f32) -> Self {
+        self.hf = hf;
+        self
+    }
+
+    /// Set the vertical frequency, in stripes/row
+    pub fn vf(mut self, vf: f32) -> Self {
+        self.vf = vf;
+        self
+    }
+
+    /// Set the relative width of each stripe
+    pub fn weights(mut self, weights: Option<Vec<f32>>) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Set the fraction of each stripe which is a solid color before blending into the next, from
+    /// 0 for a smooth gradient to 1 for hard edges
+    pub fn deadzone(mut self, deadzone: f32) -> Self {
+        self.deadzone = deadzone;
+        self
+    }
+
+    /// Set the color space that neighbouring stripes are blended in
+    pub fn blend_space(mut self, blend_space: BlendSpace) -> Self {
+        self.blend_space = blend_space;
+        self
+    }
+
+    /// Choose a frequency so that one full repetition of the flag spans around 1.5 times the
+    /// terminal width, which shows enough stripes without them becoming too thin. Falls back to
+    /// 0.05 stripes/column if the width is unknown.
+    pub fn auto_frequency(stripe_count: usize, width: Option<usize>) -> f32 {
+        match width {
+            Some(width) if width > 0 => stripe_count as f32 / (1.5 * width as f32),
+            _ => Self::DEFAULT_FREQUENCY,
+        }
+    }
+
+    /// Find the index of the stripe at the given distance along the flag, and the fraction of that
+    /// stripe which has been passed. Distance is measured in stripes, so that a full repetition of
+    /// the flag always has a length of stripes.len(), regardless of weights.
+    fn stripe_at(&self, d: f32) -> (usize, f32) {
+        let Some(weights) = &self.weights
+            else {
+                return ((d as usize) % self.stripes.len(), d.fract());
+            };
+
+        let total: f32 = weights.iter().sum();
+        let scale = total / self.stripes.len() as f32;
+
+        let mut remaining = (d * scale).rem_euclid(total);
+        for (i, &weight) in weights.iter().enumerate() {
+            if remaining < weight {
+                return (i, remaining / weight);
+            }
+            remaining -= weight;
+        }
+
+        // Rounding errors may leave us just past the end of the last stripe
+        (weights.len() - 1, 1f32)
+    }
+}
+
+
+impl PositionalRecolorizer for Flag {
+    fn get_color(&mut self, (x, y): (usize, usize)) -> Color {
+        let d = (x as f32) * self.hf + (y as f32) * self.vf;
+
+        let (real_index, frac) = self.stripe_at(d);
+
+        // Hard stripes never blend, which also avoids dividing by zero below
+        if self.deadzone >= 1f32 {
+            return self.stripes[real_index];
+        }
 
         let frac = ((frac - self.deadzone) / (1f32 - self.deadzone)).clamp(0f32, 1f32);
 
-        let real_index = base_index % self.stripes.len();
         let next_index = if real_index + 1 == self.stripes.len() {
             0
         } else {
@@ -219,14 +1092,281 @@ impl PositionalRecolorizer for Flag {
         let col_a = self.stripes[real_index];
         let col_b = self.stripes[next_index];
 
-        col_a.rgb_interpolate(col_b, frac)
+        self.blend_space.interpolate(col_a, col_b, frac)
     }
 }
 
 
-/// Positional colorizer that uses a reference image =
+/// Positional colorizer which fades from one color at the top of the output to another at the
+/// bottom. If the height of the output isn't known, it instead fades back and forth between the
+/// colors at a fixed frequency.
+pub struct VerticalGradient {
+    pub from: Color,
+    pub to: Color,
+    pub height: Option<usize>,
+    /// Frequency used when the height is unknown, in fades/row
+    pub fallback_frequency: f32,
+    pub blend_space: BlendSpace,
+}
+
+
+impl PositionalRecolorizer for VerticalGradient {
+    fn get_color(&mut self, (_, y): (usize, usize)) -> Color {
+        let alpha = match self.height {
+            Some(height) if height > 1 => (y as f32 / (height - 1) as f32).min(1f32),
+            Some(_) => 0f32,
+            None => {
+                // Triangle wave, so that the colors fade back and forth
+                let phase = (y as f32 * self.fallback_frequency / 2f32).fract();
+                1f32 - (2f32 * phase - 1f32).abs()
+            },
+        };
+
+        self.blend_space.interpolate(self.from, self.to, alpha)
+    }
+}
+
+
+/// Which way a [Gradient] fades across the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GradientDirection {
+    /// From the left to the right
+    #[default]
+    Horizontal,
+    /// From the top to the bottom
+    Vertical,
+    /// From the top left to the bottom right
+    Diagonal,
+}
+
+
+impl FromStr for GradientDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("horizontal") {
+            Ok(Self::Horizontal)
+        } else if s.eq_ignore_ascii_case("vertical") {
+            Ok(Self::Vertical)
+        } else if s.eq_ignore_ascii_case("diagonal") {
+            Ok(Self::Diagonal)
+        } else {
+            Err(anyhow!("Invalid gradient direction {s}! - Expected horizontal, vertical or diagonal"))
+        }
+    }
+}
+
+
+/// Positional colorizer which fades once from one color to another across the output. Cells past
+/// the end of the gradient's span keep the end color.
+pub struct Gradient {
+    pub start: Color,
+    pub end: Color,
+    pub direction: GradientDirection,
+    /// Number of columns the gradient spans, for horizontal and diagonal gradients
+    pub width: usize,
+    /// Number of rows the gradient spans, for vertical and diagonal gradients
+    pub height: usize,
+    pub blend_space: BlendSpace,
+}
+
+
+impl Gradient {
+    /// Width used when the output doesn't wrap, so its width isn't known
+    pub const DEFAULT_WIDTH: usize = 80;
+    /// Height used when the height of the output isn't known
+    pub const DEFAULT_HEIGHT: usize = 24;
+}
+
+
+impl PositionalRecolorizer for Gradient {
+    fn get_color(&mut self, (x, y): (usize, usize)) -> Color {
+        // Fraction of the way along a span, where the first and last cells get the exact colors
+        let along = |position: usize, span: usize| if span > 1 {
+            (position as f32 / (span - 1) as f32).min(1f32)
+        } else {
+            0f32
+        };
+
+        let alpha = match self.direction {
+            GradientDirection::Horizontal => along(x, self.width),
+            GradientDirection::Vertical => along(y, self.height),
+            GradientDirection::Diagonal => (along(x, self.width) + along(y, self.height)) / 2f32,
+        };
+
+        self.blend_space.interpolate(self.start, self.end, alpha)
+    }
+}
+
+
+/// Positional colorizer which fades from an inner color at a center point out to an outer color,
+/// in circles. Cells further than the radius from the center keep the outer color.
+pub struct RadialGradient {
+    /// Column and row of the center, which needn't be a whole cell
+    pub center: (f32, f32),
+    pub inner: Color,
+    pub outer: Color,
+    /// Radius in columns
+    pub radius: f32,
+    pub blend_space: BlendSpace,
+}
+
+
+impl RadialGradient {
+    /// Width of a cell relative to its height. Rows are scaled by this when measuring distances,
+    /// so that the circles look round rather than tall
+    pub const CELL_ASPECT: f32 = 0.5;
+}
+
+
+impl PositionalRecolorizer for RadialGradient {
+    fn get_color(&mut self, (x, y): (usize, usize)) -> Color {
+        let dx = x as f32 - self.center.0;
+        let dy = (y as f32 - self.center.1) / Self::CELL_ASPECT;
+        let distance = dx.hypot(dy);
+
+        let alpha = if self.radius > 0f32 {
+            (distance / self.radius).min(1f32)
+        } else {
+            1f32
+        };
+
+        self.blend_space.interpolate(self.inner, self.outer, alpha)
+    }
+}
+
+
+/// Positional colorizer which cycles smoothly through every hue in diagonal bands, in the style of
+/// lolcat. Hues are measured in radians, so a change of 2π is one full cycle.
+pub struct Rainbow {
+    /// Change in hue per row
+    pub freq: f32,
+    /// Number of columns for the hue to change by one radian
+    pub spread: f32,
+    /// Hue of the top left cell
+    pub phase: f32,
+}
+
+
+impl Rainbow {
+    pub const DEFAULT_FREQUENCY: f32 = 0.3;
+    pub const DEFAULT_SPREAD: f32 = 8.0;
+}
+
+
+impl PositionalRecolorizer for Rainbow {
+    fn get_color(&mut self, (x, y): (usize, usize)) -> Color {
+        let hue = x as f32 / self.spread + y as f32 * self.freq + self.phase;
+
+        // Full saturation and value in hsv is full saturation and half lightness in hsl
+        Color::from_hsl(hue.to_degrees(), 1f32, 0.5)
+    }
+}
+
+
+/// Diagnostic colorizer which alternates between two colors in a checkerboard, showing the cell
+/// grid that the colorizer believes exists. Misaligned wide characters or tabs are easy to spot.
+pub struct Grid {
+    pub even: Color,
+    pub odd: Color,
+}
+
+
+impl PositionalRecolorizer for Grid {
+    fn get_color(&mut self, (x, y): (usize, usize)) -> Color {
+        if (x + y) % 2 == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+
+/// Broad categories of characters, used by [Category]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharCategory {
+    Letter,
+    Digit,
+    Punctuation,
+    Whitespace,
+    Other,
+}
+
+
+impl CharCategory {
+    pub fn of(c: char) -> Self {
+        if c.is_numeric() {
+            Self::Digit
+        } else if c.is_alphabetic() {
+            Self::Letter
+        } else if c.is_whitespace() {
+            Self::Whitespace
+        } else if c.is_ascii_punctuation() {
+            Self::Punctuation
+        } else {
+            Self::Other
+        }
+    }
+}
+
+
+/// Colorizer that chooses colors based on the category of each character, rather than its
+/// position. Whitespace keeps whichever color was last used, since it can't be seen anyway.
+pub struct Category {
+    pub letter: Color,
+    pub digit: Color,
+    pub punctuation: Color,
+    pub other: Color,
+    last: Color,
+}
+
+
+impl Category {
+    pub fn new(letter: Color, digit: Color, punctuation: Color, other: Color) -> Self {
+        Self {
+            letter,
+            digit,
+            punctuation,
+            other,
+            last: other,
+        }
+    }
+}
+
+
+impl Recolorizer for Category {
+    fn color_for(&mut self, _: (usize, usize), grapheme: Option<&str>) -> Color {
+        // Graphemes are categorised by their first (base) character
+        let Some(c) = grapheme.and_then(|g| g.chars().next())
+            else {
+                return self.last;
+            };
+
+        self.last = match CharCategory::of(c) {
+            CharCategory::Letter => self.letter,
+            CharCategory::Digit => self.digit,
+            CharCategory::Punctuation => self.punctuation,
+            CharCategory::Whitespace => self.last,
+            CharCategory::Other => self.other,
+        };
+
+        self.last
+    }
+}
+
+
+/// Positional colorizer that uses a reference image
 pub struct Image<T> {
     img: T,
+    luma_modulation: f32,
+    /// Color shown through transparent pixels
+    background: Color,
+    /// Offset in cells added to each position before it is scaled to a pixel, so that cells (or
+    /// blocks of cells sharing a color) can be sampled from somewhere other than their top left
+    sample_offset: f32,
+    /// Number of pixels across and down for each cell
+    scale: (f32, f32),
 }
 
 
@@ -234,20 +1374,642 @@ impl<T> Image<T> {
     pub fn new(img: T) -> Self {
         Self {
             img,
+            luma_modulation: 0f32,
+            background: Color::BLACK,
+            sample_offset: 0f32,
+            scale: (1f32, 1f32),
         }
     }
+
+    /// Sample each cell offset by the given number of cells, such as 0.5 for the center of each
+    /// cell rather than its top left
+    pub fn with_sample_offset(mut self, offset: f32) -> Self {
+        self.sample_offset = offset;
+        self
+    }
+
+    /// Sample an image which has the given number of pixels across and down for each cell, rather
+    /// than one
+    pub fn with_scale(mut self, x_scale: f32, y_scale: f32) -> Self {
+        self.scale = (x_scale, y_scale);
+        self
+    }
+
+    /// Set the color shown through transparent pixels, which is black by default
+    pub fn with_background(mut self, background: Color) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Vary the lightness of each sampled color along a diagonal, so that flat regions of the image
+    /// still show some variation. Strength ranges from 0 (no effect) to 1.
+    pub fn with_luma_modulation(mut self, strength: f32) -> Self {
+        self.luma_modulation = strength;
+        self
+    }
 }
 
 
-impl<T> PositionalRecolorizer for Image<T>
-    where T: GenericImageView,
-          <<T as GenericImageView>::Pixel as Pixel>::Subpixel: Into<u8> {
-    fn get_color(&mut self, (x, y): (usize, usize)) -> Color {
+impl<T> Image<T> where T: GenericImageView {
+    /// The pixel sampled for a cell, with the image tiled to cover every cell
+    pub fn pixel_for(&self, (x, y): (usize, usize)) -> (u32, u32) {
+        let (x_scale, y_scale) = self.scale;
+        let x = ((x as f32 + self.sample_offset) * x_scale).floor() as u32;
+        let y = ((y as f32 + self.sample_offset) * y_scale).floor() as u32;
+        (x % self.img.width(), y % self.img.height())
+    }
+
+    /// Sample a pixel, normalised to 8 bits per channel, along with its alpha. The image is tiled,
+    /// so positions past its edges wrap around
+    pub fn sample(&self, (x, y): (usize, usize)) -> (Color, u8) {
         let pixel = self.img.get_pixel(
             x as u32 % self.img.width(),
             y as u32 % self.img.height());
 
-        let Rgb([r, g, b]) = pixel.to_rgb();
-        Color::from_rgb(r.into(), g.into(), b.into())
+        let Rgba([r, g, b, a]) = pixel.to_rgba();
+        (Color::from_rgb(to_8bit(r), to_8bit(g), to_8bit(b)), to_8bit(a))
+    }
+}
+
+
+impl<T> PositionalRecolorizer for Image<T> where T: GenericImageView {
+    fn get_color(&mut self, (x, y): (usize, usize)) -> Color {
+        let (px, py) = self.pixel_for((x, y));
+        let (color, alpha) = self.sample((px as usize, py as usize));
+        let color = color.blend_over(self.background, alpha as f32 / 255f32);
+
+        if self.luma_modulation == 0f32 {
+            return color;
+        }
+
+        // Triangle wave between 0 and 1, repeating every 20 cells along the diagonal
+        let phase = ((x + y) as f32 * 0.05).fract();
+        let wave = 1f32 - (2f32 * phase - 1f32).abs();
+
+        color.lighten(self.luma_modulation * (wave - 0.5))
+    }
+}
+
+
+/// Scale a channel of any bit depth (including floating point) to 8 bits
+fn to_8bit<S: Primitive>(value: S) -> u8 {
+    let max = S::DEFAULT_MAX_VALUE.to_f32().unwrap_or(1f32);
+    let value = value.to_f32().unwrap_or(0f32);
+    (value / max * 255f32).round().clamp(0f32, 255f32) as u8
+}
+
+
+/// Displays an image directly, rather than recoloring text. Each cell shows two vertically stacked
+/// pixels, using the upper half block character with the foreground as the top pixel and the
+/// background as the bottom pixel.
+pub struct HalfBlockImage<T> {
+    img: T,
+}
+
+
+impl<T> HalfBlockImage<T> {
+    pub fn new(img: T) -> Self {
+        Self {
+            img,
+        }
+    }
+}
+
+
+impl<T> HalfBlockImage<T> where T: GenericImageView {
+    fn pixel_color(&self, x: u32, y: u32) -> Color {
+        let Rgba([r, g, b, _]) = self.img.get_pixel(x, y).to_rgba();
+        Color::from_rgb(to_8bit(r), to_8bit(g), to_8bit(b))
+    }
+
+    pub fn render<O>(&self, mut output: O, config: &ColorizerConfig) -> io::Result<()>
+        where O: Write {
+        for y in (0..self.img.height()).step_by(2) {
+            for x in 0..self.img.width() {
+                let top = self.pixel_color(x, y);
+                config.color_mode.write_fg(top, &mut output)?;
+
+                // An odd number of rows leaves the bottom half of the last row empty
+                if y + 1 < self.img.height() {
+                    let bottom = self.pixel_color(x, y + 1);
+                    config.color_mode.write_bg(bottom, &mut output)?;
+                }
+
+                write!(output, "\u{2580}")?;
+            }
+
+            write!(output, "{RESET_CODE}{}", config.newline_style.terminator())?;
+            if config.flush_on_newline {
+                output.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records the position each grapheme was drawn at
+    #[derive(Default)]
+    struct Positions(Vec<(String, (usize, usize))>);
+
+
+    impl Recolorizer for Positions {
+        fn color_for(&mut self, position: (usize, usize), grapheme: Option<&str>) -> Color {
+            if let Some(grapheme) = grapheme {
+                self.0.push((grapheme.to_string(), position));
+            }
+            Color::BLACK
+        }
+    }
+
+
+    fn positions(input: &str, config: &ColorizerConfig) -> Vec<(String, (usize, usize))> {
+        let mut recorder = Positions::default();
+        recorder.copy_colorized(input.as_bytes(), io::sink(), config).unwrap();
+        recorder.0
+    }
+
+    fn position_of(input: &str, config: &ColorizerConfig, grapheme: &str) -> Vec<(usize, usize)> {
+        positions(input, config).into_iter()
+            .filter(|(g, _)| g == grapheme)
+            .map(|(_, position)| position)
+            .collect()
+    }
+
+    #[test]
+    fn restoring_the_cursor_keeps_the_saved_position() {
+        let config = ColorizerConfig::default();
+
+        // Each restore returns to the same saved position
+        assert_eq!(position_of("\u{1B}7ab\u{1B}8cd\u{1B}8ef", &config, "e"), [(0, 0)]);
+        assert_eq!(position_of("\u{1B}[sab\u{1B}[ucd\u{1B}[uef", &config, "e"), [(0, 0)]);
+
+        // A later save replaces the earlier one
+        assert_eq!(position_of("\u{1B}7ab\u{1B}7cd\u{1B}8ef", &config, "e"), [(2, 0)]);
+
+        // Restoring without a save leaves the cursor alone
+        assert_eq!(position_of("ab\u{1B}8cd", &config, "c"), [(2, 0)]);
+    }
+
+    #[test]
+    fn erasing_only_resets_the_column_when_asked() {
+        let config = ColorizerConfig::default();
+        assert_eq!(position_of("ab\u{1B}[2Kc", &config, "c"), [(2, 0)]);
+
+        let config = ColorizerConfig {
+            erase_resets_column: true,
+            ..Default::default()
+        };
+        assert_eq!(position_of("ab\u{1B}[2Kc", &config, "c"), [(0, 0)]);
+        assert_eq!(position_of("ab\u{1B}[2Jc", &config, "c"), [(0, 0)]);
+        assert_eq!(position_of("a\nab\u{1B}[3Jc", &config, "c"), [(0, 1)]);
+
+        // Partial erases leave the cursor where it is
+        assert_eq!(position_of("ab\u{1B}[1Kc", &config, "c"), [(2, 0)]);
+        assert_eq!(position_of("ab\u{1B}[Jc", &config, "c"), [(2, 0)]);
+    }
+
+    #[test]
+    fn image_shows_background_through_transparency() {
+        let background = Color::from_rgb(0x20, 0x40, 0x60);
+        let img = image::RgbaImage::from_fn(2, 1, |x, _| {
+            if x == 0 { Rgba([0xFF, 0, 0, 0]) } else { Rgba([0xFF, 0, 0, 0xFF]) }
+        });
+
+        let mut image = Image::new(img).with_background(background);
+        assert_eq!(image.get_color((0, 0)), background);
+        assert_eq!(image.get_color((1, 0)), Color::from_rgb(0xFF, 0, 0));
+    }
+
+    #[test]
+    fn noop_copies_the_selected_lines() {
+        let copy_lines = |from_line, to_line| {
+            let config = ColorizerConfig {
+                from_line,
+                to_line,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            Noop.copy_colorized("a\nb\nc\nd".as_bytes(), &mut output, &config).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+
+        assert_eq!(copy_lines(0, None), "a\nb\nc\nd");
+        assert_eq!(copy_lines(1, Some(2)), "b\nc\n");
+        assert_eq!(copy_lines(2, None), "c\nd");
+        assert_eq!(copy_lines(0, Some(0)), "a\n");
+        assert_eq!(copy_lines(5, None), "");
+    }
+
+    #[test]
+    fn tab_replacement_fills_by_display_width() {
+        let config = ColorizerConfig {
+            tab_size: 4,
+            tab_replacement: Some("→".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(position_of("a\tb", &config, "b"), [(4, 0)]);
+
+        // Wide graphemes count for two cells, and are cut off if they don't fit
+        let config = ColorizerConfig {
+            tab_replacement: Some("全全".to_string()),
+            ..config
+        };
+        assert_eq!(position_of("a\tb", &config, "全"), [(1, 0)]);
+        assert_eq!(position_of("a\tb", &config, "b"), [(4, 0)]);
+        assert_eq!(position_of("\tb", &config, "全"), [(0, 0), (2, 0)]);
+        assert_eq!(position_of("\tb", &config, "b"), [(4, 0)]);
+    }
+
+    #[test]
+    fn tab_replacement_at_the_last_column_is_kept() {
+        let config = ColorizerConfig {
+            tab_size: 4,
+            wraps_after: Some(4),
+            tab_replacement: Some("→".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(position_of("abc\tde", &config, "→"), [(3, 0)]);
+        assert_eq!(position_of("abc\tde", &config, "d"), [(0, 1)]);
+    }
+
+    #[test]
+    fn image_samples_cells_at_the_chosen_point() {
+        // Four pixels for each cell, so the center of a cell is two pixels in
+        let img = image::RgbImage::new(16, 16);
+        let top_left = Image::new(img.clone()).with_scale(4f32, 4f32);
+        let center = Image::new(img).with_scale(4f32, 4f32).with_sample_offset(0.5);
+        assert_eq!(top_left.pixel_for((0, 0)), (0, 0));
+        assert_eq!(center.pixel_for((0, 0)), (2, 2));
+        assert_eq!(center.pixel_for((1, 3)), (6, 14));
+
+        // The image is tiled
+        assert_eq!(center.pixel_for((4, 5)), (2, 6));
+
+        // With one pixel per cell, the center of a cell is still that cell's pixel
+        let img = image::RgbImage::new(4, 4);
+        let center = Image::new(img).with_sample_offset(0.5);
+        assert_eq!(center.pixel_for((3, 1)), (3, 1));
+    }
+
+    #[test]
+    fn charset_escapes_leave_the_cursor_alone() {
+        let config = ColorizerConfig::default();
+        assert_eq!(position_of("ab\u{1B}(Bc", &config, "c"), [(2, 0)]);
+        assert_eq!(position_of("ab\u{1B}7\u{1B}(0c", &config, "c"), [(2, 0)]);
+    }
+
+    #[test]
+    fn radial_gradient_blends_in_its_blend_space() {
+        let mut radial = RadialGradient {
+            center: (0f32, 0f32),
+            inner: Color::from_rgb(0xFF, 0, 0),
+            outer: Color::from_rgb(0, 0xFF, 0),
+            radius: 4f32,
+            blend_space: BlendSpace::Oklab,
+        };
+        let (inner, outer) = (radial.inner, radial.outer);
+        assert_eq!(radial.get_color((2, 0)), inner.oklab_interpolate(outer, 0.5));
+
+        radial.blend_space = BlendSpace::Srgb;
+        assert_eq!(radial.get_color((2, 0)), inner.rgb_interpolate(outer, 0.5));
+    }
+
+    #[test]
+    fn vertical_gradient_blends_in_its_blend_space() {
+        let mut gradient = VerticalGradient {
+            from: Color::from_rgb(0xFF, 0, 0),
+            to: Color::from_rgb(0, 0xFF, 0),
+            height: Some(3),
+            fallback_frequency: 0.05,
+            blend_space: BlendSpace::Oklab,
+        };
+        let (from, to) = (gradient.from, gradient.to);
+        assert_eq!(gradient.get_color((0, 1)), from.oklab_interpolate(to, 0.5));
+
+        gradient.blend_space = BlendSpace::Srgb;
+        assert_eq!(gradient.get_color((0, 1)), from.rgb_interpolate(to, 0.5));
+    }
+
+    #[test]
+    fn initial_color_stays_out_of_the_running_palette() {
+        let config = ColorizerConfig {
+            running_palette: Some(Rc::new(RefCell::new(RunningPalette::new(2)))),
+            ..Default::default()
+        };
+        let colors = |config: &ColorizerConfig| config.running_palette.as_ref().unwrap().borrow().colors.clone();
+
+        let mut flag = Flag::new(vec![Color::from_rgb(0xFF, 0, 0), Color::from_rgb(0, 0, 0xFF)]);
+        flag.copy_colorized("".as_bytes(), io::sink(), &config).unwrap();
+        assert!(colors(&config).is_empty());
+
+        flag.copy_colorized("a".as_bytes(), io::sink(), &config).unwrap();
+        assert_eq!(colors(&config).len(), 1);
+    }
+
+    #[test]
+    fn running_palette_snaps_once_full() {
+        let mut palette = RunningPalette::new(2);
+        assert_eq!(palette.nearest(Color::WHITE), Color::WHITE);
+        assert_eq!(palette.snap(Color::WHITE), Color::WHITE);
+        assert_eq!(palette.snap(Color::BLACK), Color::BLACK);
+        assert_eq!(palette.nearest(Color::gray(0xF0)), Color::WHITE);
+        assert_eq!(palette.snap(Color::gray(0x10)), Color::BLACK);
+        assert_eq!(palette.colors.len(), 2);
+    }
+
+    #[test]
+    fn weighted_stripes_take_their_share_of_the_flag() {
+        let flag = Flag::new(vec![Color::WHITE, Color::BLACK]).weights(Some(vec![3f32, 1f32]));
+
+        // A repetition is still two stripes long, with the first taking three quarters of it
+        assert_eq!(flag.stripe_at(0f32), (0, 0f32));
+        assert_eq!(flag.stripe_at(0.75), (0, 0.5));
+        assert_eq!(flag.stripe_at(1.5), (1, 0f32));
+        assert_eq!(flag.stripe_at(2f32), (0, 0f32));
+
+        let flag = flag.weights(None);
+        assert_eq!(flag.stripe_at(1.5), (1, 0.5));
+    }
+
+    /// The raw output for some input
+    fn output(input: &str, config: &ColorizerConfig) -> String {
+        let mut output = Vec::new();
+        Positions::default().copy_colorized(input.as_bytes(), &mut output, config).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn carriage_returns_can_be_stripped() {
+        let with_mode = |carriage_return| ColorizerConfig {
+            carriage_return,
+            ..Default::default()
+        };
+
+        let config = with_mode(CarriageReturnMode::Forward);
+        assert_eq!(position_of("ab\rc", &config, "c"), [(0, 0)]);
+        assert_eq!(output("ab\rc\r\n", &config).matches('\r').count(), 2);
+
+        let config = with_mode(CarriageReturnMode::StripAndReset);
+        assert_eq!(position_of("ab\rc", &config, "c"), [(0, 0)]);
+        assert!(!output("ab\rc\r\n", &config).contains('\r'));
+
+        let config = with_mode(CarriageReturnMode::Strip);
+        assert_eq!(position_of("ab\rc", &config, "c"), [(2, 0)]);
+        assert!(!output("ab\rc\r\n", &config).contains('\r'));
+        assert_eq!(position_of("a\r\nb", &config, "b"), [(0, 1)]);
+    }
+
+    #[test]
+    fn hyperlinks_take_up_no_cells() {
+        let config = ColorizerConfig::default();
+        let link = "a\u{1B}]8;;https://example.com\u{1B}\\bc\u{1B}]8;;\u{1B}\\d";
+        assert_eq!(position_of(link, &config, "d"), [(3, 0)]);
+
+        // The link is forwarded whole
+        assert!(output(link, &config).contains("\u{1B}]8;;https://example.com\u{1B}\\"));
+    }
+
+    #[test]
+    fn sample_rate_groups_cells_into_blocks() {
+        let config = ColorizerConfig {
+            sample_rate: 3,
+            ..Default::default()
+        };
+        assert_eq!(config.color_position((0, 0)), (0, 0));
+        assert_eq!(config.color_position((2, 2)), (0, 0));
+        assert_eq!(config.color_position((3, 5)), (3, 3));
+        assert_eq!(config.color_position((7, 1)), (6, 0));
+
+        // Every cell in a block gets the same color
+        let mut grid = Grid {
+            even: Color::WHITE,
+            odd: Color::BLACK,
+        };
+        let color = |grid: &mut Grid, position| grid.get_color(config.color_position(position));
+        assert_eq!(color(&mut grid, (0, 0)), color(&mut grid, (2, 1)));
+        assert_ne!(color(&mut grid, (2, 0)), color(&mut grid, (3, 0)));
+    }
+
+    #[test]
+    fn overstrikes_become_styles() {
+        let config = ColorizerConfig {
+            render_overstrike: true,
+            ..Default::default()
+        };
+        let bold = output("a\u{8}ab", &config);
+        assert!(bold.contains("\u{1B}[1ma"), "{bold:?}");
+        assert!(!bold.contains('\u{8}'));
+        assert_eq!(position_of("a\u{8}ab", &config, "b"), [(1, 0)]);
+
+        let underline = output("_\u{8}xy", &config);
+        assert!(underline.contains("\u{1B}[4mx"), "{underline:?}");
+        assert!(!underline.contains('_'));
+
+        // Anything else is left as it is
+        let plain = output("a\u{8}b", &config);
+        assert!(plain.contains('\u{8}'));
+        assert!(!plain.contains("\u{1B}[1m") && !plain.contains("\u{1B}[4m"));
+    }
+
+    #[test]
+    fn origin_offsets_every_position() {
+        let config = ColorizerConfig {
+            origin: (5, 2),
+            ..Default::default()
+        };
+        assert_eq!(position_of("ab\ncd", &config, "a"), [(5, 2)]);
+        assert_eq!(position_of("ab\ncd", &config, "b"), [(6, 2)]);
+
+        // New lines start at the first column of the terminal, below the origin
+        assert_eq!(position_of("ab\ncd", &config, "c"), [(0, 3)]);
+    }
+
+    #[test]
+    fn boxed_colorizers_match_their_concrete_type() {
+        let config = ColorizerConfig::default();
+        let input = "one\ntwo \x1b[1mthree\x1b[0m\n";
+        let flag = || Flag::new(vec![Color::WHITE, Color::BLACK]).hf(0.3).vf(0.2);
+
+        let mut expected = Vec::new();
+        flag().copy_colorized(input.as_bytes(), &mut expected, &config).unwrap();
+
+        let mut boxed: Box<dyn StreamColorizer> = Box::new(flag());
+        let mut output = Vec::new();
+        boxed.copy_colorized(input.as_bytes(), &mut output, &config).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn newlines_use_the_chosen_style() {
+        let with_style = |newline_style| ColorizerConfig {
+            newline_style,
+            emit_ansi: false,
+            ..Default::default()
+        };
+        assert_eq!(output("a\nb\n", &with_style(NewlineStyle::Lf)), "a\nb\n");
+        assert_eq!(output("a\nb\n", &with_style(NewlineStyle::Crlf)), "a\r\nb\r\n");
+        assert_eq!(output("a\nb", &with_style(NewlineStyle::Cr)), "a\rb");
+
+        // Windows line endings are converted too, while the default keeps them as they are
+        assert_eq!(output("a\r\nb\r\n", &with_style(NewlineStyle::Lf)), "a\r\nb\r\n");
+        assert_eq!(output("a\r\nb\r\n", &with_style(NewlineStyle::Crlf)), "a\r\nb\r\n");
+        assert_eq!(output("a\r\nb\r\n", &with_style(NewlineStyle::Cr)), "a\rb\r");
+
+        // Unless their carriage returns are stripped first
+        let config = ColorizerConfig {
+            carriage_return: CarriageReturnMode::Strip,
+            ..with_style(NewlineStyle::Lf)
+        };
+        assert_eq!(output("a\r\nb\r\n", &config), "a\nb\n");
+
+        // Colors don't get in the way of the terminator
+        let colored = ColorizerConfig {
+            newline_style: NewlineStyle::Crlf,
+            ..Default::default()
+        };
+        assert_eq!(output("a\n", &colored), "\x1b[38;2;0;0;0ma\r\n");
+    }
+
+    #[test]
+    fn wide_graphemes_wrap_whole() {
+        let with_mode = |wide_wrap| ColorizerConfig {
+            wraps_after: Some(3),
+            wide_wrap,
+            emit_ansi: false,
+            ..Default::default()
+        };
+
+        // Only one cell is left on the first line, so the wide grapheme starts the next
+        for mode in [WideWrapMode::Pad, WideWrapMode::Push] {
+            assert_eq!(position_of("ab\u{65E5}c", &with_mode(mode), "\u{65E5}"), [(0, 1)]);
+            assert_eq!(position_of("ab\u{65E5}c", &with_mode(mode), "c"), [(2, 1)]);
+        }
+
+        assert_eq!(output("ab\u{65E5}c", &with_mode(WideWrapMode::Pad)), "ab\u{65E5}c");
+        assert_eq!(output("ab\u{65E5}c", &with_mode(WideWrapMode::Push)), "ab \u{65E5}c");
+
+        // A wide grapheme which exactly fills the line needs no padding
+        assert_eq!(output("a\u{65E5}c", &with_mode(WideWrapMode::Push)), "a\u{65E5}c");
+    }
+
+    #[test]
+    fn inherited_colors_skip_reapplying_after_resets() {
+        let input = "a\x1b[0m\x1b[0m\x1b[0mb\x1b[0m\n";
+        let color_codes = |inherit_color_across_ansi| {
+            let config = ColorizerConfig {
+                inherit_color_across_ansi,
+                ..Default::default()
+            };
+            let output = output(input, &config);
+            assert_eq!(output.matches("\x1b[0m").count(), 4);
+            output.matches("\x1b[38;2;").count()
+        };
+
+        // Our color is written after every reset, or only once more for the next grapheme
+        assert_eq!(color_codes(false), 5);
+        assert_eq!(color_codes(true), 2);
+    }
+
+    #[test]
+    fn stats_count_each_kind_of_element() {
+        let stats = Rc::new(RefCell::new(Stats::default()));
+        let config = ColorizerConfig {
+            stats: Some(stats.clone()),
+            ..Default::default()
+        };
+        let input = b"ab\n\xFFc\x1b[31md\x1b[1m\n";
+        let mut flag = Flag::new(vec![Color::WHITE, Color::BLACK]).hf(1f32).vf(0f32).deadzone(1f32);
+        flag.copy_colorized(&input[..], io::sink(), &config).unwrap();
+
+        let stats = stats.borrow();
+        assert_eq!((stats.lines, stats.graphemes, stats.non_utf8_bytes), (2, 4, 1));
+        // The input's own color is discarded, while its bold is kept
+        assert_eq!((stats.ansi_forwarded, stats.ansi_discarded), (1, 1));
+        assert_eq!((stats.distinct_colors(), stats.distinct_input_colors()), (2, 1));
+    }
+
+    #[test]
+    fn wrapped_lines_are_indented() {
+        let config = ColorizerConfig {
+            wraps_after: Some(4),
+            wrap_indent: 2,
+            emit_ansi: false,
+            ..Default::default()
+        };
+        assert_eq!(output("abcdefgh\n", &config), "abcd  ef  gh\n");
+        assert_eq!(position_of("abcdefgh\n", &config, "e"), [(2, 1)]);
+        // The indent is colored like any other cells
+        assert_eq!(position_of("abcdef", &config, " "), [(0, 1), (1, 1)]);
+
+        // A line which exactly fills the terminal isn't followed by an indent
+        assert_eq!(output("abcd\nef", &config), "abcd\nef");
+
+        // Some of each line is always left for text
+        let config = ColorizerConfig {
+            wrap_indent: 10,
+            ..config
+        };
+        assert_eq!(output("abcdef", &config), "abcd   e   f");
+    }
+
+    #[test]
+    fn transposing_swaps_stripe_direction() {
+        let input = "abcdef\nghijkl\nmnopqr\nstuvwx\n";
+        let colorize = |mut flag: Flag, transpose| {
+            let config = ColorizerConfig {
+                transpose,
+                ..Default::default()
+            };
+            let mut output = Vec::new();
+            flag.copy_colorized(input.as_bytes(), &mut output, &config).unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        let stripes = vec![Color::WHITE, Color::from_rgb(0xFF, 0, 0), Color::BLACK];
+
+        let transposed = colorize(Flag::new(stripes.clone()).hf(0f32).vf(0.5), true);
+        assert_eq!(transposed, colorize(Flag::new(stripes.clone()).hf(0.5).vf(0f32), false));
+        assert_ne!(transposed, colorize(Flag::new(stripes).hf(0f32).vf(0.5), false));
+    }
+
+    #[test]
+    fn paletted_renders_keep_the_top_half() {
+        let config = ColorizerConfig {
+            color_mode: ColorMode::Palette8,
+            ..Default::default()
+        };
+        let img = image::RgbImage::from_fn(1, 3, |_, y| match y {
+            0 => image::Rgb([0xE0, 0x10, 0x10]),
+            1 => image::Rgb([0x10, 0x10, 0xE0]),
+            _ => image::Rgb([0x10, 0xE0, 0x10]),
+        });
+        let mut output = Vec::new();
+        HalfBlockImage::new(img).render(&mut output, &config).unwrap();
+
+        // The background of the bottom half doesn't reset the foreground of the top half
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "\x1b[31m\x1b[44m\u{2580}\x1b[0m\n\x1b[32m\u{2580}\x1b[0m\n");
+    }
+
+    #[test]
+    fn paletted_colors_keep_the_input_attributes() {
+        let config = ColorizerConfig {
+            color_mode: ColorMode::Palette8,
+            ..Default::default()
+        };
+        let mut flag = Flag::new(vec![Color::from_rgb(0xE0, 0x10, 0x10), Color::from_rgb(0x10, 0x10, 0xE0)])
+            .hf(0.5)
+            .vf(0f32)
+            .deadzone(1f32);
+        let mut output = Vec::new();
+        flag.copy_colorized(&b"\x1b[1mabcd\x1b[0m\n"[..], &mut output, &config).unwrap();
+
+        // Changing color doesn't reset the bold, so only the input's own reset ends it
+        assert_eq!(String::from_utf8(output).unwrap(), "\x1b[31m\x1b[1mab\x1b[34mcd\x1b[0m\x1b[34m\n");
     }
 }
@@ -0,0 +1,300 @@
+//! Querying the terminal directly for what it supports
+use std::env;
+#[cfg(unix)]
+use std::time::Duration;
+
+use crate::console::Color;
+use crate::sinks::ColorMode;
+
+
+/// How long to wait for the terminal to answer. Local terminals answer almost immediately, but
+/// this leaves some room for ssh connections
+#[cfg(unix)]
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+
+/// Ask the terminal whether it supports 24-bit color. This sets an unusual 24-bit background color,
+/// then asks for the current style back using a DECRQSS query. Terminals which support 24-bit color
+/// report the exact color, while others report an approximation or don't understand the query.
+///
+/// Returns None if the terminal couldn't be asked, or didn't answer within the timeout.
+#[cfg(unix)]
+pub fn probe_truecolor() -> Option<bool> {
+    let response = query(b"\x1b[48:2::1:2:3m\x1bP$qm\x1b\\\x1b[0m")?;
+
+    // A valid request is answered with "1$r", followed by the style
+    if !response.contains("$r") {
+        return None;
+    }
+    Some(response.contains("1$r") && (response.contains(":1:2:3") || response.contains(";1;2;3")))
+}
+
+
+#[cfg(not(unix))]
+pub fn probe_truecolor() -> Option<bool> {
+    None
+}
+
+
+/// Ask the terminal for its background color, using an OSC 11 query. Returns None if the terminal
+/// couldn't be asked, or didn't answer within the timeout.
+#[cfg(unix)]
+pub fn query_background_color() -> Option<Color> {
+    let response = query(b"\x1b]11;?\x1b\\")?;
+    parse_osc_color(&response)
+}
+
+
+#[cfg(not(unix))]
+pub fn query_background_color() -> Option<Color> {
+    None
+}
+
+
+/// Width/height of each cell, worked out from the size of the terminal in pixels. Only some
+/// terminals report their size in pixels.
+#[cfg(unix)]
+pub fn cell_aspect_ratio() -> Option<f64> {
+    [libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO].into_iter()
+        .find_map(|fd| {
+            // SAFETY: winsize is plain data, and is only read if ioctl fills it in
+            let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+            if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } != 0 {
+                return None;
+            }
+            aspect_from_winsize(size.ws_col, size.ws_row, size.ws_xpixel, size.ws_ypixel)
+        })
+}
+
+
+#[cfg(not(unix))]
+pub fn cell_aspect_ratio() -> Option<f64> {
+    None
+}
+
+
+/// Cell aspect ratio given the size of a terminal in cells and pixels, if all are known
+#[cfg(unix)]
+fn aspect_from_winsize(cols: u16, rows: u16, x_pixels: u16, y_pixels: u16) -> Option<f64> {
+    if cols == 0 || rows == 0 || x_pixels == 0 || y_pixels == 0 {
+        return None;
+    }
+
+    let cell_width = x_pixels as f64 / cols as f64;
+    let cell_height = y_pixels as f64 / rows as f64;
+    Some(cell_width / cell_height)
+}
+
+
+/// Guess the background color from COLORFGBG, which some terminals set to "foreground;background"
+/// using indices into the 16 color palette
+pub fn colorfgbg_background() -> Option<Color> {
+    let value = env::var("COLORFGBG").ok()?;
+    let index: u8 = value.rsplit(';').next()?.trim().parse().ok()?;
+    (index < 16).then(|| Color::from_ansi_index(index))
+}
+
+
+/// Guess the color mode from COLORTERM and TERM. COLORTERM is set to "truecolor" or "24bit" by
+/// most terminals with 24-bit color, and TERM names 256 color terminals such as "xterm-256color".
+/// Returns None if neither is set, such as when not run from a terminal at all.
+pub fn color_mode_from_env() -> Option<ColorMode> {
+    let colorterm = env::var("COLORTERM").unwrap_or_default();
+    if colorterm.eq_ignore_ascii_case("truecolor") || colorterm.eq_ignore_ascii_case("24bit") {
+        return Some(ColorMode::Truecolor);
+    }
+
+    let term = env::var("TERM").unwrap_or_default();
+    if term.contains("256color") {
+        Some(ColorMode::Palette256)
+    } else if !term.is_empty() || !colorterm.is_empty() {
+        Some(ColorMode::Palette8)
+    } else {
+        None
+    }
+}
+
+
+/// Parse the color from an answer to an OSC color query, such as
+/// "\x1b]11;rgb:ffff/8080/0000\x1b\\". Each channel has from 1 to 4 hex digits.
+#[cfg(unix)]
+fn parse_osc_color(response: &str) -> Option<Color> {
+    let start = response.find("rgb:")? + "rgb:".len();
+    let end = response[start..].find(['\x1b', '\x07']).map_or(response.len(), |end| start + end);
+
+    let channels = response[start..end].split('/')
+        .map(|channel| {
+            if !(1..=4).contains(&channel.len()) || !channel.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let value = u32::from_str_radix(channel, 16).ok()?;
+            let max = 16u32.pow(channel.len() as u32) - 1;
+            Some((value * 255 / max) as u8)
+        })
+        .collect::<Option<Vec<u8>>>()?;
+
+    let [r, g, b] = channels[..]
+        else {
+            return None;
+        };
+    Some(Color::from_rgb(r, g, b))
+}
+
+
+/// Write a query to the terminal, and read its answer
+#[cfg(unix)]
+fn query(request: &[u8]) -> Option<String> {
+    use std::fs::OpenOptions;
+    use std::io::{stdout, IsTerminal, Write};
+    use std::os::unix::io::AsRawFd;
+
+    // Output going elsewhere may not end up on this terminal at all
+    if !stdout().is_terminal() {
+        return None;
+    }
+
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .ok()?;
+
+    // The response shouldn't be echoed, and must be readable before a newline arrives
+    let _raw = RawMode::enable(tty.as_raw_fd())?;
+
+    tty.write_all(request).ok()?;
+    tty.flush().ok()?;
+
+    let response = read_response(&mut tty, PROBE_TIMEOUT)?;
+    Some(String::from_utf8_lossy(&response).into_owned())
+}
+
+
+/// Read a response from the terminal, up to the string terminator which ends it
+#[cfg(unix)]
+fn read_response(tty: &mut std::fs::File, timeout: Duration) -> Option<Vec<u8>> {
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+    use std::time::Instant;
+
+    let deadline = Instant::now() + timeout;
+    let mut response = Vec::new();
+    let mut buffer = [0; 64];
+
+    while !response.ends_with(b"\x1b\\") && !response.ends_with(b"\x07") {
+        let remaining = deadline.checked_duration_since(Instant::now())?;
+
+        let mut fds = libc::pollfd {
+            fd: tty.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: fds is a single valid pollfd
+        let ready = unsafe { libc::poll(&mut fds, 1, remaining.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            return None;
+        }
+
+        let amount = tty.read(&mut buffer).ok()?;
+        if amount == 0 {
+            return None;
+        }
+        response.extend_from_slice(&buffer[..amount]);
+    }
+
+    Some(response)
+}
+
+
+/// Disables line buffering and echo on a terminal, restoring the original settings when dropped
+#[cfg(unix)]
+struct RawMode {
+    fd: libc::c_int,
+    original: libc::termios,
+}
+
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable(fd: libc::c_int) -> Option<Self> {
+        // SAFETY: termios is plain data, and is fully initialised by tcgetattr before use
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return None;
+        }
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) } != 0 {
+            return None;
+        }
+
+        Some(Self {
+            fd,
+            original,
+        })
+    }
+}
+
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: original was filled in by tcgetattr on the same fd
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn parses_osc_colors_of_any_precision() {
+        let expected = Color::from_rgb(0xFF, 0x80, 0x00);
+        assert_eq!(parse_osc_color("\x1b]11;rgb:ffff/8080/0000\x1b\\"), Some(expected));
+        assert_eq!(parse_osc_color("\x1b]11;rgb:fff/808/000\x07"), Some(expected));
+        assert_eq!(parse_osc_color("\x1b]11;rgb:ff/80/00\x07"), Some(expected));
+        assert_eq!(parse_osc_color("\x1b]11;rgb:f/8/0\x07"), Some(Color::from_rgb(0xFF, 0x88, 0x00)));
+        assert_eq!(parse_osc_color("rgb:F/8/0"), Some(Color::from_rgb(0xFF, 0x88, 0x00)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rejects_malformed_osc_colors() {
+        assert_eq!(parse_osc_color(""), None);
+        assert_eq!(parse_osc_color("\x1b]11;?\x07"), None);
+        assert_eq!(parse_osc_color("\x1b]11;rgb:ffff/8080\x07"), None);
+        assert_eq!(parse_osc_color("\x1b]11;rgb:ffff/8080/0000/ffff\x07"), None);
+        assert_eq!(parse_osc_color("\x1b]11;rgb:ffff//0000\x07"), None);
+        assert_eq!(parse_osc_color("\x1b]11;rgb:gg/00/00\x07"), None);
+        assert_eq!(parse_osc_color("\x1b]11;rgb:fffff/0/0\x07"), None);
+        assert_eq!(parse_osc_color("\x1b]11;rgb:+f/0/0\x07"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn aspect_ratio_needs_every_size() {
+        assert_eq!(aspect_from_winsize(80, 24, 800, 480), Some(0.5));
+        assert_eq!(aspect_from_winsize(80, 24, 0, 480), None);
+        assert_eq!(aspect_from_winsize(80, 24, 800, 0), None);
+        assert_eq!(aspect_from_winsize(0, 24, 800, 480), None);
+        assert_eq!(aspect_from_winsize(80, 0, 800, 480), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn aspect_ratio_is_cell_width_over_height() {
+        // 9x20 pixel cells
+        assert_eq!(aspect_from_winsize(100, 50, 900, 1000), Some(0.45));
+        // Square cells
+        assert_eq!(aspect_from_winsize(120, 40, 1920, 640), Some(1.0));
+        // Cells which don't divide the window evenly are averaged
+        let ratio = aspect_from_winsize(3, 2, 20, 35).unwrap();
+        assert!((ratio - (20.0 / 3.0) / 17.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,93 @@
+//! Reloading user presets while running, when the preset file changes
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::console::Color;
+use crate::presets::{flag_by_name, presets_generation, reload_user_presets, FlagPreset};
+use crate::stream_colors::{Flag, PositionalRecolorizer};
+
+
+/// Watch a preset file, reloading the user presets from a background thread whenever it changes.
+/// Files it includes are not watched. The presets are only reloaded while the returned watcher
+/// is alive.
+pub fn watch_presets(path: &Path) -> Result<RecommendedWatcher> {
+    let path = fs::canonicalize(path)
+        .with_context(|| format!("Couldn't open preset file {}", path.display()))?;
+
+    // Editors often replace the file rather than writing to it, so watch its directory instead
+    let directory = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+
+    let watched = path.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event
+            else {
+                return;
+            };
+
+        let changed = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+            && event.paths.contains(&watched);
+
+        // A half-written file may fail to parse, in which case the old presets are kept until
+        // the next change
+        if changed {
+            if let Err(e) = reload_user_presets(&watched) {
+                eprintln!("Couldn't reload presets: {e:#}");
+            }
+        }
+    })?;
+
+    watcher.watch(&directory, RecursiveMode::NonRecursive)
+        .with_context(|| format!("Couldn't watch preset file {}", path.display()))?;
+    Ok(watcher)
+}
+
+
+/// Flag which is looked up again by name whenever the presets are reloaded. The new flag takes
+/// effect from the next line, so that lines aren't split between two flags.
+pub struct WatchedFlag<F> {
+    name: String,
+    build: F,
+    flag: Flag,
+    generation: usize,
+    row: usize,
+}
+
+
+impl<F> WatchedFlag<F> where F: FnMut(&FlagPreset) -> Flag {
+    /// Create a flag from the named preset, using build to apply any other options
+    pub fn new(name: String, mut build: F) -> Result<Self> {
+        let generation = presets_generation();
+        let preset = flag_by_name(&name)
+            .ok_or_else(|| anyhow!("Invalid preset name {name}! - Use --presets to list all available flag presets"))?;
+
+        Ok(Self {
+            flag: build(&preset),
+            name,
+            build,
+            generation,
+            row: 0,
+        })
+    }
+}
+
+
+impl<F> PositionalRecolorizer for WatchedFlag<F> where F: FnMut(&FlagPreset) -> Flag {
+    fn get_color(&mut self, position: (usize, usize)) -> Color {
+        if position.1 != self.row {
+            self.row = position.1;
+
+            let generation = presets_generation();
+            if generation != self.generation {
+                self.generation = generation;
+                // If the preset was removed, keep using the last version of it
+                if let Some(preset) = flag_by_name(&self.name) {
+                    self.flag = (self.build)(&preset);
+                }
+            }
+        }
+
+        self.flag.get_color(position)
+    }
+}